@@ -3,7 +3,6 @@
 use core::intrinsics::{offset, transmute, volatile_store};
 
 use core::failure;
-use core::fmt;
 
 use platform::io;
 
@@ -116,12 +115,6 @@ pub unsafe fn debug() {
     asm!("movs pc, lr")
 }
 
-// TODO respect destructors
-#[lang="begin_unwind"]
-unsafe extern "C" fn begin_unwind(fmt: &fmt::Arguments, file: &str, line: uint) -> ! {
-    loop { };
-}
-
 /*
 #[lang="fail_"]
 #[fixed_stack_segment]
@@ -1,9 +1,11 @@
 //! Memory management unit.
 
+use core::prelude::*;
 use core;
 
 use kernel::mm::physical;
 use kernel::mm::physical::Phys;
+use kernel::mm::OutOfMemory;
 
 pub type Frame = [u8; ..PAGE_SIZE];
 
@@ -119,8 +121,9 @@ fn enable_paging() {
     }
 }
 
-pub unsafe fn map(_: *mut u8, _: uint, _: Flags) {
+pub unsafe fn map(_: *mut u8, _: uint, _: Flags) -> Result<(), OutOfMemory> {
     // TODO
+    Ok(())
 }
 
 impl Descriptor {
@@ -133,8 +136,9 @@ impl Descriptor {
 impl_ops!(Descriptor, Flags);
 
 impl PageDirectory {
-    pub unsafe fn map(&self, _: *mut u8, _: uint, _: Flags) {
+    pub unsafe fn map(&self, _: *mut u8, _: uint, _: Flags) -> Result<(), OutOfMemory> {
         // TODO
+        Ok(())
     }
 
     pub unsafe fn clone(&mut self) -> Phys<PageDirectory> {
@@ -1,5 +1,9 @@
+use core::fmt;
+use core::intrinsics::volatile_store;
+
 pub mod interrupt;
 pub mod mmu;
+pub mod fpu;
 
 pub fn init() {
     unsafe {
@@ -7,5 +11,118 @@ pub fn init() {
     }
 }
 
-pub fn info() {
+/// Processor identification from the Main ID Register.
+#[derive(Copy)]
+pub struct CpuInfo {
+    pub implementer: u8,
+    pub variant: u8,
+    pub architecture: u8,
+    pub part: u16,
+    pub revision: u8
+}
+
+impl fmt::Show for CpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let implementer = match self.implementer {
+            0x41 => "ARM",
+            0x69 => "Intel",
+            _ => "unknown"
+        };
+        write!(f, "{} part {:03x} variant {} revision {} architecture {}",
+               implementer, self.part, self.variant, self.revision, self.architecture)
+    }
+}
+
+pub fn info() -> CpuInfo {
+    let midr: u32;
+    unsafe {
+        asm!("mrc p15, 0, $0, c0, c0, 0" : "=r"(midr));
+    }
+    CpuInfo {
+        implementer: (midr >> 24) as u8,
+        variant: ((midr >> 20) & 0xF) as u8,
+        architecture: ((midr >> 16) & 0xF) as u8,
+        part: ((midr >> 4) & 0xFFF) as u16,
+        revision: (midr & 0xF) as u8
+    }
+}
+
+pub fn dump_registers() {
+    let (sp, lr, cpsr): (u32, u32, u32);
+    unsafe {
+        asm!("mov $0, sp
+              mov $1, lr
+              mrs $2, cpsr" : "=r"(sp), "=r"(lr), "=r"(cpsr));
+    }
+    println!("sp={:08x} lr={:08x} cpsr={:08x}", sp, lr, cpsr);
+}
+
+/// Runs `f` with IRQs masked, then restores the mask.
+pub fn without_interrupts<T, F: FnOnce() -> T>(f: F) -> T {
+    let cpsr: u32;
+    unsafe {
+        asm!("mrs $0, cpsr
+              orr r1, $0, #0x80
+              msr cpsr_c, r1" : "=r"(cpsr) :: "r1" : "volatile");
+    }
+    let result = f();
+    unsafe {
+        asm!("msr cpsr_c, $0" :: "r"(cpsr) :: "volatile");
+    }
+    result
+}
+
+/// Stops the processor for good.
+pub fn halt() -> ! {
+    unsafe {
+        // mask IRQ and FIQ
+        asm!("mrs r0, cpsr
+              orr r0, r0, #0xC0
+              msr cpsr_c, r0" ::: "r0" : "volatile");
+    }
+    loop {
+        unsafe {
+            // wait for interrupt (ARM926)
+            asm!("mcr p15, 0, $0, c7, c0, 4" :: "r"(0) :: "volatile");
+        }
+    }
+}
+
+/// Resets the board through the Versatile system controller.
+pub fn reboot() -> ! {
+    static SYS_LOCK: *mut u32 = 0x10000020 as *mut u32;
+    static SYS_RESETCTL: *mut u32 = 0x10000040 as *mut u32;
+    unsafe {
+        volatile_store(SYS_LOCK, 0xA05F);
+        volatile_store(SYS_RESETCTL, 0x100);
+    }
+    halt()
+}
+
+/// Exits QEMU started with `-semihosting`. Any nonzero code exits with status 1.
+pub fn exit_qemu(code: u8) -> ! {
+    // angel_SWIreason_ReportException with ADP_Stopped_ApplicationExit
+    // or ADP_Stopped_RunTimeErrorUnknown
+    let reason: u32 = if code == 0 { 0x20026 } else { 0x20023 };
+    unsafe {
+        asm!("mov r0, #0x18
+              mov r1, $0
+              svc 0x123456" :: "r"(reason) : "r0", "r1" : "volatile");
+    }
+    halt()
+}
+
+/// There is a single processor.
+pub fn current() -> usize {
+    0
+}
+
+#[inline]
+pub fn relax() {}
+
+/// Waits for the next interrupt (ARM926).
+pub fn idle() {
+    unsafe {
+        asm!("mcr p15, 0, $0, c7, c0, 4" :: "r"(0) :: "volatile");
+    }
 }
@@ -0,0 +1,38 @@
+//! The monotonic clock, from timer 0 of the SP804 dual timer counting down
+//! freely at 1 MHz. The counter wraps every 71 minutes, which is noticed as
+//! long as the clock is read more often than that.
+
+use core::intrinsics::{volatile_load, volatile_store};
+
+static TIMER0_LOAD: *mut u32 = 0x101E2000 as *mut u32;
+static TIMER0_VALUE: *mut u32 = (0x101E2000 + 0x04) as *mut u32;
+static TIMER0_CONTROL: *mut u32 = (0x101E2000 + 0x08) as *mut u32;
+
+const ENABLE: u32 = 1 << 7;
+const SIZE_32: u32 = 1 << 1;
+
+static mut last: u32 = 0;
+static mut wraps: u64 = 0;
+
+pub fn init() {
+    unsafe {
+        volatile_store(TIMER0_CONTROL, 0);
+        volatile_store(TIMER0_LOAD, !0);
+        // free-running, no interrupt, no prescaler
+        volatile_store(TIMER0_CONTROL, ENABLE | SIZE_32);
+        last = !0;
+    }
+}
+
+/// Nanoseconds since `init`.
+pub fn nanos() -> u64 {
+    unsafe {
+        let value = volatile_load(TIMER0_VALUE as *const u32);
+        if value > last {
+            wraps += 1;
+        }
+        last = value;
+        let us = (wraps << 32) + (!0 - value) as u64;
+        us * 1000
+    }
+}
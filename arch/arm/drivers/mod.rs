@@ -4,6 +4,9 @@ use super::cpu::interrupt;
 use super::io;
 use kernel;
 
+pub mod clock;
+pub mod rtc;
+
 pub static mut keydown: Option<fn(u32)> = None;
 
 pub fn init() {
@@ -12,6 +15,7 @@ pub fn init() {
             t.enable(interrupt::IRQ, keypress);
         });
     }
+    kernel::time::init();
 }
 
 #[no_mangle]
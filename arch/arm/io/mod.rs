@@ -1,10 +1,41 @@
 //! UART read/write
 
 use core::intrinsics::volatile_store;
+use core::fmt;
+use core::prelude::*;
 
 pub static UART0: *mut u32 = 0x101f1000 as *mut u32;
 pub static UART0_IMSC: *mut u32 = (0x101f1000 + 0x038) as *mut u32;
 
+/// A format writer that writes out to the UART.
+struct Stdout;
+
+impl Stdout {
+    fn write_fmt(&mut self, fmt: &fmt::Arguments) {
+        fmt::write(self, fmt);
+    }
+}
+
+impl fmt::FormatWriter for Stdout {
+    fn write(&mut self, bytes: &[u8]) -> fmt::Result {
+        for &c in bytes.iter() {
+            putc(c as u32);
+        }
+        Ok(())
+    }
+}
+
+/// The UART needs no setup.
+pub fn init() {}
+
+pub fn print_args(fmt: &fmt::Arguments) {
+    write!(&mut Stdout, "{}", fmt);
+}
+
+pub fn println_args(fmt: &fmt::Arguments) {
+    writeln!(&mut Stdout, "{}", fmt);
+}
+
 pub unsafe fn write_word(c: u32) {
     volatile_store(UART0, c);
 }
@@ -18,3 +49,24 @@ pub fn putc(c: u32) {
 		write_word(c);
 	}
 }
+
+/// There is only the UART, whatever the terminal.
+pub fn write_terminal(_n: usize, c: u8) {
+    putc(c as u32);
+}
+
+/// Nothing is typed yet.
+pub fn read_terminal(_n: usize) -> Option<u8> {
+    None
+}
+
+/// Echoes a typed key.
+pub fn echo(c: u32) {
+    putc(c);
+}
+
+pub fn puts(s: &str) {
+    for &c in s.as_bytes().iter() {
+        putc(c as u32);
+    }
+}
@@ -0,0 +1,13 @@
+//! Powering off and resetting the board.
+
+use cpu;
+
+/// The Versatile board can't power itself off, so this only works by exiting
+/// QEMU started with `-semihosting`.
+pub fn shutdown() -> ! {
+    cpu::exit_qemu(0)
+}
+
+pub fn reboot() -> ! {
+    cpu::reboot()
+}
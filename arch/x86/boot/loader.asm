@@ -3,6 +3,7 @@ global abort
 global start
 
 extern main
+extern stack_overflow
 
 ; Assembly code in this file is used to set up the image in memory.
 ; The directive `use16` marks the beginning of 16-bit code[1]. Label `start`
@@ -27,7 +28,7 @@ start:
 
     ; BIOS interrupt 0x13 provides disk services. When given parameter ah=2,
     ; it reads sectors from drive[2].
-    ; Load Rust code into 0x10000...0x1ffff so we can jump to it later
+    ; Load Rust code into 0x10000...0x2ffff so we can jump to it later
     mov si, 2  ; starting with sector 67
     xor di, di ; and memory segment in di
 .loop:
@@ -47,7 +48,7 @@ start:
     int 0x13          ; disk read [2]
     jc error
     add si, 128
-    cmp di, 0x1000 ; while di != 0x3000
+    cmp di, 0x2000 ; while di != 0x2000
     jne .loop
 
     ; load protected mode GDT and a null IDT
@@ -96,16 +97,28 @@ protected_mode:
     mov eax, cr4
     or eax, 512
     mov cr4, eax
-    ; Temporarily store 0 as a stack upper limit.
-    ; Rust would call morestack otherwise.
-    ; Later, we should point gs to a small segment of local data.
+    ; No stack limit until `cpu::init` points gs to local data and records
+    ; the real one. Rust would call morestack otherwise.
     mov dword[gs:0x30], 0
+    ; a null frame pointer ends backtraces
+    xor ebp, ebp
     ; jump into Rust
     call main
 abort:
-__morestack:
     jmp $
 
+; Function prologues call this when their frame would cross the stack limit
+; in gs:0x30, with the frame size pushed. Lift the limit so that reporting
+; can use the red zone below it.
+__morestack:
+    mov dword[gs:0x30], 0
+    mov eax, [esp]      ; return address, in the prologue
+    mov ecx, [esp + 4]  ; frame size
+    push ebp
+    push ecx
+    push eax
+    call stack_overflow
+
 gdtr:
     dw (gdt_end - gdt) + 1  ; size
     dd gdt                  ; offset
@@ -0,0 +1,77 @@
+global ap_trampoline
+global ap_trampoline_end
+global ap_cr3
+global ap_stack
+
+extern ap_main
+
+; Application processors start in real mode at the page given by the
+; startup IPI. This code is copied there, so it addresses its own data
+; relative to AP_BASE, and reaches the kernel with absolute jumps.
+;
+; [1]: http://wiki.osdev.org/SMP "SMP - OSDev Wiki"
+
+AP_BASE equ 0x8000
+
+%define AP_ADDR(label) (AP_BASE + (label - ap_trampoline))
+
+section .text
+use16
+ap_trampoline:
+    cli
+    cld
+    xor ax, ax
+    mov ds, ax
+    lgdt [AP_ADDR(ap_gdtr)]
+    mov eax, cr0
+    or eax, 1
+    mov cr0, eax
+    jmp dword (1 << 3):AP_ADDR(ap_protected)
+
+use32
+ap_protected:
+    mov eax, 2 << 3
+    mov ds, eax
+    mov es, eax
+    mov fs, eax
+    mov gs, eax
+    mov ss, eax
+    ; share the page directory of the bootstrap processor
+    mov eax, [AP_ADDR(ap_cr3)]
+    mov cr3, eax
+    mov eax, cr0
+    or eax, 1 << 31
+    mov cr0, eax
+    ; enable SSE instructions
+    mov eax, cr4
+    or eax, 512
+    mov cr4, eax
+    mov esp, [AP_ADDR(ap_stack)]
+    mov dword[gs:0x30], 0
+    xor ebp, ebp
+    mov eax, ap_main
+    call eax
+    jmp $
+
+align 4
+ap_cr3:
+    dd 0
+ap_stack:
+    dd 0
+
+ap_gdtr:
+    dw (ap_gdt_end - ap_gdt) - 1
+    dd AP_ADDR(ap_gdt)
+
+align 8
+ap_gdt:
+    ; null entry
+    dq 0
+    ; flat code entry
+    dw 0xffff, 0x0000
+    db 0x00, 0b10011010, 0xcf, 0x00
+    ; flat data entry
+    dw 0xffff, 0x0000
+    db 0x00, 0b10010010, 0xcf, 0x00
+ap_gdt_end:
+ap_trampoline_end:
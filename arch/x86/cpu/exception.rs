@@ -1,14 +1,16 @@
 use core::mem::transmute;
 
-use core::fmt;
-
 use platform::io;
+use platform::drivers::vga;
 use platform::cpu::mmu::Page;
-use cpu::Context;
+use cpu::{Context, Eflags, CR0, CR2, CR3};
+use cpu::interrupt::Table;
+use kernel::{backtrace, panic};
 
 #[repr(u8)]
 pub enum Fault {
     DivideError = 0,
+    Debug = 1,
     NMI = 2,
     Breakpoint = 3,
     Overflow = 4,
@@ -22,12 +24,33 @@ pub enum Fault {
     StackSegmentFault = 12,
     GeneralProtection = 13,
     PageFault = 14,
+    Reserved15 = 15,
     FloatingPointError = 16,
     AlignmentCheck = 17,
     MachineCheck = 18,
     SimdFpException = 19,
+    VirtualizationException = 20,
+    ControlProtection = 21,
+    Reserved22 = 22,
+    Reserved23 = 23,
+    Reserved24 = 24,
+    Reserved25 = 25,
+    Reserved26 = 26,
+    Reserved27 = 27,
+    HypervisorInjection = 28,
+    VmmCommunication = 29,
+    SecurityException = 30,
+    Reserved31 = 31,
 }
 
+bitflags!(flags PageFaultCode: u32 {
+    const PF_PRESENT  = 1 << 0, // protection violation, not a missing page
+    const PF_WRITE    = 1 << 1,
+    const PF_USER     = 1 << 2,
+    const PF_RESERVED = 1 << 3, // reserved bit set in a paging structure
+    const PF_FETCH    = 1 << 4  // instruction fetch
+});
+
 static Exceptions: &'static [&'static str] = &[
     "Divide-by-zero Error",
     "Debug",
@@ -50,28 +73,171 @@ static Exceptions: &'static [&'static str] = &[
     "Machine Check",
     "SIMD Floating-Point Exception",
     "Virtualization Exception",
+    "Control Protection Exception",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Reserved",
+    "Hypervisor Injection Exception",
+    "VMM Communication Exception",
+    "Security Exception",
+    "Reserved",
 ];
 
-// TODO respect destructors
-#[lang="begin_unwind"]
-unsafe extern "C" fn begin_unwind(fmt: &fmt::Arguments, file: &str, line: usize) -> ! {
-    asm!("hlt");
-    loop { }; // for divergence check
+/// Exceptions for which the CPU pushes an error code.
+fn has_error_code(vector: u8) -> bool {
+    match vector {
+        8 | 10...14 | 17 | 21 | 29 | 30 => true,
+        _ => false
+    }
+}
+
+/// Installs the common exception handler for all 32 exception vectors.
+pub unsafe fn install(table: &mut Table) {
+    let handler = exception_handler();
+    for vector in 0..32u8 {
+        let fault: Fault = transmute(vector);
+        table.set_isr(fault, has_error_code(vector), handler);
+    }
+}
+
+/// Explains a selector error code, as pushed by segment-related faults.
+fn print_selector(code: u32) {
+    if code == 0 {
+        return;
+    }
+    let table = match (code >> 1) & 0b11 {
+        0 => "GDT",
+        2 => "LDT",
+        _ => "IDT"
+    };
+    println!("Selector: {} index {}{}", table, (code >> 3) & 0x1FFF,
+             if code & 1 != 0 { " (external event)" } else { "" });
 }
 
-#[lang = "panic_fmt"]
-pub extern fn rust_begin_unwind(msg: &fmt::Arguments,
-                                file: &'static str, line: usize) -> ! {
-    unsafe { asm!("hlt"); }
-    loop { }; // for divergence check
+fn print_page_fault(code: u32) {
+    let code = PageFaultCode::from_bits_truncate(code);
+    println!("Page fault: {} of {:08x} in {} mode, {}{}{}",
+             if code.contains(PF_WRITE) { "write" } else { "read" },
+             CR2::read(),
+             if code.contains(PF_USER) { "user" } else { "kernel" },
+             if code.contains(PF_PRESENT) { "protection violation" } else { "page not present" },
+             if code.contains(PF_RESERVED) { ", reserved bit set" } else { "" },
+             if code.contains(PF_FETCH) { ", instruction fetch" } else { "" });
 }
 
+/// Prints every register saved in the context, along with control registers.
+fn dump_context(stack: &Context) {
+    let cs = stack.call_stack.cs;
+    // The CPU pushes ss:esp only when it changes privilege level.
+    let (ss, esp) = if cs & 0b11 != 0 {
+        (stack.call_stack.ss, stack.call_stack.esp)
+    } else {
+        (stack.ds, &stack.call_stack.esp as *const u32 as u32)
+    };
+
+    println!("eax={:08x} ebx={:08x} ecx={:08x} edx={:08x}",
+             stack.eax, stack.ebx, stack.ecx, stack.edx);
+    println!("esi={:08x} edi={:08x} ebp={:08x} esp={:08x}",
+             stack.esi, stack.edi, stack.ebp, esp);
+    println!("eip={:08x} cs={:04x} ss={:04x} ds={:04x} es={:04x} fs={:04x} gs={:04x}",
+             stack.call_stack.eip, cs, ss, stack.ds, stack.es, stack.fs, stack.gs);
+    println!("eflags={}", Eflags::from_bits_truncate(stack.call_stack.eflags));
+    print_control_registers();
+}
+
+fn print_control_registers() {
+    println!("cr0={:08x} cr2={:08x} cr3={:08x}",
+             CR0::read().bits, CR2::read(), CR3::read() as u32);
+}
+
+/// Walks the chain of frame pointers saved on the stack. Each frame starts
+/// with the caller's `ebp`, followed by the return address.
+fn print_backtrace(eip: u32, mut ebp: u32) {
+    println!("Backtrace:");
+    backtrace::print_frame(eip as usize);
+
+    // a corrupt `ebp` must not fault in the middle of a report
+    let (bottom, top) = super::stack_bounds();
+    let mut depth = 0;
+    // `ebp` is zeroed before entering Rust, which ends the chain.
+    while ebp as usize >= bottom && ebp as usize + 8 <= top && ebp % 4 == 0 && depth < 32 {
+        let frame = ebp as *const u32;
+        let (caller_ebp, ret) = unsafe { (*frame, *frame.offset(1)) };
+        if ret == 0 {
+            break;
+        }
+        backtrace::print_frame(ret as usize);
+        // callers' frames are further up the stack
+        if caller_ebp <= ebp {
+            break;
+        }
+        ebp = caller_ebp;
+        depth += 1;
+    }
+}
+
+/// Prints the registers of the caller and a backtrace, for reports outside
+/// of exceptions.
+pub fn dump_registers() {
+    let (eip, esp, ebp): (u32, u32, u32);
+    let (cs, ds, ss): (u16, u16, u16);
+    unsafe {
+        asm!("call 1f
+              1: pop $0" : "=r"(eip) ::: "volatile");
+        asm!("mov $0, esp
+              mov $1, ebp" : "=r"(esp), "=r"(ebp) ::: "intel");
+        asm!("mov $0, cs
+              mov $1, ds
+              mov $2, ss" : "=r"(cs), "=r"(ds), "=r"(ss) ::: "intel");
+    }
+    println!("esp={:08x} ebp={:08x} cs={:04x} ds={:04x} ss={:04x}", esp, ebp, cs, ds, ss);
+    println!("eflags={}", Eflags::read());
+    print_control_registers();
+    print_backtrace(eip, ebp);
+}
+
+/// Set while an exception is reported.
+static mut reporting: bool = false;
+
 #[no_stack_check]
 #[inline(never)]
-unsafe fn blue_screen(stack: &Context) {
-    io::puts("Exception ");
-    io::puts(Exceptions[stack.int_no as usize]);
-    asm!("hlt");
+unsafe fn blue_screen(stack: &Context) -> ! {
+    if reporting {
+        // the report itself faulted
+        super::halt();
+    }
+    reporting = true;
+    io::clear(vga::Color::Blue);
+
+    let vector = stack.int_no as u8;
+    println!("Exception {}: {}", vector, Exceptions[vector as usize]);
+    if has_error_code(vector) {
+        println!("Error code: {:08x}", stack.err_code);
+    }
+    match vector {
+        10...13 => print_selector(stack.err_code),
+        14 => print_page_fault(stack.err_code),
+        _ => {}
+    }
+    dump_context(stack);
+    print_backtrace(stack.call_stack.eip, stack.ebp);
+
+    panic::finish()
+}
+
+/// Called by `__morestack` when a frame of `frame_size` bytes doesn't fit
+/// above the stack limit.
+#[no_mangle]
+#[no_stack_check]
+pub unsafe extern "C" fn stack_overflow(eip: u32, frame_size: u32, ebp: u32) -> ! {
+    let esp: u32;
+    asm!("mov $0, esp" : "=r"(esp) ::: "volatile", "intel");
+    println!("\nStack overflow: a frame of {} bytes with esp near {:08x}", frame_size, esp);
+    print_backtrace(eip, ebp);
+    panic!("stack overflow")
 }
 
 #[no_stack_check]
@@ -84,12 +250,6 @@ pub unsafe fn exception_handler() -> unsafe extern "C" fn() {
     // Points to the data on the stack
     let stack_ptr = Context::save();
 
-    if stack_ptr.int_no as u8 == Fault::PageFault as u8 {
-        let cr2: usize;
-        asm!("mov %cr2, %eax" : "={eax}"(cr2));
-        println!("Accessed {0:x} from {1:x}", cr2, stack_ptr.call_stack.eip);
-    }
-
     if stack_ptr.int_no as u8 == Fault::Breakpoint as u8 {
         asm!("debug:" :::: "volatile")
     }
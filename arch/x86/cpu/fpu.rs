@@ -0,0 +1,147 @@
+//! Lazy switching of x87 and SSE registers. Switching tasks only sets CR0.TS,
+//! and the first floating point instruction afterwards raises a
+//! device-not-available fault, which saves the registers of their previous
+//! owner and loads those of the running task.
+//!
+//! Interrupt handlers and system calls run as the kernel task, so that the
+//! SSE code the compiler emits, such as in `memcpy`, doesn't clobber the
+//! registers of the interrupted process.
+
+use core::prelude::*;
+
+use cpu::{CR0, CR0_EM, CR0_MP, CR0_TS, FXSR, Context, LocalSegment};
+use cpu::exception::Fault;
+use cpu::interrupt::Table;
+use kernel::heap;
+
+/// The 512-byte area of `fxsave`, which must be 16-byte aligned.
+pub struct FxArea {
+    data: [u8; ..512]
+}
+
+/// The floating point registers of a process.
+pub struct FpuState {
+    area: *mut FxArea
+}
+
+impl FpuState {
+    /// Registers as after `fninit`, with all SSE exceptions masked.
+    pub fn new() -> FpuState {
+        unsafe {
+            // blocks of the buddy allocator are aligned to their size
+            let area = heap::zero_alloc::<FxArea>(1);
+            assert!(area as usize & 0xF == 0);
+            let data = &mut (*area).data;
+            data[0] = 0x7F; // FCW = 0x037F
+            data[1] = 0x03;
+            data[24] = 0x80; // MXCSR = 0x1F80
+            data[25] = 0x1F;
+            FpuState { area: area }
+        }
+    }
+
+    pub fn free(self) {
+        let cpu = LocalSegment::get();
+        if cpu.fpu_owner == self.area {
+            cpu.fpu_owner = 0 as *mut FxArea;
+        }
+        unsafe {
+            heap::free(self.area);
+        }
+    }
+}
+
+static mut enabled: bool = false;
+
+/// Makes the next floating point instruction fault unless the running task
+/// already owns the registers.
+#[inline]
+fn set_task_switched(owner: *mut FxArea, current: *mut FxArea) {
+    if !unsafe { enabled } {
+        return;
+    }
+    if owner != current {
+        CR0::write(CR0 | CR0_TS);
+    } else {
+        unsafe { asm!("clts" :::: "volatile"); }
+    }
+}
+
+/// Uses the native x87 error reporting and catches the first floating point
+/// instruction of each task. Without `fxsave`, all tasks share the registers.
+pub unsafe fn install(table: &mut Table) {
+    if !super::info().has(FXSR) {
+        return;
+    }
+    table.set_isr(Fault::NoMathCoprocessor, false, nm_handler());
+    CR0::write((CR0::read() & !CR0_EM) | CR0_MP);
+    // OSFXSR is set by the loader, OSXMMEXCPT reports SIMD exceptions as #XM
+    asm!("mov eax, cr4
+          or eax, 1 << 10
+          mov cr4, eax" ::: "eax" : "volatile", "intel");
+    enabled = true;
+}
+
+/// Makes `state` the registers of the running task. They are loaded on the
+/// task's first floating point instruction.
+pub fn switch_to(state: &FpuState) {
+    let cpu = LocalSegment::get();
+    cpu.fpu_current = state.area;
+    set_task_switched(cpu.fpu_owner, cpu.fpu_current);
+}
+
+/// Runs the following kernel code as the kernel task. Returns the task to
+/// give back to `leave_kernel`.
+pub fn enter_kernel() -> *mut FxArea {
+    let cpu = LocalSegment::get();
+    let prev = cpu.fpu_current;
+    cpu.fpu_current = 0 as *mut FxArea;
+    set_task_switched(cpu.fpu_owner, cpu.fpu_current);
+    prev
+}
+
+pub fn leave_kernel(prev: *mut FxArea) {
+    let cpu = LocalSegment::get();
+    cpu.fpu_current = prev;
+    set_task_switched(cpu.fpu_owner, cpu.fpu_current);
+}
+
+/// Hands the registers over to the running task. The kernel task's registers
+/// are scratch and never saved.
+#[no_stack_check]
+fn device_not_available() {
+    unsafe {
+        asm!("clts" :::: "volatile");
+        let cpu = LocalSegment::get();
+        if cpu.fpu_owner == cpu.fpu_current {
+            return;
+        }
+        if !cpu.fpu_owner.is_null() {
+            asm!("fxsave [$0]" :: "r"(cpu.fpu_owner) : "memory" : "volatile", "intel");
+        }
+        if cpu.fpu_current.is_null() {
+            asm!("fninit" :::: "volatile");
+        } else {
+            asm!("fxrstor [$0]" :: "r"(cpu.fpu_current) :: "volatile", "intel");
+        }
+        cpu.fpu_owner = cpu.fpu_current;
+    }
+}
+
+#[no_stack_check]
+#[inline(never)]
+unsafe fn nm_handler() -> unsafe extern "C" fn() {
+    asm!("jmp skip_nm_handler
+      nm_handler_asm:"
+        :::: "volatile", "intel");
+
+    Context::save();
+    device_not_available();
+    Context::restore();
+
+    asm!("skip_nm_handler:"
+        :::: "volatile", "intel");
+
+    extern { fn nm_handler_asm(); }
+    nm_handler_asm
+}
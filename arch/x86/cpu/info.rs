@@ -0,0 +1,173 @@
+//! Processor identification through the `cpuid` instruction.
+
+use core::fmt;
+use core::prelude::*;
+use core::str;
+
+bitflags!(flags Features: u32 {
+    const FPU    = 1 << 0,
+    const TSC    = 1 << 1,
+    const PAE    = 1 << 2,
+    const APIC   = 1 << 3,
+    const PGE    = 1 << 4,
+    const FXSR   = 1 << 5,
+    const SSE    = 1 << 6,
+    const SSE2   = 1 << 7,
+    const SSE3   = 1 << 8,
+    const SSSE3  = 1 << 9,
+    const SSE4_1 = 1 << 10,
+    const SSE4_2 = 1 << 11,
+    const X2APIC = 1 << 12,
+    const RDRAND = 1 << 13,
+    const NX     = 1 << 14,
+    const SMEP   = 1 << 15,
+    const SMAP   = 1 << 16
+});
+
+static FEATURE_NAMES: &'static [(Features, &'static str)] = &[
+    (FPU, "fpu"), (TSC, "tsc"), (PAE, "pae"), (APIC, "apic"), (PGE, "pge"),
+    (FXSR, "fxsr"), (SSE, "sse"), (SSE2, "sse2"), (SSE3, "sse3"),
+    (SSSE3, "ssse3"), (SSE4_1, "sse4.1"), (SSE4_2, "sse4.2"),
+    (X2APIC, "x2apic"), (RDRAND, "rdrand"), (NX, "nx"), (SMEP, "smep"),
+    (SMAP, "smap")
+];
+
+// (bit, feature) of the registers of each cpuid leaf
+static LEAF1_EDX: &'static [(u32, Features)] = &[
+    (0, FPU), (4, TSC), (6, PAE), (9, APIC), (13, PGE), (24, FXSR), (25, SSE), (26, SSE2)
+];
+static LEAF1_ECX: &'static [(u32, Features)] = &[
+    (0, SSE3), (9, SSSE3), (19, SSE4_1), (20, SSE4_2), (21, X2APIC), (30, RDRAND)
+];
+static LEAF7_EBX: &'static [(u32, Features)] = &[(7, SMEP), (20, SMAP)];
+static EXT1_EDX: &'static [(u32, Features)] = &[(20, NX)];
+
+#[derive(Copy)]
+pub struct CpuInfo {
+    vendor: [u8; ..12],
+    brand: [u8; ..48],
+    pub family: u32,
+    pub model: u32,
+    pub stepping: u32,
+    pub features: Features
+}
+
+/// Returns eax, ebx, ecx and edx.
+fn cpuid(leaf: u32, subleaf: u32) -> (u32, u32, u32, u32) {
+    let (a, b, c, d);
+    unsafe {
+        asm!("cpuid"
+            : "={eax}"(a), "={ebx}"(b), "={ecx}"(c), "={edx}"(d)
+            : "{eax}"(leaf), "{ecx}"(subleaf)
+            :: "volatile");
+    }
+    (a, b, c, d)
+}
+
+fn store(dst: &mut [u8], regs: &[u32]) {
+    for (i, reg) in regs.iter().enumerate() {
+        for j in 0..4 {
+            dst[i * 4 + j] = (*reg >> (j * 8)) as u8;
+        }
+    }
+}
+
+fn features(reg: u32, bits: &[(u32, Features)]) -> Features {
+    let mut f = Features::empty();
+    for &(bit, feature) in bits.iter() {
+        if reg & (1 << bit as usize) != 0 {
+            f = f | feature;
+        }
+    }
+    f
+}
+
+/// A string of `bytes` up to the first NUL, without surrounding spaces.
+fn as_str(bytes: &[u8]) -> &str {
+    let len = bytes.iter().position(|&b| b == 0).unwrap_or(bytes.len());
+    match str::from_utf8(&bytes[..len]) {
+        Ok(s) => s.trim(),
+        Err(_) => ""
+    }
+}
+
+impl CpuInfo {
+    pub fn detect() -> CpuInfo {
+        let mut info = CpuInfo {
+            vendor: [0; 12],
+            brand: [0; 48],
+            family: 0,
+            model: 0,
+            stepping: 0,
+            features: Features::empty()
+        };
+
+        let (max, b, c, d) = cpuid(0, 0);
+        store(&mut info.vendor, &[b, d, c]);
+
+        if max >= 1 {
+            let (a, _, c, d) = cpuid(1, 0);
+            let family = (a >> 8) & 0xF;
+            let model = (a >> 4) & 0xF;
+            info.stepping = a & 0xF;
+            info.family = if family == 0xF { family + ((a >> 20) & 0xFF) } else { family };
+            info.model = if family == 0x6 || family == 0xF { ((a >> 12) & 0xF0) | model } else { model };
+            info.features = features(d, LEAF1_EDX) | features(c, LEAF1_ECX);
+        }
+        if max >= 7 {
+            let (_, b, _, _) = cpuid(7, 0);
+            info.features = info.features | features(b, LEAF7_EBX);
+        }
+
+        let (max_ext, _, _, _) = cpuid(0x80000000, 0);
+        if max_ext >= 0x80000001 {
+            let (_, _, _, d) = cpuid(0x80000001, 0);
+            info.features = info.features | features(d, EXT1_EDX);
+        }
+        if max_ext >= 0x80000004 {
+            for i in 0..3 {
+                let (a, b, c, d) = cpuid(0x80000002 + i as u32, 0);
+                store(&mut info.brand[i * 16..(i + 1) * 16], &[a, b, c, d]);
+            }
+        }
+        info
+    }
+
+    pub fn vendor(&self) -> &str {
+        as_str(&self.vendor)
+    }
+
+    /// The brand string, or an empty string on older processors.
+    pub fn brand(&self) -> &str {
+        as_str(&self.brand)
+    }
+
+    #[inline]
+    pub fn has(&self, features: Features) -> bool {
+        self.features.contains(features)
+    }
+}
+
+impl fmt::Show for Features {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let mut first = true;
+        for &(ref feature, name) in FEATURE_NAMES.iter() {
+            if self.contains(*feature) {
+                try!(write!(f, "{}{}", if first { "" } else { " " }, name));
+                first = false;
+            }
+        }
+        Ok(())
+    }
+}
+
+impl fmt::Show for CpuInfo {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} family {} model {} stepping {}",
+                    self.vendor(), self.family, self.model, self.stepping));
+        if !self.brand().is_empty() {
+            try!(write!(f, " ({})", self.brand()));
+        }
+        write!(f, "\nfeatures: {}", self.features)
+    }
+}
@@ -5,19 +5,14 @@ use core::mem::transmute;
 use cpu::DtReg;
 use cpu::exception::Fault;
 use cpu::idt::{IdtEntry, IdtReg, INTR_GATE, PRESENT};
+use cpu::irq;
 use platform::drivers::pic;
 use kernel::heap;
-
-// TODO for Rust: nested C-like enums
-// #[repr(u8)]
-pub enum Int {
-    FaultInt(Fault)
-}
+use kernel::collections::Box;
 
 pub struct Table {
     reg: &'static IdtReg,
     table: *mut IdtEntry,
-    mask: u16,
 }
 
 impl Table {
@@ -28,35 +23,47 @@ impl Table {
             *(reg as *mut IdtReg) = DtReg::new(table, 256);
             Table {
                 reg: transmute(reg),
-                table: table,
-                mask: 0xffff
+                table: table
             }
         }
     }
 
-    pub unsafe fn enable_maskable(&mut self, irq: usize, isr: unsafe extern "C" fn()) {
-        *self.table.offset(irq as isize) = IdtEntry::new(
+    /// Installs a raw ISR for an IRQ line and unmasks the line. Drivers should
+    /// rather register a handler with `cpu::irq`.
+    pub unsafe fn enable_maskable(&mut self, irq: u8, isr: unsafe extern "C" fn()) {
+        *self.table.offset((irq::IRQ_BASE + irq) as isize) = IdtEntry::new(
             isr,                // interrupt service routine
             1 << 3,             // segment selector
             INTR_GATE | PRESENT // flags
         );
 
-        self.mask &= !(1u16 << (irq & 0b1111));
-        pic::mask(self.mask);
+        irq::enable(irq);
     }
 
     #[allow(visible_private_types)]
     pub unsafe fn set_isr(&mut self, val: Fault, code: bool, handler: unsafe extern "C" fn()) {
-        *self.table.offset(val as isize) = Isr::new(Int::FaultInt(val), code).idt_entry(handler);
+        self.set_vector(val as u8, code, handler);
+    }
+
+    /// Points a vector to a new ISR that pushes the vector number and jumps
+    /// to `handler`.
+    pub unsafe fn set_vector(&mut self, vector: u8, code: bool, handler: unsafe extern "C" fn()) {
+        *self.table.offset(vector as isize) = Isr::idt_entry(Isr::new(vector, code), handler);
     }
 
     pub unsafe fn load(&self) {
         self.reg.load();
         pic::remap();
-        pic::mask(self.mask);
+        // lines are unmasked as handlers are registered
+        pic::mask(0xffff);
         enable();
         // loop {} // faults here?
     }
+
+    /// Loads the table on another processor, once the controllers are set up.
+    pub unsafe fn load_local(&self) {
+        self.reg.load();
+    }
 }
 
 fn enable() {
@@ -88,25 +95,24 @@ fn enable() {
 pub struct Isr {
     push_dummy: u8, // push eax  // (only for exceptions without error codes)
     push: u8,       // push byte <imm>  // save int. number
-    value: Int,
+    value: u8,
     jmp: u8,        // jmp rel  // jump to the common handler
     rel: i32
 }
 
 impl Isr {
-    // TODO: drop for Isr
-    pub fn new<'a>(val: Int, code: bool) -> &'a mut Isr {
-        let this: &mut Isr = unsafe { transmute(heap::alloc::<Isr>(1)) };
-        *this = Isr {
+    pub fn new(val: u8, code: bool) -> Box<Isr> {
+        Box::new(Isr {
             push_dummy: if code { 0x90 } else { 0x50 },   // [3]
             push: 0x6a, value: val,
             jmp: 0xe9, rel: -5
-        };
-        this
+        })
     }
 
-    pub unsafe fn idt_entry(&mut self, handler: unsafe extern "C" fn()) -> IdtEntry {
-        self.rel = handler as i32 - (self as *mut Isr).offset(1) as i32;
-        IdtEntry::new(transmute(self), 1 << 3, INTR_GATE | PRESENT)
+    /// The IDT keeps pointing to the routine, so it is never freed.
+    pub unsafe fn idt_entry(isr: Box<Isr>, handler: unsafe extern "C" fn()) -> IdtEntry {
+        let this = isr.into_raw();
+        (*this).rel = handler as i32 - this.offset(1) as i32;
+        IdtEntry::new(transmute(this), 1 << 3, INTR_GATE | PRESENT)
     }
 }
@@ -1,4 +1,4 @@
-//! Low-level CPU IO: inb, outb instructions.
+//! Low-level CPU IO: in and out instructions.
 
 #[inline(always)]
 pub fn out<T>(port: u16, val: T) {
@@ -16,6 +16,49 @@ pub fn inb(port: u16) -> u8 {
     val
 }
 
-pub fn wait(port: u16, mask: u8) {
-    while inb(port) & mask != 0 {}
+#[inline(always)]
+pub fn outw(port: u16, val: u16) {
+    unsafe {
+        asm!("out $1, $0" :: "{ax}"(val), "{dx}"(port) :: "intel");
+    }
+}
+
+#[inline(always)]
+pub fn inw(port: u16) -> u16 {
+    let mut val: u16;
+    unsafe {
+        asm!("in $0, $1" : "={ax}"(val) : "{dx}"(port) :: "intel");
+    }
+    val
+}
+
+#[inline(always)]
+pub fn outl(port: u16, val: u32) {
+    unsafe {
+        asm!("out $1, $0" :: "{eax}"(val), "{dx}"(port) :: "intel");
+    }
+}
+
+#[inline(always)]
+pub fn inl(port: u16) -> u32 {
+    let mut val: u32;
+    unsafe {
+        asm!("in $0, $1" : "={eax}"(val) : "{dx}"(port) :: "intel");
+    }
+    val
+}
+
+/// Polls for about 100 ms, one port 0x80 read apart.
+pub const WAIT_LOOPS: u32 = 100000;
+
+/// Waits until the bits of `mask` in `port` clear. Returns false if they
+/// don't in time.
+pub fn wait(port: u16, mask: u8) -> bool {
+    for _ in 0..WAIT_LOOPS {
+        if inb(port) & mask == 0 {
+            return true;
+        }
+        inb(0x80);
+    }
+    false
 }
@@ -0,0 +1,214 @@
+//! Hardware interrupt lines. Drivers register handlers for an ISA line, and a
+//! common dispatcher calls them and acknowledges the interrupt controller.
+//! Lines are numbered the same whether the 8259 PIC or the IO-APIC delivers
+//! them.
+
+use core::prelude::*;
+
+use cpu::{Context, fpu};
+use cpu::interrupt::Table;
+use kernel::mm::OutOfMemory;
+use platform::drivers::{apic, ioapic, pic};
+use platform::drivers::apic::Topology;
+
+/// The vector of IRQ 0 after remapping the PIC.
+pub const IRQ_BASE: u8 = 0x20;
+/// Handlers that can share one line.
+const MAX_SHARED: usize = 4;
+
+pub type Handler = fn();
+
+static mut handlers: [[Option<Handler>; ..MAX_SHARED]; ..16] = [[None; MAX_SHARED]; 16];
+
+#[derive(Copy, PartialEq)]
+pub enum Controller {
+    Pic,
+    Apic
+}
+
+static mut controller: Controller = Controller::Pic;
+/// The IO-APIC input and polarity/trigger flags of each ISA line.
+static mut gsi: [(u32, u16); ..16] = [(0, 0); 16];
+/// Line 0 is driven by the local APIC timer instead of the PIT.
+static mut lapic_timer: bool = false;
+
+pub fn controller() -> Controller {
+    unsafe { controller }
+}
+
+/// Installs the dispatcher for all 16 lines. Lines stay masked until a
+/// handler is registered.
+pub unsafe fn install(table: &mut Table) {
+    let handler = irq_handler();
+    for irq in 0..16u8 {
+        table.set_vector(IRQ_BASE + irq, false, handler);
+    }
+}
+
+/// Adds a handler to a line, next to the ones already there, and unmasks the
+/// line. Returns `false` when the line is full.
+pub fn register(irq: u8, handler: Handler) -> bool {
+    unsafe {
+        for slot in handlers[irq as usize].iter_mut() {
+            if slot.is_none() {
+                *slot = Some(handler);
+                enable(irq);
+                return true;
+            }
+        }
+    }
+    false
+}
+
+/// Removes a handler. The line is masked once it has no handlers left.
+pub fn unregister(irq: u8, handler: Handler) {
+    unsafe {
+        let line = &mut handlers[irq as usize];
+        for slot in line.iter_mut() {
+            match *slot {
+                Some(f) if f as usize == handler as usize => *slot = None,
+                _ => {}
+            }
+        }
+        if line.iter().all(|slot| slot.is_none()) {
+            disable(irq);
+        }
+    }
+}
+
+pub fn enable(irq: u8) {
+    unsafe {
+        match controller {
+            Controller::Pic => {
+                pic::enable(irq);
+                if irq >= 8 {
+                    pic::enable(pic::CASCADE);
+                }
+            }
+            Controller::Apic if irq == 0 && lapic_timer => {}
+            Controller::Apic => ioapic::unmask(gsi[irq as usize].0)
+        }
+    }
+}
+
+pub fn disable(irq: u8) {
+    unsafe {
+        match controller {
+            Controller::Pic => pic::disable(irq),
+            Controller::Apic if irq == 0 && lapic_timer => apic::stop_timer(),
+            Controller::Apic => ioapic::mask(gsi[irq as usize].0)
+        }
+    }
+}
+
+fn eoi(irq: u8) {
+    match controller() {
+        Controller::Pic => pic::eoi(irq),
+        Controller::Apic => apic::eoi()
+    }
+}
+
+fn dispatch(irq: u8) {
+    // The local APIC delivers spurious interrupts on their own vector.
+    if controller() == Controller::Pic && pic::is_spurious(irq) {
+        // The master did see the cascaded request from the slave.
+        if irq >= 8 {
+            pic::eoi(pic::CASCADE);
+        }
+        return;
+    }
+
+    let task = fpu::enter_kernel();
+    unsafe {
+        for slot in handlers[irq as usize].iter() {
+            slot.map(|f| f());
+        }
+    }
+    eoi(irq);
+    fpu::leave_kernel(task);
+}
+
+/// Switches from the PIC to the local APIC and the first IO-APIC of
+/// `topology`, which must list at least one IO-APIC. ISA lines keep their numbers and vectors, and the lines with
+/// handlers stay enabled.
+pub unsafe fn use_apic(table: &mut Table, topology: &Topology) -> Result<(), OutOfMemory> {
+    let first = match topology.ioapics.first() {
+        Some(first) => first,
+        None => panic!("irq: no IO-APIC to route through")
+    };
+    try!(apic::init(topology.lapic_addr));
+    try!(ioapic::init(first));
+    table.set_vector(apic::SPURIOUS_VECTOR, false, spurious_handler());
+
+    for irq in 0..16u8 {
+        gsi[irq as usize] = (irq as u32, 0);
+    }
+    for o in topology.overrides.iter() {
+        if o.isa_irq < 16 {
+            gsi[o.isa_irq as usize] = (o.gsi, o.flags);
+        }
+    }
+
+    pic::mask(0xffff);
+    controller = Controller::Apic;
+
+    let dest = apic::id();
+    for irq in 0..16u8 {
+        if irq == pic::CASCADE {
+            continue;
+        }
+        let (line, flags) = gsi[irq as usize];
+        ioapic::route(line, IRQ_BASE + irq, flags, dest);
+        if handlers[irq as usize].iter().any(|slot| slot.is_some()) {
+            enable(irq);
+        }
+    }
+    Ok(())
+}
+
+/// Drives line 0 from the local APIC timer at `hz` instead of the PIT.
+/// Only available once the APIC is in use; `clock::init` calls it.
+pub fn use_lapic_timer(hz: u32) -> bool {
+    unsafe {
+        if controller != Controller::Apic {
+            return false;
+        }
+        ioapic::mask(gsi[0].0);
+        lapic_timer = true;
+        apic::start_timer(IRQ_BASE, hz);
+    }
+    true
+}
+
+#[no_stack_check]
+#[inline(never)]
+unsafe fn irq_handler() -> unsafe extern "C" fn() {
+    asm!("jmp skip_irq_handler
+      irq_handler_asm:"
+        :::: "volatile", "intel");
+
+    let stack_ptr = Context::save();
+    dispatch(stack_ptr.int_no as u8 - IRQ_BASE);
+    Context::restore();
+
+    asm!("skip_irq_handler:"
+        :::: "volatile", "intel");
+
+    extern { fn irq_handler_asm(); }
+    irq_handler_asm
+}
+
+/// Spurious interrupts of the local APIC are neither handled nor acknowledged.
+#[no_stack_check]
+#[inline(never)]
+unsafe fn spurious_handler() -> unsafe extern "C" fn() {
+    asm!("jmp skip_spurious_handler
+      spurious_handler_asm:
+        add esp, 8
+        iretd
+      skip_spurious_handler:"
+        :::: "volatile", "intel");
+
+    extern { fn spurious_handler_asm(); }
+    spurious_handler_asm
+}
@@ -10,7 +10,7 @@ use core;
 
 use kernel::mm::physical;
 use kernel::mm::physical::Phys;
-use kernel;
+use kernel::mm::OutOfMemory;
 
 pub type Frame = [u8; ..PAGE_SIZE];
 
@@ -18,6 +18,8 @@ bitflags!(flags Flags: usize {
     const PRESENT  = 1 << 0,
     const RW       = 1 << 1,
     const USER     = 1 << 2,
+    const WRITE_THROUGH = 1 << 3,
+    const NO_CACHE = 1 << 4,
     const ACCESSED = 1 << 5,
     const HUGE     = 1 << 7
 });
@@ -66,11 +68,6 @@ pub unsafe fn init() {
     // When accessing its virtual address(...)
     (*dir.as_ptr()).map_self(dir);
 
-    kernel::int_table.map(|mut t| {
-        use super::exception::{Fault, exception_handler};
-        t.set_isr(Fault::PageFault, true, exception_handler());
-    });
-
     switch_directory(dir);
     enable_paging();
 }
@@ -85,8 +82,26 @@ fn enable_paging() {
     CR0::write(CR0 | CR0_PG);
 }
 
-pub unsafe fn map(page_ptr: *mut u8, len: usize, flags: Flags) {
-    (*VMEM).dir.map(page_ptr, len, flags);
+pub unsafe fn map(page_ptr: *mut u8, len: usize, flags: Flags) -> Result<(), OutOfMemory> {
+    (*VMEM).dir.map(page_ptr, len, flags)
+}
+
+/// Maps physical memory, such as firmware tables, at its own address.
+pub unsafe fn map_physical(phys: usize, len: usize, flags: Flags) -> Result<*mut u8, OutOfMemory> {
+    let mut page = phys & !(PAGE_SIZE - 1);
+    while page < phys + len {
+        let frame: Phys<u8> = Phys::at(page);
+        match (*VMEM).dir.set_page(page as *mut u8, frame, flags | PRESENT) {
+            Some(_) => page += PAGE_SIZE,
+            None => return Err(OutOfMemory)
+        }
+    }
+    Ok(phys as *mut u8)
+}
+
+/// Maps device registers at their physical address, bypassing the cache.
+pub unsafe fn map_device(phys: usize, len: usize) -> Result<*mut u8, OutOfMemory> {
+    map_physical(phys, len, RW | WRITE_THROUGH | NO_CACHE)
 }
 
 #[inline]
@@ -110,6 +125,11 @@ impl Page {
         Phys::at(p & 0xFFFFF000)
     }
 
+    fn bits(&self) -> usize {
+        let &Page(p) = self;
+        p
+    }
+
     fn is_present(self) -> bool {
         self.contains(PRESENT)
     }
@@ -173,40 +193,106 @@ impl Table<Page> {
 
 // Can't impl on typedefs. Rust #9767
 impl Table<Table<Page>> {
-    fn fetch_table<T>(&mut self, vptr: *mut T, flags: Flags) -> *mut PageTable {
+    fn fetch_table<T>(&mut self, vptr: *mut T, flags: Flags) -> Option<*mut PageTable> {
         match self.get(vptr as usize) {
             table @ Page(_) if table.is_present() => {
-                table.physical().as_ptr()
+                Some(table.physical().as_ptr())
             }
             _ => unsafe { // allocate table
-                let table: Phys<PageTable> = physical::zero_alloc_frames(1);
-                self.set_addr(vptr, table, flags); // page fault
-                // flush_tlb(table);
-                table.as_ptr()
+                match physical::try_zero_alloc_frames(1) {
+                    Some(table) => {
+                        let table: Phys<PageTable> = table;
+                        self.set_addr(vptr, table, flags); // page fault
+                        // flush_tlb(table);
+                        Some(table.as_ptr())
+                    }
+                    None => None
+                }
+            }
+        }
+    }
+
+    /// Returns `None` when a page table can't be allocated.
+    pub unsafe fn set_page<T>(&mut self, vptr: *mut T, phys: Phys<T>, flags: Flags) -> Option<*mut T> {
+        match self.fetch_table(vptr, flags) {
+            Some(table) => {
+                (*table).set_addr(vptr, phys, flags);
+                Some(vptr)
             }
+            None => None
         }
     }
 
-    pub unsafe fn set_page<T>(&mut self, vptr: *mut T, phys: Phys<T>, flags: Flags) -> *mut T {
-        let table = self.fetch_table(vptr, flags);
-        (*table).set_addr(vptr, phys, flags);
-        vptr
+    /// Clears the entry for `vptr` and returns the frame it pointed to.
+    unsafe fn clear_page(&mut self, vptr: *mut u8) -> Option<Phys<Frame>> {
+        let table = self.get(vptr as usize);
+        if !table.is_present() {
+            return None;
+        }
+        let table: *mut PageTable = table.physical().as_ptr();
+        let page = (*table).get(vptr as usize);
+        if !page.is_present() {
+            return None;
+        }
+        (*table).set(vptr as usize, Page(0));
+        flush_tlb(vptr);
+        Some(page.physical())
     }
 
-    pub unsafe fn map_frame(&mut self, vptr: *mut u8, flags: Flags) {
-        self.set_page(vptr, physical::alloc_frames(1), flags | PRESENT);
+    pub unsafe fn map_frame(&mut self, vptr: *mut u8, flags: Flags) -> Result<(), OutOfMemory> {
+        let frame = match physical::try_alloc_frames(1) {
+            Some(frame) => frame,
+            None => return Err(OutOfMemory)
+        };
+        match self.set_page(vptr, frame, flags | PRESENT) {
+            Some(_) => Ok(()),
+            None => {
+                physical::free_frames(frame);
+                Err(OutOfMemory)
+            }
+        }
     }
 
-    pub fn map(&mut self, mut page_ptr: *mut u8, len: usize, flags: Flags) {
+    /// Maps fresh frames over `len` bytes. On failure, the pages mapped so far
+    /// are released, so that only the caller's request fails.
+    pub fn map(&mut self, page_ptr: *mut u8, len: usize, flags: Flags) -> Result<(), OutOfMemory> {
         // TODO: optimize with uints?
         unsafe {
             let end = page_ptr.offset(len as isize);
-            while page_ptr < end {
-                let frame = physical::alloc_frames(1);
-                self.set_page(page_ptr, frame, flags | PRESENT);
-                (*VMEM).dir.set_page(page_ptr, frame, flags | PRESENT);
-                page_ptr = page_ptr.offset(PAGE_SIZE as isize);
+            let mut ptr = page_ptr;
+            while ptr < end {
+                let frame = match physical::try_alloc_frames(1) {
+                    Some(frame) => frame,
+                    None => {
+                        self.unmap(page_ptr, ptr);
+                        return Err(OutOfMemory);
+                    }
+                };
+                if self.set_page(ptr, frame, flags | PRESENT).is_none() {
+                    physical::free_frames(frame);
+                    self.unmap(page_ptr, ptr);
+                    return Err(OutOfMemory);
+                }
+                if (*VMEM).dir.set_page(ptr, frame, flags | PRESENT).is_none() {
+                    // the frame is already in this table, release it with the rest
+                    self.unmap(page_ptr, ptr.offset(PAGE_SIZE as isize));
+                    return Err(OutOfMemory);
+                }
+                ptr = ptr.offset(PAGE_SIZE as isize);
             }
+            Ok(())
+        }
+    }
+
+    /// Unmaps pages mapped with `map` and frees their frames.
+    pub unsafe fn unmap(&mut self, mut page_ptr: *mut u8, end: *mut u8) {
+        while page_ptr < end {
+            match self.clear_page(page_ptr) {
+                Some(frame) => physical::free_frames(frame),
+                None => {}
+            }
+            (*VMEM).dir.clear_page(page_ptr);
+            page_ptr = page_ptr.offset(PAGE_SIZE as isize);
         }
     }
 
@@ -214,10 +300,14 @@ impl Table<Table<Page>> {
         self.set(DIR_VADDR as usize, Page::new(this, PRESENT | RW));
     }
 
-    pub fn clone(&self) -> Phys<PageDirectory> {
+    /// A new directory that shares the kernel's tables below 0xC0000000.
+    pub fn try_clone(&self) -> Result<Phys<PageDirectory>, OutOfMemory> {
         unsafe {
             // new directory
-            let dir_phys: Phys<PageDirectory> = physical::zero_alloc_frames(1);
+            let dir_phys: Phys<PageDirectory> = match physical::try_zero_alloc_frames(1) {
+                Some(frame) => frame,
+                None => return Err(OutOfMemory)
+            };
 
             let &VMemLayout { ref mut temp1, ref mut dir, .. } = &mut *VMEM;
             dir.set_page(temp1, dir_phys, PRESENT | RW);
@@ -226,13 +316,30 @@ impl Table<Table<Page>> {
             let cnt = 0xC0000000 / (ENTRIES * PAGE_SIZE);
             copy_nonoverlapping(&mut temp1.entries[0] as *mut Page, &self.entries as *const Page, cnt);
 
-            dir_phys
+            Ok(dir_phys)
         }
     }
 }
 
-pub fn clone_directory() -> Phys<PageDirectory> {
+pub fn clone_directory() -> Result<Phys<PageDirectory>, OutOfMemory> {
     unsafe {
-        (*VMEM).dir.clone()
+        (*VMEM).dir.try_clone()
+    }
+}
+
+/// Frees a directory made by `clone_directory`, with the page tables it
+/// doesn't share with the current directory. Its own pages must have been
+/// unmapped already.
+pub unsafe fn free_directory(dir: Phys<PageDirectory>) {
+    let this = &*dir.as_ptr();
+    let current = &(*VMEM).dir;
+    // the last entry maps the directory itself
+    for i in 0..ENTRIES - 1 {
+        let table = this.entries[i];
+        if table.is_present() && table.bits() != current.entries[i].bits() {
+            let table: Phys<PageTable> = table.physical();
+            physical::free_frames(table);
+        }
     }
+    physical::free_frames(dir);
 }
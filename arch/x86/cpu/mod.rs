@@ -1,43 +1,56 @@
 use core::mem::size_of;
+use core::fmt;
 use core::option::Option;
 use core::option::Option::{Some, None};
 use core;
 
 use kernel::heap;
+use platform::runtime::stack;
 use kernel;
 
 mod gdt;
 mod idt;
 mod tss;
 pub mod interrupt;
+pub mod irq;
+pub mod syscall;
+pub mod smp;
+mod info;
+pub mod fpu;
 pub mod io;
 mod exception;
 pub mod mmu;
 
-macro_rules! cpuid(
-    ($n:expr, $s1:expr, $s2:expr, $s3:expr, $s4:expr) => (
-        asm!("cpuid"
-            : "=A"($s1),
-              "={ebx}"($s2),
-              "={edx}"($s3),
-              "={ecx}"($s4)
-            : "A"($n) :: "intel");
-    );
-    ($n:expr, *$s1:expr) => (
-        cpuid!($n, (*$s1)[0], (*$s1)[1], (*$s1)[2], (*$s1)[3]);
-    );
-    ($n:expr, $s1:expr) => (
-        asm!("cpuid"
-            : "=A"($s1)
-            : "A"($n) : "ebx", "edx", "ecx" : "intel");
-    );
-);
+pub use self::exception::dump_registers;
+pub use self::info::{CpuInfo, Features, FPU, TSC, PAE, APIC, PGE, FXSR, SSE, SSE2, SSE3,
+                     SSSE3, SSE4_1, SSE4_2, X2APIC, RDRAND, NX, SMEP, SMAP};
 
 bitflags!(flags Eflags: u32 {
-    const CF = 1 << 0,
-    const IF = 1 << 9
+    const CF   = 1 << 0,
+    const PF   = 1 << 2,
+    const AF   = 1 << 4,
+    const ZF   = 1 << 6,
+    const SF   = 1 << 7,
+    const TF   = 1 << 8,
+    const IF   = 1 << 9,
+    const DF   = 1 << 10,
+    const OF   = 1 << 11,
+    const IOPL = 3 << 12,
+    const NT   = 1 << 14,
+    const RF   = 1 << 16,
+    const VM   = 1 << 17,
+    const AC   = 1 << 18,
+    const VIF  = 1 << 19,
+    const VIP  = 1 << 20,
+    const ID   = 1 << 21
 });
 
+static EFLAGS_NAMES: &'static [(Eflags, &'static str)] = &[
+    (CF, "CF"), (PF, "PF"), (AF, "AF"), (ZF, "ZF"), (SF, "SF"), (TF, "TF"),
+    (IF, "IF"), (DF, "DF"), (OF, "OF"), (NT, "NT"), (RF, "RF"), (VM, "VM"),
+    (AC, "AC"), (VIF, "VIF"), (VIP, "VIP"), (ID, "ID")
+];
+
 impl Eflags {
     fn read() -> Eflags {
         unsafe {
@@ -48,9 +61,30 @@ impl Eflags {
     }
 }
 
+impl fmt::Show for Eflags {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{:08x} [", self.bits));
+        for &(ref flag, name) in EFLAGS_NAMES.iter() {
+            if self.bits & flag.bits != 0 {
+                try!(write!(f, " {}", name));
+            }
+        }
+        write!(f, " IOPL={} ]", (self.bits & IOPL.bits) >> 12)
+    }
+}
+
 bitflags!(flags CR0Flags: u32 {
-    // TODO: all flags
-    const CR0_PG = 1 << 31
+    const CR0_PE = 1 << 0,  // Protected mode
+    const CR0_MP = 1 << 1,  // Monitor coprocessor
+    const CR0_EM = 1 << 2,  // x87 emulation
+    const CR0_TS = 1 << 3,  // Task switched
+    const CR0_ET = 1 << 4,
+    const CR0_NE = 1 << 5,  // Native x87 errors
+    const CR0_WP = 1 << 16, // Write protect
+    const CR0_AM = 1 << 18, // Alignment mask
+    const CR0_NW = 1 << 29, // Not write-through
+    const CR0_CD = 1 << 30, // Cache disable
+    const CR0_PG = 1 << 31  // Paging
 });
 
 struct CR0;
@@ -81,6 +115,20 @@ impl core::ops::BitOr<CR0Flags, CR0Flags> for CR0 {
     }
 }
 
+struct CR2;
+
+impl CR2 {
+    /// The linear address that caused the last page fault.
+    #[inline]
+    fn read() -> usize {
+        unsafe {
+            let addr;
+            asm!("mov $0, cr2" : "=r"(addr) ::: "intel");
+            addr
+        }
+    }
+}
+
 struct CR3;
 
 // http://www.jaist.ac.jp/iscenter-new/mpc/altix/altixdata/opt/intel/vtune/doc/users_guide/mergedProjects/analyzer_ec/mergedProjects/reference_olh/mergedProjects/instructions/instruct32_hh/vc178.htm
@@ -102,6 +150,18 @@ impl CR3 {
     }
 }
 
+/// Reads a model-specific register.
+pub unsafe fn rdmsr(msr: u32) -> u64 {
+    let (lo, hi): (u32, u32);
+    asm!("rdmsr" : "={eax}"(lo), "={edx}"(hi) : "{ecx}"(msr) :: "volatile");
+    (hi as u64) << 32 | lo as u64
+}
+
+/// Writes a model-specific register.
+pub unsafe fn wrmsr(msr: u32, val: u64) {
+    asm!("wrmsr" :: "{ecx}"(msr), "{eax}"(val as u32), "{edx}"((val >> 32) as u32) :: "volatile");
+}
+
 pub trait Load {
     unsafe fn load(reg: &DtReg<Self>);
 }
@@ -170,6 +230,16 @@ impl Context {
 
 struct LocalSegment {
     ts: tss::TssEntry,
+    /// Index of this processor, 0 for the bootstrap processor.
+    cpu: usize,
+    /// Local timer interrupts received.
+    ticks: usize,
+    /// The end of the stack this processor runs on. Its limit is in the TLS.
+    stack_top: usize,
+    /// Whose floating point registers are loaded, and whose should be.
+    /// Null stands for the kernel.
+    fpu_owner: *mut fpu::FxArea,
+    fpu_current: *mut fpu::FxArea
 }
 
 impl LocalSegment {
@@ -185,16 +255,24 @@ impl LocalSegment {
 
 pub static mut desc_table: Option<gdt::Gdt> = None;
 
-pub fn init() {
+/// The boot stack ends above the BIOS data area.
+const BOOT_STACK_BOTTOM: usize = 0x500;
+/// The bootloader sets up the stack below itself.
+const BOOT_STACK_TOP: usize = 0x7c00;
+
+/// Builds and loads a GDT, TSS and local segment for the processor with
+/// index `cpu`, and records its stack.
+fn load_descriptors(cpu: usize, stack_bottom: usize, stack_top: usize) -> gdt::Gdt {
     use cpu::gdt::{Gdt, GdtEntry, SIZE_32, STORAGE, CODE_READ, DATA_WRITE, DPL3};
 
     let local_data = unsafe {
-        heap::zero_alloc::<LocalSegment>(1)
+        let local_data = heap::zero_alloc::<LocalSegment>(1);
+        (*local_data).cpu = cpu;
+        local_data
     };
     let tls = unsafe {
         let seg = heap::zero_alloc::<u32>(32);
         *seg = local_data as u32;
-        // *(mut_offset(seg, 12)) = 0; // TODO: record stack bottom later
         seg
     };
 
@@ -208,18 +286,146 @@ pub fn init() {
         t.enable(6, (*local_data).ts.gdt_entry());
     }
     t.load(1 << 3, 2 << 3, 5 << 3);
+    unsafe {
+        set_stack(stack_bottom, stack_top);
+    }
+    t
+}
+
+/// Records the stack this processor runs on, from `bottom` up to `top`.
+pub unsafe fn set_stack(bottom: usize, top: usize) {
+    stack::record_sp_limit(bottom + stack::RED_ZONE);
+    LocalSegment::get().stack_top = top;
+}
+
+/// The stack this processor runs on, from its bottom to its top, for
+/// checking pointers into it.
+pub fn stack_bounds() -> (usize, usize) {
+    unsafe {
+        if desc_table.is_none() {
+            // nothing is recorded before `init`
+            return (BOOT_STACK_BOTTOM, BOOT_STACK_TOP);
+        }
+        (stack::get_sp_limit() - stack::RED_ZONE, LocalSegment::get().stack_top)
+    }
+}
+
+pub fn init() {
+    info();
+    let t = load_descriptors(0, BOOT_STACK_BOTTOM, BOOT_STACK_TOP);
 
     unsafe {
         desc_table = Some(t);
 
         kernel::int_table.map(|mut t| {
-            use cpu::exception::{Fault, exception_handler};
-            t.set_isr(Fault::Breakpoint, false, exception_handler());
+            exception::install(&mut t);
+            fpu::install(&mut t);
+            irq::install(&mut t);
+            syscall::install(&mut t);
         });
 
         mmu::init();
     }
 }
 
-pub fn info() {
+/// Sets up an application processor, which shares the IDT and paging of the
+/// bootstrap processor.
+fn init_ap(cpu: usize, stack_bottom: usize, stack_top: usize) {
+    // the GDT stays allocated for as long as the processor runs
+    load_descriptors(cpu, stack_bottom, stack_top);
+    unsafe {
+        kernel::int_table.map(|t| t.load_local());
+    }
+}
+
+/// Index of the processor running this code.
+pub fn current() -> usize {
+    LocalSegment::get().cpu
+}
+
+/// Hints the processor that it is busy-waiting.
+#[inline]
+pub fn relax() {
+    unsafe {
+        asm!("pause" :::: "volatile");
+    }
+}
+
+/// Waits for the next interrupt.
+pub fn idle() {
+    unsafe {
+        asm!("sti
+              hlt" :::: "volatile");
+    }
+}
+
+static mut cpu_info: Option<CpuInfo> = None;
+
+/// Identifies the processor, once.
+pub fn info() -> CpuInfo {
+    unsafe {
+        match cpu_info {
+            Some(info) => info,
+            None => {
+                let info = CpuInfo::detect();
+                cpu_info = Some(info);
+                info
+            }
+        }
+    }
+}
+
+/// Whether this processor takes maskable interrupts.
+pub fn interrupts_enabled() -> bool {
+    Eflags::read().contains(IF)
+}
+
+/// Runs `f` with interrupts disabled, then restores the interrupt flag.
+pub fn without_interrupts<T, F: FnOnce() -> T>(f: F) -> T {
+    let enabled = Eflags::read().contains(IF);
+    unsafe {
+        asm!("cli" :::: "volatile");
+    }
+    let result = f();
+    if enabled {
+        unsafe {
+            asm!("sti" :::: "volatile");
+        }
+    }
+    result
+}
+
+/// Stops the processor for good.
+pub fn halt() -> ! {
+    loop {
+        unsafe {
+            asm!("cli
+                  hlt" :::: "volatile");
+        }
+    }
+}
+
+/// Resets the machine by pulsing the reset line of the keyboard controller.
+/// Should that fail, a triple fault with an empty IDT resets the processor.
+pub fn reboot() -> ! {
+    io::wait(0x64, 2);
+    io::out(0x64, 0xFEu8);
+    for _ in 0..100000u32 {
+        io::inb(0x80); // give the controller time
+    }
+    unsafe {
+        asm!("cli
+              push 0
+              push 0
+              lidt [esp]
+              int3" :::: "volatile", "intel");
+    }
+    halt()
+}
+
+/// Exits QEMU started with `-device isa-debug-exit,iobase=0xf4,iosize=0x04`.
+/// Its exit status becomes `(code << 1) | 1`.
+pub fn exit_qemu(code: u8) -> ! {
+    io::out(0xF4, code);
+    halt()
 }
@@ -0,0 +1,164 @@
+//! Symmetric multiprocessing. Application processors are started one at a
+//! time with INIT-SIPI-SIPI[[1]], run the real-mode trampoline in
+//! `boot/trampoline.asm`, then get their own descriptors, stack and local
+//! timer and run tasks from their own run queue.
+//!
+//! 1. [MultiProcessor Specification 1.4, B.4][[1]]
+//! [1]: http://www.intel.com/design/pentium/datashts/24201606.pdf
+
+use core::atomic::{AtomicUsize, Ordering, ATOMIC_USIZE_INIT};
+use core::intrinsics::copy_nonoverlapping;
+use core::prelude::*;
+
+use cpu::{Context, CR3, LocalSegment};
+use cpu::interrupt::Table;
+use kernel::{heap, sched};
+use platform::drivers::{apic, pit};
+use platform::drivers::apic::Topology;
+
+/// Vector of the local timer of application processors.
+pub const TIMER_VECTOR: u8 = 0x40;
+const TIMER_HZ: u32 = 100;
+
+/// Where the trampoline is copied. Startup IPIs take a page number below 1 MiB.
+const TRAMPOLINE: usize = 0x8000;
+const STACK_SIZE: usize = 0x8000;
+
+extern {
+    static ap_trampoline: u8;
+    static ap_trampoline_end: u8;
+    static ap_cr3: u32;
+    static ap_stack: u32;
+}
+
+/// Processors running, including the bootstrap processor.
+static online: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Application processors that ran a task from their queue.
+static worked: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Index of the application processor being started, until it takes it.
+/// Zero when there is none to take.
+static starting: AtomicUsize = ATOMIC_USIZE_INIT;
+/// Stack for the application processor being started.
+static mut next_stack: usize = 0;
+
+/// The copy of a variable of the trampoline.
+unsafe fn trampoline_var(var: &u32) -> *mut u32 {
+    let offset = var as *const u32 as usize - &ap_trampoline as *const u8 as usize;
+    (TRAMPOLINE + offset) as *mut u32
+}
+
+/// Starts all other processors of `topology` and returns how many processors
+/// are online. The local APIC must already be in use.
+pub unsafe fn start(table: &mut Table, topology: &Topology) -> usize {
+    table.set_vector(TIMER_VECTOR, false, timer_handler());
+    // before any processor shares the PIT
+    apic::calibrate_timer();
+    sched::init(topology.cpus.len());
+
+    let len = &ap_trampoline_end as *const u8 as usize - &ap_trampoline as *const u8 as usize;
+    copy_nonoverlapping(TRAMPOLINE as *mut u8, &ap_trampoline, len);
+    *trampoline_var(&ap_cr3) = CR3::read() as u32;
+
+    online.store(1, Ordering::SeqCst);
+    let me = apic::id();
+    for &id in topology.cpus.iter() {
+        if id == me {
+            continue;
+        }
+        let cpu = online.load(Ordering::SeqCst);
+        // never freed, since a late processor could still use it
+        let stack = heap::alloc::<u8>(STACK_SIZE);
+        *trampoline_var(&ap_stack) = stack.offset(STACK_SIZE as isize) as u32;
+        next_stack = stack as usize;
+        starting.store(cpu, Ordering::SeqCst);
+
+        apic::send_init(id);
+        pit::delay(10000);
+        for _ in 0..2u8 {
+            if starting.load(Ordering::SeqCst) == 0 {
+                break;
+            }
+            apic::send_startup(id, (TRAMPOLINE >> 12) as u8);
+            pit::delay(200);
+        }
+
+        // give it up to 100 ms to take its index
+        let mut waited = 0u32;
+        while starting.load(Ordering::SeqCst) != 0 && waited < 100 {
+            pit::delay(1000);
+            waited += 1;
+        }
+        if starting.swap(0, Ordering::SeqCst) != 0 {
+            // Park it, so that it can't come up later with the index and
+            // stack of the next processor.
+            apic::send_init(id);
+            println!("smp: processor with APIC ID {} did not start", id);
+            continue;
+        }
+        // it owns its index and stack now, and will soon be online
+        while online.load(Ordering::SeqCst) == cpu {
+            super::relax();
+        }
+    }
+
+    let cpus = online.load(Ordering::SeqCst);
+    for cpu in 1..cpus {
+        sched::spawn(cpu, report);
+    }
+    let mut waited = 0u32;
+    while worked.load(Ordering::SeqCst) < cpus - 1 && waited < 100 {
+        pit::delay(1000);
+        waited += 1;
+    }
+    println!("smp: {} processors online, {} running tasks",
+             cpus, worked.load(Ordering::SeqCst) + 1);
+    cpus
+}
+
+fn report() {
+    worked.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Entered from the trampoline in protected mode, on the stack given in
+/// `ap_stack`.
+#[no_mangle]
+#[no_stack_check]
+pub unsafe extern "C" fn ap_main() -> ! {
+    let cpu = starting.swap(0, Ordering::SeqCst);
+    if cpu == 0 {
+        // too late: the bootstrap processor gave up on this one and is
+        // parking it
+        super::halt();
+    }
+    // left alone until this processor is online
+    let stack = next_stack;
+    super::init_ap(cpu, stack, stack + STACK_SIZE);
+    apic::enable();
+    apic::start_timer(TIMER_VECTOR, TIMER_HZ);
+    online.fetch_add(1, Ordering::SeqCst);
+    sched::run()
+}
+
+/// The local timer wakes idle processors to check their run queue.
+fn tick() {
+    LocalSegment::get().ticks += 1;
+    apic::eoi();
+}
+
+#[no_stack_check]
+#[inline(never)]
+unsafe fn timer_handler() -> unsafe extern "C" fn() {
+    asm!("jmp skip_timer_handler
+      timer_handler_asm:"
+        :::: "volatile", "intel");
+
+    Context::save();
+    tick();
+    Context::restore();
+
+    asm!("skip_timer_handler:"
+        :::: "volatile", "intel");
+
+    extern { fn timer_handler_asm(); }
+    timer_handler_asm
+}
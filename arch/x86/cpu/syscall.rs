@@ -0,0 +1,33 @@
+//! The `int 0x80` system call gate. The number is passed in eax and the
+//! arguments in ebx, ecx and edx, like on Linux.
+
+use cpu::{Context, fpu};
+use cpu::interrupt::Table;
+use kernel::syscall;
+
+pub const VECTOR: u8 = 0x80;
+
+/// Processes run in ring 0 for now, so the gate keeps DPL 0.
+pub unsafe fn install(table: &mut Table) {
+    table.set_vector(VECTOR, false, syscall_handler());
+}
+
+#[no_stack_check]
+#[inline(never)]
+unsafe fn syscall_handler() -> unsafe extern "C" fn() {
+    asm!("jmp skip_syscall_handler
+      syscall_handler_asm:"
+        :::: "volatile", "intel");
+
+    let ctx = Context::save();
+    let task = fpu::enter_kernel();
+    ctx.eax = syscall::dispatch(ctx.eax, [ctx.ebx, ctx.ecx, ctx.edx]);
+    fpu::leave_kernel(task);
+    Context::restore();
+
+    asm!("skip_syscall_handler:"
+        :::: "volatile", "intel");
+
+    extern { fn syscall_handler_asm(); }
+    syscall_handler_asm
+}
@@ -0,0 +1,348 @@
+//! ACPI tables. The firmware describes processors, interrupt controllers,
+//! power management and timers in tables reached from the RSDP.
+//!
+//! 1. [Advanced Configuration and Power Interface Specification 5.0][[1]]
+//! [1]: http://www.acpi.info/DOWNLOADS/ACPIspec50.pdf
+
+use core::mem::size_of;
+use core::prelude::*;
+
+use cpu::mmu;
+use cpu::mmu::RW;
+use super::apic::{IoApicEntry, Override, Topology};
+use super::mp::checksum;
+
+#[repr(packed)]
+struct Rsdp {
+    signature: [u8; ..8],   // "RSD PTR "
+    checksum: u8,
+    oem: [u8; ..6],
+    revision: u8,           // 0 for ACPI 1.0, 2 since ACPI 2.0
+    rsdt: u32,
+    // since ACPI 2.0
+    length: u32,
+    xsdt: u64,
+    ext_checksum: u8,
+    reserved: [u8; ..3]
+}
+
+/// The header common to all system description tables.
+#[repr(packed)]
+pub struct SdtHeader {
+    pub signature: [u8; ..4],
+    pub length: u32,
+    pub revision: u8,
+    pub checksum: u8,
+    pub oem: [u8; ..6],
+    pub oem_table: [u8; ..8],
+    pub oem_revision: u32,
+    pub creator: u32,
+    pub creator_revision: u32
+}
+
+/// The location of a register in an address space.
+#[repr(packed)]
+#[derive(Copy)]
+pub struct GenericAddress {
+    pub space: u8,          // 0: memory, 1: I/O port
+    pub bit_width: u8,
+    pub bit_offset: u8,
+    pub access_size: u8,
+    pub address: u64
+}
+
+pub const SPACE_MEMORY: u8 = 0;
+pub const SPACE_IO: u8 = 1;
+
+/// Fixed ACPI Description Table, "FACP". Fields past `flags` are only
+/// present when the table is long enough; see `Fadt::reset_register`.
+#[repr(packed)]
+pub struct Fadt {
+    pub header: SdtHeader,
+    pub firmware_ctrl: u32,
+    pub dsdt: u32,
+    reserved0: u8,
+    pub preferred_pm_profile: u8,
+    pub sci_int: u16,
+    pub smi_cmd: u32,
+    pub acpi_enable: u8,
+    pub acpi_disable: u8,
+    pub s4bios_req: u8,
+    pub pstate_cnt: u8,
+    pub pm1a_evt_blk: u32,
+    pub pm1b_evt_blk: u32,
+    pub pm1a_cnt_blk: u32,
+    pub pm1b_cnt_blk: u32,
+    pub pm2_cnt_blk: u32,
+    pub pm_tmr_blk: u32,
+    pub gpe0_blk: u32,
+    pub gpe1_blk: u32,
+    pub pm1_evt_len: u8,
+    pub pm1_cnt_len: u8,
+    pub pm2_cnt_len: u8,
+    pub pm_tmr_len: u8,
+    pub gpe0_blk_len: u8,
+    pub gpe1_blk_len: u8,
+    pub gpe1_base: u8,
+    pub cst_cnt: u8,
+    pub p_lvl2_lat: u16,
+    pub p_lvl3_lat: u16,
+    pub flush_size: u16,
+    pub flush_stride: u16,
+    pub duty_offset: u8,
+    pub duty_width: u8,
+    pub day_alrm: u8,
+    pub mon_alrm: u8,
+    pub century: u8,
+    pub iapc_boot_arch: u16,
+    reserved1: u8,
+    pub flags: u32,
+    reset_reg: GenericAddress,
+    reset_value: u8
+}
+
+/// The reset register is supported.
+const RESET_REG_SUP: u32 = 1 << 10;
+
+impl Fadt {
+    /// Returns the register and the value to write to it to reset the
+    /// machine, since ACPI 2.0.
+    pub fn reset_register(&self) -> Option<(GenericAddress, u8)> {
+        if self.header.length as usize >= size_of::<Fadt>() && self.flags & RESET_REG_SUP != 0 {
+            Some((self.reset_reg, self.reset_value))
+        } else {
+            None
+        }
+    }
+
+    /// The Differentiated System Description Table, holding AML code.
+    pub fn dsdt(&self) -> Option<&'static SdtHeader> {
+        map_table(self.dsdt as usize)
+    }
+}
+
+/// High Precision Event Timer table, "HPET".
+#[repr(packed)]
+pub struct Hpet {
+    pub header: SdtHeader,
+    pub event_timer_block_id: u32,
+    pub address: GenericAddress,
+    pub number: u8,
+    pub minimum_tick: u16,
+    pub page_protection: u8
+}
+
+#[repr(packed)]
+struct MadtHeader {
+    header: SdtHeader,
+    lapic_addr: u32,
+    flags: u32
+}
+
+#[repr(packed)]
+struct MadtLapic {
+    kind: u8,
+    length: u8,
+    acpi_id: u8,
+    apic_id: u8,
+    flags: u32              // bit 0: enabled
+}
+
+#[repr(packed)]
+struct MadtIoApic {
+    kind: u8,
+    length: u8,
+    id: u8,
+    reserved: u8,
+    addr: u32,
+    gsi_base: u32
+}
+
+#[repr(packed)]
+struct MadtOverride {
+    kind: u8,
+    length: u8,
+    bus: u8,
+    source: u8,
+    gsi: u32,
+    flags: u16
+}
+
+#[repr(packed)]
+struct MadtLapicAddress {
+    kind: u8,
+    length: u8,
+    reserved: u16,
+    addr: u64
+}
+
+const MADT_LAPIC: u8 = 0;
+const MADT_IO_APIC: u8 = 1;
+const MADT_OVERRIDE: u8 = 2;
+const MADT_LAPIC_ADDRESS: u8 = 5;
+
+/// The root table: the RSDT holds 32-bit pointers, the XSDT 64-bit ones.
+struct Root {
+    table: &'static SdtHeader,
+    entry_size: usize
+}
+
+static mut root: Option<Root> = None;
+
+/// Maps a table and validates its checksum.
+fn map_table(addr: usize) -> Option<&'static SdtHeader> {
+    if addr == 0 {
+        return None;
+    }
+    unsafe {
+        match mmu::map_physical(addr, size_of::<SdtHeader>(), RW) {
+            Ok(_) => {}
+            Err(_) => return None
+        }
+        let header = &*(addr as *const SdtHeader);
+        match mmu::map_physical(addr, header.length as usize, RW) {
+            Ok(_) if checksum(addr, header.length as usize) => Some(header),
+            _ => None
+        }
+    }
+}
+
+/// Scans `[start, start+len)` on 16-byte boundaries for the RSDP.
+fn scan(start: usize, len: usize) -> Option<&'static Rsdp> {
+    let mut addr = start & !0xF;
+    while addr + 20 <= start + len {
+        let rsdp = unsafe { &*(addr as *const Rsdp) };
+        if &rsdp.signature == b"RSD PTR " && checksum(addr, 20) {
+            if rsdp.revision < 2 || checksum(addr, rsdp.length as usize) {
+                return Some(rsdp);
+            }
+        }
+        addr += 16;
+    }
+    None
+}
+
+/// Locates the RSDP in the first KiB of the EBDA or in the BIOS area, and
+/// the root table it points to. Returns `false` without ACPI.
+pub fn init() -> bool {
+    let ebda = unsafe { (*(0x40E as *const u16) as usize) << 4 };
+    let rsdp = match if ebda != 0 { scan(ebda, 1024) } else { None } {
+        None => scan(0xE0000, 0x20000),
+        some => some
+    };
+    let rsdp = match rsdp {
+        Some(rsdp) => rsdp,
+        None => return false
+    };
+
+    // 32-bit paging can't reach an XSDT above 4 GiB
+    let table = if rsdp.revision >= 2 && rsdp.xsdt != 0 && rsdp.xsdt >> 32 == 0 {
+        map_table(rsdp.xsdt as usize).map(|t| Root { table: t, entry_size: 8 })
+    } else {
+        None
+    };
+    let table = match table {
+        None => map_table(rsdp.rsdt as usize).map(|t| Root { table: t, entry_size: 4 }),
+        some => some
+    };
+
+    unsafe {
+        root = table;
+        root.is_some()
+    }
+}
+
+/// Finds the first table with the given signature, such as `b"APIC"`.
+pub fn find(signature: &[u8; ..4]) -> Option<&'static SdtHeader> {
+    let root_table = match unsafe { root.as_ref() } {
+        Some(r) => r,
+        None => return None
+    };
+    let start = root_table.table as *const SdtHeader as usize + size_of::<SdtHeader>();
+    let count = (root_table.table.length as usize - size_of::<SdtHeader>()) / root_table.entry_size;
+
+    for i in 0..count {
+        let entry = start + i * root_table.entry_size;
+        let addr = unsafe {
+            if root_table.entry_size == 8 {
+                let addr = *(entry as *const u64);
+                if addr >> 32 != 0 {
+                    continue;
+                }
+                addr as usize
+            } else {
+                *(entry as *const u32) as usize
+            }
+        };
+        match map_table(addr) {
+            Some(table) if &table.signature == signature => return Some(table),
+            _ => {}
+        }
+    }
+    None
+}
+
+pub fn fadt() -> Option<&'static Fadt> {
+    find(b"FACP").map(|t| unsafe { &*(t as *const SdtHeader as *const Fadt) })
+}
+
+pub fn hpet() -> Option<&'static Hpet> {
+    find(b"HPET").map(|t| unsafe { &*(t as *const SdtHeader as *const Hpet) })
+}
+
+/// Reads the processors, IO-APICs and ISA overrides from the MADT, "APIC".
+pub fn madt() -> Option<Topology> {
+    let table = match find(b"APIC") {
+        Some(table) => table,
+        None => return None
+    };
+    let madt = unsafe { &*(table as *const SdtHeader as *const MadtHeader) };
+    let mut topology = Topology::new(madt.lapic_addr as usize);
+
+    let start = madt as *const MadtHeader as usize;
+    let end = start + table.length as usize;
+    let mut entry = start + size_of::<MadtHeader>();
+
+    while entry + 2 <= end {
+        let (kind, length) = unsafe { (*(entry as *const u8), *((entry + 1) as *const u8)) };
+        if length < 2 {
+            break;
+        }
+        match kind {
+            MADT_LAPIC => {
+                let cpu = unsafe { &*(entry as *const MadtLapic) };
+                if cpu.flags & 1 != 0 {
+                    topology.cpus.push(cpu.apic_id);
+                }
+            }
+            MADT_IO_APIC => {
+                let ioapic = unsafe { &*(entry as *const MadtIoApic) };
+                topology.ioapics.push(IoApicEntry {
+                    id: ioapic.id,
+                    addr: ioapic.addr as usize,
+                    gsi_base: ioapic.gsi_base
+                });
+            }
+            MADT_OVERRIDE => {
+                let o = unsafe { &*(entry as *const MadtOverride) };
+                // bus 0 is ISA
+                if o.bus == 0 {
+                    topology.overrides.push(Override {
+                        isa_irq: o.source,
+                        gsi: o.gsi,
+                        flags: o.flags
+                    });
+                }
+            }
+            MADT_LAPIC_ADDRESS => {
+                let a = unsafe { &*(entry as *const MadtLapicAddress) };
+                if a.addr >> 32 == 0 {
+                    topology.lapic_addr = a.addr as usize;
+                }
+            }
+            _ => {}
+        }
+        entry += length as usize;
+    }
+    Some(topology)
+}
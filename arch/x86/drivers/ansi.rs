@@ -0,0 +1,116 @@
+//! A parser for the ANSI/VT100 escape sequences the console understands:
+//! control sequences (CSI) of the form `ESC [ params final`.
+
+const MAX_PARAMS: usize = 8;
+
+/// A complete control sequence.
+#[derive(Copy)]
+pub struct Csi {
+    pub command: u8,
+    params: [u16; ..MAX_PARAMS],
+    count: usize
+}
+
+impl Csi {
+    /// The `i`th parameter, or `default` when it is missing or 0.
+    pub fn param(&self, i: usize, default: u16) -> u16 {
+        if i < self.count && self.params[i] != 0 { self.params[i] } else { default }
+    }
+
+    pub fn count(&self) -> usize {
+        self.count
+    }
+}
+
+#[derive(Copy)]
+pub enum Action {
+    /// A byte to display.
+    Print(u8),
+    /// A C0 control character, such as `\n`.
+    Control(u8),
+    Csi(Csi),
+    /// `ESC c`, resets the terminal.
+    Reset,
+    /// Part of a sequence.
+    Pending
+}
+
+#[derive(Copy, PartialEq)]
+enum State {
+    Ground,
+    Escape,
+    Csi
+}
+
+#[derive(Copy)]
+pub struct Parser {
+    state: State,
+    csi: Csi
+}
+
+impl Parser {
+    pub fn new() -> Parser {
+        Parser {
+            state: State::Ground,
+            csi: Csi { command: 0, params: [0; MAX_PARAMS], count: 0 }
+        }
+    }
+
+    pub fn feed(&mut self, b: u8) -> Action {
+        match (self.state, b) {
+            // CAN and SUB abort a sequence
+            (_, 0x18) | (_, 0x1A) => {
+                self.state = State::Ground;
+                Action::Pending
+            }
+            (_, 0x1B) => {
+                self.state = State::Escape;
+                Action::Pending
+            }
+            (State::Ground, b) if b < 0x20 || b == 0x7F => Action::Control(b),
+            (State::Ground, b) => Action::Print(b),
+
+            (State::Escape, b'[') => {
+                self.state = State::Csi;
+                self.csi.count = 0;
+                self.csi.params = [0; MAX_PARAMS];
+                Action::Pending
+            }
+            (State::Escape, b'c') => {
+                self.state = State::Ground;
+                Action::Reset
+            }
+            (State::Escape, _) => {
+                self.state = State::Ground;
+                Action::Pending
+            }
+
+            (State::Csi, b @ b'0'...b'9') => {
+                if self.csi.count == 0 {
+                    self.csi.count = 1;
+                }
+                let p = &mut self.csi.params[self.csi.count - 1];
+                if *p < 6553 {
+                    *p = *p * 10 + (b - b'0') as u16;
+                }
+                Action::Pending
+            }
+            (State::Csi, b';') => {
+                if self.csi.count == 0 {
+                    self.csi.count = 1;
+                }
+                if self.csi.count < MAX_PARAMS {
+                    self.csi.count += 1;
+                }
+                Action::Pending
+            }
+            (State::Csi, b) if b >= 0x40 && b <= 0x7E => {
+                self.state = State::Ground;
+                self.csi.command = b;
+                Action::Csi(self.csi)
+            }
+            // intermediate bytes and private markers such as `?` are ignored
+            (State::Csi, _) => Action::Pending
+        }
+    }
+}
@@ -0,0 +1,166 @@
+//! Local APIC. Each processor has one, to receive interrupts routed by the
+//! IO-APIC, to send interrupts to other processors and to run a timer.
+
+use core::intrinsics::{volatile_load, volatile_store};
+use core::prelude::*;
+
+use cpu::{mmu, rdmsr, wrmsr};
+use kernel::collections::Vec;
+use kernel::mm::OutOfMemory;
+use super::pit;
+
+/// An IO-APIC described by the firmware.
+pub struct IoApicEntry {
+    pub id: u8,
+    pub addr: usize,
+    /// The first global system interrupt handled by this IO-APIC.
+    pub gsi_base: u32
+}
+
+/// An ISA interrupt that the firmware wired differently than the 8259.
+pub struct Override {
+    pub isa_irq: u8,
+    pub gsi: u32,
+    /// Polarity and trigger mode, encoded as in the MP and ACPI tables.
+    pub flags: u16
+}
+
+/// Processors and interrupt controllers found in the firmware tables.
+pub struct Topology {
+    pub lapic_addr: usize,
+    /// Local APIC IDs of enabled processors.
+    pub cpus: Vec<u8>,
+    pub ioapics: Vec<IoApicEntry>,
+    pub overrides: Vec<Override>
+}
+
+impl Topology {
+    pub fn new(lapic_addr: usize) -> Topology {
+        Topology {
+            lapic_addr: lapic_addr,
+            cpus: Vec::new(),
+            ioapics: Vec::new(),
+            overrides: Vec::new()
+        }
+    }
+}
+
+pub const SPURIOUS_VECTOR: u8 = 0xFF;
+
+const IA32_APIC_BASE: u32 = 0x1B;
+const APIC_GLOBAL_ENABLE: u64 = 1 << 11;
+
+const ID: usize = 0x20;
+const TPR: usize = 0x80;
+const EOI: usize = 0xB0;
+const SVR: usize = 0xF0;
+const ICR_LOW: usize = 0x300;
+const ICR_HIGH: usize = 0x310;
+const LVT_TIMER: usize = 0x320;
+const LVT_LINT0: usize = 0x350;
+const LVT_LINT1: usize = 0x360;
+const LVT_ERROR: usize = 0x370;
+const TIMER_INITIAL: usize = 0x380;
+const TIMER_CURRENT: usize = 0x390;
+const TIMER_DIVIDE: usize = 0x3E0;
+
+const SVR_ENABLE: u32 = 1 << 8;
+const LVT_MASKED: u32 = 1 << 16;
+const TIMER_PERIODIC: u32 = 1 << 17;
+const DIVIDE_BY_16: u32 = 0b11;
+
+const ICR_INIT: u32 = 0b101 << 8;
+const ICR_STARTUP: u32 = 0b110 << 8;
+const ICR_ASSERT: u32 = 1 << 14;
+const ICR_PENDING: u32 = 1 << 12;
+
+static mut base: *mut u32 = 0 as *mut u32;
+/// Timer ticks per millisecond, with the divider set to 16.
+static mut ticks_per_ms: u32 = 0;
+
+#[inline]
+fn read(reg: usize) -> u32 {
+    unsafe { volatile_load(base.offset((reg / 4) as isize) as *const u32) }
+}
+
+#[inline]
+fn write(reg: usize, val: u32) {
+    unsafe { volatile_store(base.offset((reg / 4) as isize), val) }
+}
+
+/// Maps and enables the local APIC of the bootstrap processor.
+pub unsafe fn init(lapic_addr: usize) -> Result<(), OutOfMemory> {
+    base = try!(mmu::map_device(lapic_addr, 0x400)) as *mut u32;
+    enable();
+    Ok(())
+}
+
+/// Enables the local APIC of the current processor. All local APICs sit at
+/// the same address.
+pub unsafe fn enable() {
+    wrmsr(IA32_APIC_BASE, rdmsr(IA32_APIC_BASE) | APIC_GLOBAL_ENABLE);
+
+    // accept all interrupts
+    write(TPR, 0);
+    write(LVT_TIMER, LVT_MASKED);
+    write(LVT_LINT0, LVT_MASKED);
+    write(LVT_LINT1, LVT_MASKED);
+    write(LVT_ERROR, LVT_MASKED);
+    write(SVR, SVR_ENABLE | SPURIOUS_VECTOR as u32);
+}
+
+pub fn id() -> u8 {
+    (read(ID) >> 24) as u8
+}
+
+pub fn eoi() {
+    write(EOI, 0);
+}
+
+/// Counts timer ticks during 10 ms, once. Processors share the result, so
+/// that only one of them uses the PIT.
+pub fn calibrate_timer() {
+    unsafe {
+        if ticks_per_ms != 0 {
+            return;
+        }
+        write(TIMER_DIVIDE, DIVIDE_BY_16);
+        write(TIMER_INITIAL, !0);
+        pit::delay(10000);
+
+        let elapsed = !0 - read(TIMER_CURRENT);
+        write(TIMER_INITIAL, 0);
+        ticks_per_ms = elapsed / 10;
+    }
+}
+
+fn send_ipi(dest: u8, command: u32) {
+    write(ICR_HIGH, (dest as u32) << 24);
+    write(ICR_LOW, command);
+    while read(ICR_LOW) & ICR_PENDING != 0 {}
+}
+
+/// Sends an INIT IPI, which resets a processor to wait for a startup IPI.
+pub fn send_init(dest: u8) {
+    send_ipi(dest, ICR_INIT | ICR_ASSERT);
+}
+
+/// Sends a startup IPI. The processor starts in real mode at `page << 12`.
+pub fn send_startup(dest: u8, page: u8) {
+    send_ipi(dest, ICR_STARTUP | ICR_ASSERT | page as u32);
+}
+
+/// Starts the periodic timer, firing `vector` at `hz` times per second.
+pub fn start_timer(vector: u8, hz: u32) {
+    calibrate_timer();
+    unsafe {
+        write(TIMER_DIVIDE, DIVIDE_BY_16);
+        write(LVT_TIMER, TIMER_PERIODIC | vector as u32);
+        write(TIMER_INITIAL, ticks_per_ms * 1000 / hz);
+    }
+}
+
+pub fn stop_timer() {
+    write(LVT_TIMER, LVT_MASKED);
+    write(TIMER_INITIAL, 0);
+}
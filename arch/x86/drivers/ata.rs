@@ -0,0 +1,407 @@
+//! ATA disks on the two IDE channels, with PIO transfers. Commands complete
+//! by interrupt; while interrupts are off, the driver polls instead.
+//!
+//! The channels are found through the PCI IDE controller, in legacy or
+//! native mode, or at the legacy ports when there is no controller on PCI.
+
+use core::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use core::cmp::min;
+use core::prelude::*;
+use core::ptr;
+use core::str;
+
+use cpu;
+use cpu::{io, irq};
+use kernel::block;
+use kernel::block::{BlockDevice, Error};
+use kernel::heap;
+use kernel::time::Timeout;
+use super::pci;
+
+// task file registers, from the channel's base port
+const DATA: u16 = 0;
+const ERROR: u16 = 1;
+const SECTOR_COUNT: u16 = 2;
+const LBA_LOW: u16 = 3;
+const LBA_MID: u16 = 4;
+const LBA_HIGH: u16 = 5;
+const DRIVE: u16 = 6;
+const STATUS: u16 = 7;
+const COMMAND: u16 = 7;
+
+// device control register, at the control port
+const NO_INTERRUPT: u8 = 1 << 1;
+
+const BUSY: u8 = 1 << 7;
+const FAULT: u8 = 1 << 5;
+const DATA_REQUEST: u8 = 1 << 3;
+const ERR: u8 = 1 << 0;
+
+const IDENTIFY: u8 = 0xEC;
+const READ_SECTORS: u8 = 0x20;
+const READ_SECTORS_EXT: u8 = 0x24;
+const WRITE_SECTORS: u8 = 0x30;
+const WRITE_SECTORS_EXT: u8 = 0x34;
+const FLUSH_CACHE: u8 = 0xE7;
+const FLUSH_CACHE_EXT: u8 = 0xEA;
+
+const SECTOR_SIZE: usize = 512;
+/// Sectors reachable with 28-bit commands.
+const LBA28_LIMIT: u64 = 1 << 28;
+/// Sectors per command; a count of 0 means 256.
+const MAX_SECTORS: u64 = 256;
+/// How long a command may take to raise its interrupt.
+const COMMAND_TIMEOUT_MS: u32 = 5000;
+
+// IDENTIFY words
+const ID_MODEL: usize = 27;
+const ID_MODEL_WORDS: usize = 20;
+const ID_SECTORS: usize = 60;
+const ID_COMMAND_SETS: usize = 83;
+const ID_SECTORS_48: usize = 100;
+const SUPPORTS_LBA48: u16 = 1 << 10;
+
+#[derive(Copy)]
+struct Channel {
+    base: u16,
+    control: u16,
+    irq: u8
+}
+
+const LEGACY: [Channel; ..2] = [
+    Channel { base: 0x1F0, control: 0x3F6, irq: 14 },
+    Channel { base: 0x170, control: 0x376, irq: 15 }
+];
+
+static mut channels: [Channel; ..2] = LEGACY;
+/// Set by the interrupt handler of each channel.
+static interrupted: [AtomicBool; ..2] = [ATOMIC_BOOL_INIT, ATOMIC_BOOL_INIT];
+static mut controller_found: bool = false;
+
+static NAMES: [&'static str; ..4] = ["ata0", "ata1", "ata2", "ata3"];
+
+pub static DRIVER: pci::Driver = pci::Driver {
+    name: "ata",
+    matches: &[pci::Match::Class(0x01, 0x01)],
+    probe: probe
+};
+
+impl Channel {
+    fn status(&self) -> u8 {
+        io::inb(self.base + STATUS)
+    }
+
+    /// Reading the alternate status four times takes the 400 ns a drive
+    /// needs to show a valid status.
+    fn delay(&self) {
+        for _ in 0..4 {
+            io::inb(self.control);
+        }
+    }
+
+    fn wait_not_busy(&self) -> Result<u8, Error> {
+        for _ in 0..io::WAIT_LOOPS * 10 {
+            let status = self.status();
+            if status & BUSY == 0 {
+                return Ok(status);
+            }
+            io::inb(0x80);
+        }
+        Err(Error::Timeout)
+    }
+
+    fn wait_data(&self) -> Result<(), Error> {
+        let status = try!(self.wait_not_busy());
+        if status & (ERR | FAULT) != 0 || status & DATA_REQUEST == 0 {
+            return Err(Error::Io);
+        }
+        Ok(())
+    }
+
+    fn select(&self, slave: bool, bits: u8) {
+        io::out(self.base + DRIVE, bits | if slave { 1 << 4 } else { 0 });
+        self.delay();
+    }
+
+    fn command(&self, number: usize, cmd: u8) {
+        interrupted[number].store(false, Ordering::SeqCst);
+        io::out(self.base + COMMAND, cmd);
+    }
+
+    /// Waits for the interrupt that ends a command or a sector, and checks
+    /// the result.
+    fn wait_interrupt(&self, number: usize) -> Result<(), Error> {
+        if cpu::interrupts_enabled() {
+            let timeout = Timeout::ms(COMMAND_TIMEOUT_MS);
+            while !interrupted[number].swap(false, Ordering::SeqCst) {
+                if timeout.expired() {
+                    return Err(Error::Timeout);
+                }
+                cpu::relax();
+            }
+        }
+        // without interrupts, this polls; right after the command, the
+        // status may not show BSY yet
+        self.delay();
+        let status = try!(self.wait_not_busy());
+        if status & (ERR | FAULT) != 0 {
+            return Err(Error::Io);
+        }
+        Ok(())
+    }
+
+    fn read_sector(&self, buf: *mut u8) {
+        let words = buf as *mut u16;
+        for i in 0..SECTOR_SIZE / 2 {
+            unsafe { *words.offset(i as isize) = io::inw(self.base + DATA); }
+        }
+    }
+
+    fn write_sector(&self, buf: *const u8) {
+        let words = buf as *const u16;
+        for i in 0..SECTOR_SIZE / 2 {
+            io::outw(self.base + DATA, unsafe { *words.offset(i as isize) });
+        }
+    }
+}
+
+pub struct Drive {
+    /// Channel number, 0 or 1.
+    channel: usize,
+    slave: bool,
+    lba48: bool,
+    sectors: u64,
+    model: [u8; ..ID_MODEL_WORDS * 2],
+    model_len: usize
+}
+
+impl Drive {
+    fn channel(&self) -> Channel {
+        unsafe { channels[self.channel] }
+    }
+
+    pub fn model(&self) -> &str {
+        str::from_utf8(&self.model[..self.model_len]).unwrap_or("?")
+    }
+
+    /// Sends a read or write command for `count` sectors at `lba`.
+    fn start(&self, lba: u64, count: u64, cmd28: u8, cmd48: u8) -> Result<(), Error> {
+        let c = self.channel();
+        try!(c.wait_not_busy());
+        if lba + count > LBA28_LIMIT {
+            c.select(self.slave, 0x40);
+            // high bytes first, then the low ones
+            io::out(c.base + SECTOR_COUNT, (count >> 8) as u8);
+            io::out(c.base + LBA_LOW, (lba >> 24) as u8);
+            io::out(c.base + LBA_MID, (lba >> 32) as u8);
+            io::out(c.base + LBA_HIGH, (lba >> 40) as u8);
+            self.set_low(&c, lba, count);
+            c.command(self.channel, cmd48);
+        } else {
+            c.select(self.slave, 0xE0 | (lba >> 24) as u8 & 0xF);
+            self.set_low(&c, lba, count);
+            c.command(self.channel, cmd28);
+        }
+        Ok(())
+    }
+
+    fn set_low(&self, c: &Channel, lba: u64, count: u64) {
+        io::out(c.base + SECTOR_COUNT, count as u8);
+        io::out(c.base + LBA_LOW, lba as u8);
+        io::out(c.base + LBA_MID, (lba >> 8) as u8);
+        io::out(c.base + LBA_HIGH, (lba >> 16) as u8);
+    }
+
+    fn flush(&self) -> Result<(), Error> {
+        let c = self.channel();
+        try!(c.wait_not_busy());
+        c.select(self.slave, 0xE0);
+        c.command(self.channel, if self.lba48 { FLUSH_CACHE_EXT } else { FLUSH_CACHE });
+        c.wait_interrupt(self.channel)
+    }
+}
+
+impl BlockDevice for Drive {
+    fn name(&self) -> &str {
+        NAMES[self.channel * 2 + self.slave as usize]
+    }
+
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let count = try!(self.check(lba, buf.len()));
+        let c = self.channel();
+        let mut done = 0;
+        while done < count {
+            let n = min(count - done, MAX_SECTORS);
+            try!(self.start(lba + done, n, READ_SECTORS, READ_SECTORS_EXT));
+            for _ in 0..n {
+                try!(c.wait_interrupt(self.channel));
+                c.read_sector(buf[done as usize * SECTOR_SIZE..].as_mut_ptr());
+                done += 1;
+            }
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u64, buf: &[u8]) -> Result<(), Error> {
+        let count = try!(self.check(lba, buf.len()));
+        let c = self.channel();
+        let mut done = 0;
+        while done < count {
+            let n = min(count - done, MAX_SECTORS);
+            try!(self.start(lba + done, n, WRITE_SECTORS, WRITE_SECTORS_EXT));
+            // the first sector is asked for without an interrupt
+            try!(c.wait_data());
+            for _ in 0..n {
+                c.write_sector(buf[done as usize * SECTOR_SIZE..].as_ptr());
+                try!(c.wait_interrupt(self.channel));
+                done += 1;
+            }
+        }
+        self.flush()
+    }
+}
+
+/// Runs IDENTIFY on a drive. Returns `None` when there is none, or when it's
+/// a packet device such as a CD drive.
+fn identify(number: usize, slave: bool) -> Option<Drive> {
+    let c = unsafe { channels[number] };
+    c.select(slave, 0xA0);
+    io::out(c.base + SECTOR_COUNT, 0u8);
+    io::out(c.base + LBA_LOW, 0u8);
+    io::out(c.base + LBA_MID, 0u8);
+    io::out(c.base + LBA_HIGH, 0u8);
+    io::out(c.base + COMMAND, IDENTIFY);
+    c.delay();
+    // 0 means no drive; a floating bus reads as all ones
+    match c.status() {
+        0 | 0xFF => return None,
+        _ => {}
+    }
+    if c.wait_not_busy().is_err() {
+        return None;
+    }
+    // packet devices set a signature here and fail the command
+    if io::inb(c.base + LBA_MID) != 0 || io::inb(c.base + LBA_HIGH) != 0 {
+        return None;
+    }
+    if c.wait_data().is_err() {
+        io::inb(c.base + ERROR);
+        return None;
+    }
+
+    let mut id = [0u16; 256];
+    c.read_sector(id.as_mut_ptr() as *mut u8);
+
+    let lba48 = id[ID_COMMAND_SETS] & SUPPORTS_LBA48 != 0;
+    let sectors = if lba48 {
+        id[ID_SECTORS_48] as u64 | (id[ID_SECTORS_48 + 1] as u64) << 16
+            | (id[ID_SECTORS_48 + 2] as u64) << 32 | (id[ID_SECTORS_48 + 3] as u64) << 48
+    } else {
+        id[ID_SECTORS] as u64 | (id[ID_SECTORS + 1] as u64) << 16
+    };
+
+    let mut drive = Drive {
+        channel: number,
+        slave: slave,
+        lba48: lba48,
+        sectors: sectors,
+        model: [0; ID_MODEL_WORDS * 2],
+        model_len: 0
+    };
+    // each word holds two characters, the first in the high byte
+    for i in 0..ID_MODEL_WORDS {
+        drive.model[i * 2] = (id[ID_MODEL + i] >> 8) as u8;
+        drive.model[i * 2 + 1] = id[ID_MODEL + i] as u8;
+    }
+    drive.model_len = drive.model.len();
+    while drive.model_len > 0 && drive.model[drive.model_len - 1] == b' ' {
+        drive.model_len -= 1;
+    }
+    Some(drive)
+}
+
+fn interrupt(number: usize) {
+    // reading the status acknowledges the drive
+    let status = unsafe { channels[number].status() };
+    // A drive interrupts once it isn't busy, with DRQ when data is ready.
+    // While it is, the interrupt came from another device on a shared line.
+    if status & BUSY == 0 {
+        interrupted[number].store(true, Ordering::SeqCst);
+    }
+}
+
+fn primary_interrupt() {
+    interrupt(0);
+}
+
+fn secondary_interrupt() {
+    interrupt(1);
+}
+
+/// Finds the drives on a channel, registers them as block devices and
+/// turns on the channel's interrupt.
+fn init_channel(number: usize, channel: Channel) {
+    unsafe {
+        channels[number] = channel;
+    }
+    io::out(channel.control, NO_INTERRUPT);
+    let mut found = false;
+    for &slave in [false, true].iter() {
+        match identify(number, slave) {
+            Some(drive) => unsafe {
+                println!("ata: {} {}, {} sectors{}", NAMES[number * 2 + slave as usize],
+                         drive.model(), drive.sectors, if drive.lba48 { ", LBA48" } else { "" });
+                let ptr = heap::alloc::<Drive>(1);
+                ptr::write(ptr, drive);
+                if block::register(&mut *ptr).is_none() {
+                    println!("ata: too many block devices");
+                }
+                found = true;
+            },
+            None => {}
+        }
+    }
+    if found {
+        let handler = if number == 0 { primary_interrupt as irq::Handler } else { secondary_interrupt };
+        irq::register(channel.irq, handler);
+        io::out(channel.control, 0u8);
+    }
+}
+
+/// Sets up the channels of a PCI IDE controller. Channels in native mode
+/// have their ports in BARs and share the controller's interrupt.
+fn probe(device: &pci::Device) -> bool {
+    unsafe {
+        controller_found = true;
+    }
+    device.enable(pci::IO_SPACE);
+    for number in 0..2 {
+        let native = device.prog_if & (1 << (number * 2)) != 0;
+        let channel = match (native, device.io_bar(number * 2), device.io_bar(number * 2 + 1), device.irq) {
+            (false, _, _, _) => LEGACY[number],
+            (true, Some(base), Some(control), Some(irq)) => Channel { base: base, control: control + 2, irq: irq },
+            _ => continue
+        };
+        init_channel(number, channel);
+    }
+    true
+}
+
+/// Registers the PCI driver, and probes the legacy ports if there is no IDE
+/// controller on PCI.
+pub fn init() {
+    pci::register(&DRIVER);
+    if unsafe { !controller_found } {
+        for number in 0..2 {
+            init_channel(number, LEGACY[number]);
+        }
+    }
+}
@@ -0,0 +1,83 @@
+//! Bochs Graphics Adapter, the VBE display of Bochs and QEMU (`-vga std`).
+//! Modes are set through its DISPI registers, and the framebuffer is at
+//! PCI BAR 0 of device 1234:1111.
+
+use core::prelude::*;
+
+use cpu::{io, mmu};
+use super::fb::Framebuffer;
+use super::pci;
+use super::pci::Bar;
+
+const INDEX: u16 = 0x1CE;
+const DATA: u16 = 0x1CF;
+
+const REG_ID: u16 = 0;
+const REG_XRES: u16 = 1;
+const REG_YRES: u16 = 2;
+const REG_BPP: u16 = 3;
+const REG_ENABLE: u16 = 4;
+const REG_VIRT_WIDTH: u16 = 6;
+
+const ID_MIN: u16 = 0xB0C0;
+const ID_MAX: u16 = 0xB0C5;
+
+const DISABLED: u16 = 0x00;
+const ENABLED: u16 = 0x01;
+const LFB_ENABLED: u16 = 0x40;
+
+const VENDOR: u16 = 0x1234;
+const DEVICE: u16 = 0x1111;
+/// Where QEMU puts the framebuffer when it can't be found on PCI.
+const DEFAULT_LFB: usize = 0xE0000000;
+
+fn write(reg: u16, val: u16) {
+    io::outw(INDEX, reg);
+    io::outw(DATA, val);
+}
+
+fn read(reg: u16) -> u16 {
+    io::outw(INDEX, reg);
+    io::inw(DATA)
+}
+
+pub fn present() -> bool {
+    let id = read(REG_ID);
+    id >= ID_MIN && id <= ID_MAX
+}
+
+/// BAR 0 of the adapter.
+fn lfb_address() -> usize {
+    match pci::find(VENDOR, DEVICE) {
+        Some(device) => match device.bars[0] {
+            Bar::Memory { addr, .. } => addr,
+            _ => DEFAULT_LFB
+        },
+        None => DEFAULT_LFB
+    }
+}
+
+/// Sets a 32-bit mode and maps its framebuffer.
+pub fn init(width: usize, height: usize) -> Option<Framebuffer> {
+    if !present() {
+        return None;
+    }
+    write(REG_ENABLE, DISABLED);
+    write(REG_XRES, width as u16);
+    write(REG_YRES, height as u16);
+    write(REG_BPP, 32);
+    write(REG_ENABLE, ENABLED | LFB_ENABLED);
+    if read(REG_XRES) as usize != width || read(REG_YRES) as usize != height {
+        write(REG_ENABLE, DISABLED);
+        return None;
+    }
+
+    let pitch = read(REG_VIRT_WIDTH) as usize;
+    match unsafe { mmu::map_device(lfb_address(), pitch * height * 4) } {
+        Ok(base) => Some(Framebuffer::new(base, width, height, pitch)),
+        Err(_) => {
+            write(REG_ENABLE, DISABLED);
+            None
+        }
+    }
+}
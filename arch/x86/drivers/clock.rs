@@ -0,0 +1,52 @@
+//! The monotonic clock: the TSC when the processor has one, calibrated
+//! against the PIT, or else the ticks of line 0 alone. Once the APIC is in
+//! use, the local APIC timer drives line 0 in place of the PIT.
+
+use cpu;
+use cpu::irq;
+use super::pit;
+
+/// TSC cycles per millisecond, or 0 without a TSC.
+static mut tsc_khz: u64 = 0;
+static mut tsc_base: u64 = 0;
+/// Nanoseconds between ticks of line 0.
+static mut tick_ns: u64 = 0;
+
+fn rdtsc() -> u64 {
+    let (lo, hi): (u32, u32);
+    unsafe {
+        asm!("rdtsc" : "={eax}"(lo), "={edx}"(hi) ::: "volatile");
+    }
+    (hi as u64) << 32 | lo as u64
+}
+
+pub fn init() {
+    pit::init();
+    let lapic = irq::use_lapic_timer(pit::HZ);
+    unsafe {
+        tick_ns = if lapic { 1000000000 / pit::HZ as u64 } else { pit::period_ns() };
+    }
+    if lapic {
+        println!("time: ticking from the local APIC timer");
+    }
+    if cpu::info().has(cpu::TSC) {
+        let start = rdtsc();
+        pit::delay(10000);
+        unsafe {
+            tsc_khz = (rdtsc() - start) / 10;
+            tsc_base = rdtsc();
+        }
+    }
+}
+
+/// Nanoseconds since `init`.
+pub fn nanos() -> u64 {
+    unsafe {
+        if tsc_khz == 0 {
+            return pit::ticks() * tick_ns;
+        }
+        let cycles = rdtsc() - tsc_base;
+        // split to keep `cycles * 10^6` from overflowing
+        cycles / tsc_khz * 1000000 + cycles % tsc_khz * 1000000 / tsc_khz
+    }
+}
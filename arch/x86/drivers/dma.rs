@@ -0,0 +1,58 @@
+//! The 8237 ISA DMA controller, for the 8-bit channels 0 to 3.
+//!
+//! A transfer can only reach the first 16 MiB of memory and can't cross a
+//! 64 KiB boundary.
+
+use core::prelude::*;
+
+use cpu::io;
+
+const MASK: u16 = 0x0A;
+const MODE: u16 = 0x0B;
+/// Any write selects the low byte of the address and count registers.
+const CLEAR_FLIP_FLOP: u16 = 0x0C;
+
+const MASK_ON: u8 = 1 << 2;
+
+static ADDRESS: [u16; ..4] = [0x00, 0x02, 0x04, 0x06];
+static COUNT: [u16; ..4] = [0x01, 0x03, 0x05, 0x07];
+static PAGE: [u16; ..4] = [0x87, 0x83, 0x81, 0x82];
+
+/// Which way the data goes, named from the device's side.
+#[derive(Copy, PartialEq)]
+pub enum Direction {
+    /// From the device to memory.
+    Read,
+    /// From memory to the device.
+    Write
+}
+
+const SINGLE: u8 = 1 << 6;
+const TO_MEMORY: u8 = 1 << 2;
+const FROM_MEMORY: u8 = 2 << 2;
+
+/// Programs `channel` for one transfer of `len` bytes at physical address
+/// `addr`, then unmasks it. Returns false if the buffer can't be reached.
+pub fn setup(channel: u8, addr: usize, len: usize, direction: Direction) -> bool {
+    let end = addr + len - 1;
+    if channel > 3 || len == 0 || end >= 1 << 24 || addr >> 16 != end >> 16 {
+        return false;
+    }
+    let ch = channel as usize;
+    let count = len - 1;
+    let kind = match direction {
+        Direction::Read => TO_MEMORY,
+        Direction::Write => FROM_MEMORY
+    };
+    io::out(MASK, MASK_ON | channel);
+    io::out(CLEAR_FLIP_FLOP, 0xFFu8);
+    io::out(ADDRESS[ch], addr as u8);
+    io::out(ADDRESS[ch], (addr >> 8) as u8);
+    io::out(PAGE[ch], (addr >> 16) as u8);
+    io::out(CLEAR_FLIP_FLOP, 0xFFu8);
+    io::out(COUNT[ch], count as u8);
+    io::out(COUNT[ch], (count >> 8) as u8);
+    io::out(MODE, SINGLE | kind | channel);
+    io::out(MASK, channel);
+    true
+}
@@ -0,0 +1,94 @@
+//! Drawing on a linear framebuffer of 32-bit pixels.
+
+use core::prelude::*;
+
+use platform::runtime::memcpy;
+
+/// A pixel colour, `0x00RRGGBB`.
+pub type Rgb = u32;
+
+#[inline]
+pub fn rgb(r: u8, g: u8, b: u8) -> Rgb {
+    (r as u32) << 16 | (g as u32) << 8 | b as u32
+}
+
+#[derive(Copy)]
+pub struct Framebuffer {
+    base: *mut Rgb,
+    pub width: usize,
+    pub height: usize,
+    /// Pixels from the start of one line to the next.
+    pitch: usize
+}
+
+impl Framebuffer {
+    pub fn new(base: *mut u8, width: usize, height: usize, pitch: usize) -> Framebuffer {
+        Framebuffer { base: base as *mut Rgb, width: width, height: height, pitch: pitch }
+    }
+
+    #[inline]
+    fn at(&self, x: usize, y: usize) -> *mut Rgb {
+        unsafe { self.base.offset((y * self.pitch + x) as isize) }
+    }
+
+    pub fn pixel(&self, x: usize, y: usize, color: Rgb) {
+        if x < self.width && y < self.height {
+            unsafe { *self.at(x, y) = color; }
+        }
+    }
+
+    /// Fills a rectangle, clipped to the screen.
+    pub fn fill_rect(&self, x: usize, y: usize, w: usize, h: usize, color: Rgb) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let w = if x + w > self.width { self.width - x } else { w };
+        let h = if y + h > self.height { self.height - y } else { h };
+        for row in y..y + h {
+            let line = self.at(x, row);
+            for i in 0..w {
+                unsafe { *line.offset(i as isize) = color; }
+            }
+        }
+    }
+
+    pub fn clear(&self, color: Rgb) {
+        self.fill_rect(0, 0, self.width, self.height, color);
+    }
+
+    /// Copies a `w` by `h` image whose lines are `stride` pixels apart,
+    /// clipped to the screen.
+    pub fn blit(&self, x: usize, y: usize, w: usize, h: usize, src: *const Rgb, stride: usize) {
+        if x >= self.width || y >= self.height {
+            return;
+        }
+        let w = if x + w > self.width { self.width - x } else { w };
+        let h = if y + h > self.height { self.height - y } else { h };
+        for row in 0..h {
+            unsafe {
+                memcpy(self.at(x, y + row) as *mut u8,
+                       src.offset((row * stride) as isize) as *const u8, w * 4);
+            }
+        }
+    }
+
+    /// Draws a 1-bit-per-pixel bitmap, such as a font glyph, in `fg` on `bg`.
+    /// Each line of the bitmap takes `(w + 7) / 8` bytes, most significant
+    /// bit first.
+    pub fn bitmap(&self, x: usize, y: usize, w: usize, h: usize, bits: &[u8], fg: Rgb, bg: Rgb) {
+        let line_bytes = (w + 7) / 8;
+        for row in 0..h {
+            if y + row >= self.height {
+                break;
+            }
+            let line = self.at(x, y + row);
+            for col in 0..w {
+                if x + col >= self.width {
+                    break;
+                }
+                let set = bits[row * line_bytes + col / 8] & (0x80 >> (col % 8)) != 0;
+                unsafe { *line.offset(col as isize) = if set { fg } else { bg }; }
+            }
+        }
+    }
+}
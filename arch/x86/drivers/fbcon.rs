@@ -0,0 +1,123 @@
+//! Renders the VGA text screen on a framebuffer with a PSF font, for when
+//! the console is selected to draw in graphics mode. The text buffer at
+//! 0xb8000 stays the record of what is on screen; cells are drawn as they
+//! change.
+
+use core::prelude::*;
+
+use super::bga;
+use super::fb::{Framebuffer, Rgb};
+use super::psf::Font;
+use super::vga;
+use super::vga::{WIDTH, HEIGHT, SCREEN_SIZE};
+
+/// The standard VGA palette, indexed by `vga::Color`.
+static PALETTE: [Rgb; ..16] = [
+    0x000000, 0x0000AA, 0x00AA00, 0x00AAAA, 0xAA0000, 0xAA00AA, 0xAA5500, 0xAAAAAA,
+    0x555555, 0x5555FF, 0x55FF55, 0x55FFFF, 0xFF5555, 0xFF55FF, 0xFFFF55, 0xFFFFFF
+];
+
+/// Lines of the underline cursor.
+const CURSOR_LINES: usize = 2;
+
+struct FbCon {
+    fb: Framebuffer,
+    font: Font,
+    cursor: usize
+}
+
+static mut fbcon: Option<FbCon> = None;
+
+/// The font linked into the kernel by the Makefile.
+#[cfg(console_fb)]
+fn embedded_font() -> &'static [u8] {
+    use core::mem::transmute;
+    use core::raw;
+
+    extern {
+        static _binary_font_psf_start: u8;
+        static _binary_font_psf_end: u8;
+    }
+    unsafe {
+        let start = &_binary_font_psf_start as *const u8;
+        let len = &_binary_font_psf_end as *const u8 as usize - start as usize;
+        transmute(raw::Slice { data: start, len: len })
+    }
+}
+
+/// Without `CONSOLE=fb` no font is linked in.
+#[cfg(not(console_fb))]
+fn embedded_font() -> &'static [u8] {
+    &[]
+}
+
+/// Switches to a graphics mode that fits the text screen in the embedded
+/// font. Returns false, leaving text mode alone, if the font or the adapter
+/// is missing.
+pub fn init() -> bool {
+    let font = match Font::new(embedded_font()) {
+        Some(font) => font,
+        None => return false
+    };
+    match bga::init(WIDTH * font.width, HEIGHT * font.height) {
+        Some(fb) => unsafe {
+            fbcon = Some(FbCon { fb: fb, font: font, cursor: SCREEN_SIZE });
+            true
+        },
+        None => false
+    }
+}
+
+pub fn enabled() -> bool {
+    unsafe { fbcon.is_some() }
+}
+
+impl FbCon {
+    fn draw(&self, pos: usize, c: u8, attr: u8) {
+        let x = pos % WIDTH * self.font.width;
+        let y = pos / WIDTH * self.font.height;
+        let fg = PALETTE[(attr & 0xF) as usize];
+        let bg = PALETTE[(attr >> 4) as usize];
+        self.fb.bitmap(x, y, self.font.width, self.font.height, self.font.glyph(c), fg, bg);
+    }
+}
+
+/// Draws the character `c` with VGA attribute `attr` at cell `pos`.
+pub fn draw(pos: usize, c: u8, attr: u8) {
+    unsafe {
+        match fbcon {
+            Some(ref con) => {
+                con.draw(pos, c, attr);
+                if pos == con.cursor {
+                    draw_cursor(con, attr);
+                }
+            }
+            None => {}
+        }
+    }
+}
+
+fn draw_cursor(con: &FbCon, attr: u8) {
+    let x = con.cursor % WIDTH * con.font.width;
+    let y = (con.cursor / WIDTH + 1) * con.font.height - CURSOR_LINES;
+    con.fb.fill_rect(x, y, con.font.width, CURSOR_LINES, PALETTE[(attr & 0xF) as usize]);
+}
+
+/// Moves the cursor to cell `pos`. Positions past the screen hide it.
+pub fn cursor_at(pos: usize) {
+    unsafe {
+        match fbcon {
+            Some(ref mut con) => {
+                if con.cursor < SCREEN_SIZE {
+                    let (c, attr) = vga::cell(con.cursor);
+                    con.draw(con.cursor, c, attr);
+                }
+                con.cursor = pos;
+                if pos < SCREEN_SIZE {
+                    draw_cursor(con, vga::cell(pos).1);
+                }
+            }
+            None => {}
+        }
+    }
+}
@@ -0,0 +1,424 @@
+//! Floppy drives on the 82077AA controller at 0x3F0. Data moves by ISA DMA
+//! on channel 2 through a buffer in low memory, a track at a time, and
+//! commands complete with IRQ 6; while interrupts are off, the driver polls
+//! the main status register instead.
+//!
+//! The drive types come from CMOS. Motors stay on once started.
+
+use core::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use core::cmp::min;
+use core::prelude::*;
+use core::ptr;
+
+use cpu;
+use cpu::{io, irq};
+use kernel::block;
+use kernel::block::{BlockDevice, Error};
+use kernel::heap;
+use kernel::time::Timeout;
+use super::dma;
+use super::dma::Direction;
+use super::{pit, rtc};
+
+pub const IRQ: u8 = 6;
+const DMA_CHANNEL: u8 = 2;
+
+/// Free low memory below the kernel, within one 64 KiB page: room for the
+/// longest track.
+const BUFFER: usize = 0x9000;
+const BUFFER_SIZE: usize = 0x4800;
+
+// registers
+const DOR: u16 = 0x3F2;
+const MSR: u16 = 0x3F4;
+const FIFO: u16 = 0x3F5;
+const CCR: u16 = 0x3F7;
+
+// digital output register
+const NOT_RESET: u8 = 1 << 2;
+const IRQ_DMA: u8 = 1 << 3;
+const MOTOR: u8 = 1 << 4;
+
+// main status register
+const RQM: u8 = 1 << 7;
+const DIO: u8 = 1 << 6;
+const BUSY: u8 = 1 << 4;
+
+// commands
+const SPECIFY: u8 = 0x03;
+const RECALIBRATE: u8 = 0x07;
+const SENSE_INTERRUPT: u8 = 0x08;
+const SEEK: u8 = 0x0F;
+const VERSION: u8 = 0x10;
+const CONFIGURE: u8 = 0x13;
+const LOCK: u8 = 0x94;
+const MULTI_TRACK: u8 = 0x80;
+const MFM: u8 = 0x40;
+const SKIP_DELETED: u8 = 0x20;
+const READ_DATA: u8 = 0x06;
+const WRITE_DATA: u8 = 0x05;
+
+const VERSION_82077AA: u8 = 0x90;
+/// No implied seeks, FIFO on, polling off, a threshold of 8 bytes.
+const CONFIGURATION: u8 = 0x17;
+/// At 500 kb/s, a 3 ms step rate and 240 ms head unload, then 2 ms head
+/// load, with DMA. Slower data rates stretch the times.
+const SPECIFICATION: [u8; ..2] = [0xDF, 0x02];
+
+// status register 0
+const ST0_ERROR: u8 = 0xC0;
+const ST0_SEEK_END: u8 = 1 << 5;
+// status register 1
+const ST1_NOT_WRITABLE: u8 = 1 << 1;
+
+/// 512 bytes per sector, as the size code commands take.
+const SECTOR_SIZE: usize = 512;
+const SIZE_CODE: u8 = 2;
+const RETRIES: usize = 3;
+const SPIN_UP_MS: u32 = 300;
+const COMMAND_TIMEOUT_MS: u32 = 3000;
+
+/// CMOS register with the types of drives 0 (high nibble) and 1.
+const CMOS_DRIVES: u8 = 0x10;
+
+#[derive(Copy)]
+struct Geometry {
+    name: &'static str,
+    cylinders: u8,
+    heads: u8,
+    sectors: u8,
+    /// Data rate, in the code for the control register.
+    rate: u8,
+    gap: u8
+}
+
+/// By CMOS drive type, from 1.
+static GEOMETRIES: [Geometry; ..5] = [
+    Geometry { name: "360 KiB", cylinders: 40, heads: 2, sectors: 9, rate: 2, gap: 0x2A },
+    Geometry { name: "1.2 MiB", cylinders: 80, heads: 2, sectors: 15, rate: 0, gap: 0x1B },
+    Geometry { name: "720 KiB", cylinders: 80, heads: 2, sectors: 9, rate: 2, gap: 0x1B },
+    Geometry { name: "1.44 MiB", cylinders: 80, heads: 2, sectors: 18, rate: 0, gap: 0x1B },
+    Geometry { name: "2.88 MiB", cylinders: 80, heads: 2, sectors: 36, rate: 3, gap: 0x1B }
+];
+
+static NAMES: [&'static str; ..2] = ["fd0", "fd1"];
+
+/// Set by the interrupt handler.
+static interrupted: AtomicBool = ATOMIC_BOOL_INIT;
+/// The digital output register: the selected drive and running motors.
+static mut dor: u8 = 0;
+/// Whether the controller polls the drives, as it does until `CONFIGURATION`
+/// is locked in.
+static mut polling: bool = true;
+
+/// What to poll for in place of the interrupt.
+#[derive(Copy)]
+enum Wait {
+    /// The result phase of a command.
+    Result,
+    /// The end of a seek on a drive.
+    Seek(u8),
+    Reset
+}
+
+fn write_byte(b: u8) -> Result<(), Error> {
+    for _ in 0..io::WAIT_LOOPS {
+        if io::inb(MSR) & (RQM | DIO) == RQM {
+            io::out(FIFO, b);
+            return Ok(());
+        }
+        io::inb(0x80);
+    }
+    Err(Error::Timeout)
+}
+
+fn read_byte() -> Result<u8, Error> {
+    for _ in 0..io::WAIT_LOOPS {
+        if io::inb(MSR) & (RQM | DIO) == RQM | DIO {
+            return Ok(io::inb(FIFO));
+        }
+        io::inb(0x80);
+    }
+    Err(Error::Timeout)
+}
+
+fn command(bytes: &[u8]) -> Result<(), Error> {
+    for &b in bytes.iter() {
+        try!(write_byte(b));
+    }
+    Ok(())
+}
+
+/// Waits for the controller's interrupt, or polls for `what` while
+/// interrupts are off. Polling counts its own time with the PIT, since the
+/// clock may need interrupts to advance.
+fn wait(what: Wait) -> Result<(), Error> {
+    if cpu::interrupts_enabled() {
+        let timeout = Timeout::ms(COMMAND_TIMEOUT_MS);
+        while !interrupted.swap(false, Ordering::SeqCst) {
+            if timeout.expired() {
+                return Err(Error::Timeout);
+            }
+            cpu::relax();
+        }
+        return Ok(());
+    }
+    for _ in 0..COMMAND_TIMEOUT_MS * 10 {
+        let msr = io::inb(MSR);
+        let done = match what {
+            Wait::Result => msr & (RQM | DIO | BUSY) == RQM | DIO | BUSY,
+            Wait::Seek(drive) => msr & 1 << drive as usize == 0,
+            Wait::Reset => msr & RQM != 0
+        };
+        if done {
+            interrupted.store(false, Ordering::SeqCst);
+            return Ok(());
+        }
+        pit::delay(100);
+    }
+    Err(Error::Timeout)
+}
+
+/// Acknowledges an interrupt after a seek or reset. Returns status register
+/// 0 and the cylinder the head is on.
+fn sense_interrupt() -> Result<(u8, u8), Error> {
+    try!(write_byte(SENSE_INTERRUPT));
+    let st0 = try!(read_byte());
+    let cylinder = try!(read_byte());
+    Ok((st0, cylinder))
+}
+
+/// Resets the controller, which forgets where every head is.
+fn reset() -> Result<(), Error> {
+    interrupted.store(false, Ordering::SeqCst);
+    unsafe {
+        io::out(DOR, dor & !NOT_RESET);
+        pit::delay(1000);
+        io::out(DOR, dor);
+    }
+    try!(wait(Wait::Reset));
+    // one for each drive the controller polls, or just the reset's own
+    let senses = if unsafe { polling } { 4 } else { 1 };
+    for _ in 0..senses {
+        try!(sense_interrupt());
+    }
+    command(&[SPECIFY, SPECIFICATION[0], SPECIFICATION[1]])
+}
+
+fn handler() {
+    interrupted.store(true, Ordering::SeqCst);
+}
+
+pub struct Drive {
+    number: u8,
+    geometry: Geometry,
+    /// Where the head is, unless the controller was reset since.
+    cylinder: Option<u8>
+}
+
+impl Drive {
+    /// Selects the drive and starts its motor, giving it time to spin up.
+    fn select(&self) {
+        let motor = MOTOR << self.number as usize;
+        unsafe {
+            let running = dor & motor != 0;
+            dor = (dor & !3) | motor | self.number;
+            io::out(DOR, dor);
+            if !running {
+                pit::delay(SPIN_UP_MS * 1000);
+            }
+        }
+        io::out(CCR, self.geometry.rate);
+    }
+
+    fn seek_end(&mut self, cylinder: u8) -> Result<(), Error> {
+        try!(wait(Wait::Seek(self.number)));
+        let (st0, at) = try!(sense_interrupt());
+        if st0 & ST0_ERROR != 0 || st0 & ST0_SEEK_END == 0 || at != cylinder {
+            return Err(Error::Io);
+        }
+        self.cylinder = Some(cylinder);
+        Ok(())
+    }
+
+    fn recalibrate(&mut self) -> Result<(), Error> {
+        // one command steps at most 77 times, short of 80 cylinders
+        let mut result = Err(Error::Io);
+        for _ in 0..2 {
+            try!(command(&[RECALIBRATE, self.number]));
+            result = self.seek_end(0);
+            if result.is_ok() {
+                break;
+            }
+        }
+        result
+    }
+
+    fn seek(&mut self, cylinder: u8, head: u8) -> Result<(), Error> {
+        if self.cylinder.is_none() {
+            try!(self.recalibrate());
+        }
+        if self.cylinder == Some(cylinder) {
+            return Ok(());
+        }
+        try!(command(&[SEEK, head << 2 | self.number, cylinder]));
+        self.seek_end(cylinder)
+    }
+
+    /// Transfers `count` sectors from sector `lba` on, within one track,
+    /// between the drive and the DMA buffer.
+    fn transfer(&mut self, lba: u64, count: usize, direction: Direction) -> Result<(), Error> {
+        let g = self.geometry;
+        let track = lba / g.sectors as u64;
+        let cylinder = (track / g.heads as u64) as u8;
+        let head = (track % g.heads as u64) as u8;
+        let sector = (lba % g.sectors as u64) as u8 + 1;
+        let op = match direction {
+            Direction::Read => MULTI_TRACK | MFM | SKIP_DELETED | READ_DATA,
+            Direction::Write => MULTI_TRACK | MFM | WRITE_DATA
+        };
+
+        self.select();
+        try!(self.seek(cylinder, head));
+        if !dma::setup(DMA_CHANNEL, BUFFER, count * SECTOR_SIZE, direction) {
+            return Err(Error::Io);
+        }
+        interrupted.store(false, Ordering::SeqCst);
+        try!(command(&[op, head << 2 | self.number, cylinder, head, sector, SIZE_CODE,
+                       sector + count as u8 - 1, g.gap, 0xFF]));
+        try!(wait(Wait::Result));
+        let mut result = [0u8; 7];
+        for b in result.iter_mut() {
+            *b = try!(read_byte());
+        }
+        if result[0] & ST0_ERROR != 0 {
+            return Err(if result[1] & ST1_NOT_WRITABLE != 0 { Error::ReadOnly } else { Error::Io });
+        }
+        Ok(())
+    }
+
+    /// `transfer`, resetting the controller after an error and trying again.
+    fn transfer_retrying(&mut self, lba: u64, count: usize, direction: Direction) -> Result<(), Error> {
+        let mut result = Err(Error::Io);
+        for _ in 0..RETRIES {
+            result = self.transfer(lba, count, direction);
+            match result {
+                Ok(()) | Err(Error::ReadOnly) => break,
+                Err(_) => {
+                    self.cylinder = None;
+                    try!(reset());
+                }
+            }
+        }
+        result
+    }
+
+    /// Splits a request into runs of sectors that end with a track.
+    fn track_run(&self, lba: u64, remaining: usize) -> usize {
+        let sectors = self.geometry.sectors as usize;
+        min(min(remaining, sectors - (lba % sectors as u64) as usize), BUFFER_SIZE / SECTOR_SIZE)
+    }
+}
+
+impl BlockDevice for Drive {
+    fn name(&self) -> &str {
+        NAMES[self.number as usize]
+    }
+
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sectors(&self) -> u64 {
+        let g = self.geometry;
+        g.cylinders as u64 * g.heads as u64 * g.sectors as u64
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), Error> {
+        let total = try!(self.check(lba, buf.len())) as usize;
+        let mut done = 0;
+        while done < total {
+            let start = lba + done as u64;
+            let count = self.track_run(start, total - done);
+            try!(self.transfer_retrying(start, count, Direction::Read));
+            unsafe {
+                ptr::copy_nonoverlapping(buf[done * SECTOR_SIZE..].as_mut_ptr(), BUFFER as *const u8,
+                                         count * SECTOR_SIZE);
+            }
+            done += count;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u64, buf: &[u8]) -> Result<(), Error> {
+        let total = try!(self.check(lba, buf.len())) as usize;
+        let mut done = 0;
+        while done < total {
+            let start = lba + done as u64;
+            let count = self.track_run(start, total - done);
+            unsafe {
+                ptr::copy_nonoverlapping(BUFFER as *mut u8, buf[done * SECTOR_SIZE..].as_ptr(),
+                                         count * SECTOR_SIZE);
+            }
+            try!(self.transfer_retrying(start, count, Direction::Write));
+            done += count;
+        }
+        Ok(())
+    }
+}
+
+/// Resets the controller and registers the drives CMOS lists as block
+/// devices.
+pub fn init() {
+    let types = rtc::read_reg(CMOS_DRIVES);
+    let kinds = [types >> 4, types & 0xF];
+    if kinds.iter().all(|&kind| kind == 0) {
+        return;
+    }
+
+    irq::register(IRQ, handler);
+    unsafe {
+        dor = NOT_RESET | IRQ_DMA;
+    }
+    match reset() {
+        Ok(()) => {}
+        Err(e) => {
+            println!("floppy: reset: {}", e);
+            return;
+        }
+    }
+    let version = match command(&[VERSION]).and_then(|_| read_byte()) {
+        Ok(version) => version,
+        Err(e) => {
+            println!("floppy: {}", e);
+            return;
+        }
+    };
+    if version == VERSION_82077AA {
+        // locked, so that resets keep it
+        let configured = command(&[CONFIGURE, 0, CONFIGURATION, 0])
+            .and_then(|_| command(&[LOCK]))
+            .and_then(|_| read_byte());
+        match configured {
+            Ok(_) => unsafe { polling = false },
+            Err(e) => println!("floppy: configure: {}", e)
+        }
+    } else {
+        println!("floppy: controller version {:02x}, not an 82077AA", version);
+    }
+
+    for (number, &kind) in kinds.iter().enumerate() {
+        let geometry = match kind {
+            1...5 => GEOMETRIES[kind as usize - 1],
+            _ => continue
+        };
+        println!("floppy: {} {}", NAMES[number], geometry.name);
+        unsafe {
+            let ptr = heap::alloc::<Drive>(1);
+            ptr::write(ptr, Drive { number: number as u8, geometry: geometry, cylinder: None });
+            if block::register(&mut *ptr).is_none() {
+                println!("floppy: too many block devices");
+            }
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! IO-APIC. Routes device interrupts, numbered as global system interrupts
+//! (GSI), to vectors of local APICs.
+
+use core::intrinsics::{volatile_load, volatile_store};
+use core::prelude::*;
+
+use cpu::mmu;
+use kernel::mm::OutOfMemory;
+use super::apic::IoApicEntry;
+
+const IOREGSEL: isize = 0;
+const IOWIN: isize = 0x10 / 4;
+
+const VERSION: u32 = 1;
+const REDIRECTION: u32 = 0x10;
+
+const MASKED: u32 = 1 << 16;
+const LEVEL: u32 = 1 << 15;
+const ACTIVE_LOW: u32 = 1 << 13;
+
+static mut base: *mut u32 = 0 as *mut u32;
+static mut gsi_base: u32 = 0;
+static mut count: u32 = 0;
+
+fn read(reg: u32) -> u32 {
+    unsafe {
+        volatile_store(base.offset(IOREGSEL), reg);
+        volatile_load(base.offset(IOWIN) as *const u32)
+    }
+}
+
+fn write(reg: u32, val: u32) {
+    unsafe {
+        volatile_store(base.offset(IOREGSEL), reg);
+        volatile_store(base.offset(IOWIN), val);
+    }
+}
+
+/// Maps the IO-APIC and masks all of its inputs.
+pub unsafe fn init(entry: &IoApicEntry) -> Result<(), OutOfMemory> {
+    base = try!(mmu::map_device(entry.addr, 0x20)) as *mut u32;
+    gsi_base = entry.gsi_base;
+    count = ((read(VERSION) >> 16) & 0xFF) + 1;
+
+    for pin in 0..count {
+        write(REDIRECTION + pin * 2, MASKED);
+    }
+    Ok(())
+}
+
+fn pin(gsi: u32) -> Option<u32> {
+    unsafe {
+        if gsi >= gsi_base && gsi < gsi_base + count {
+            Some(gsi - gsi_base)
+        } else {
+            None
+        }
+    }
+}
+
+/// Routes an interrupt to `vector` of the local APIC `dest`. It stays masked.
+/// `flags` holds the polarity and trigger mode as in the MP and ACPI tables,
+/// where 0 means the ISA default of active high, edge triggered.
+pub fn route(gsi: u32, vector: u8, flags: u16, dest: u8) {
+    let pin = match pin(gsi) {
+        Some(pin) => pin,
+        None => return
+    };
+    let mut low = MASKED | vector as u32;
+    if flags & 0b11 == 0b11 {
+        low |= ACTIVE_LOW;
+    }
+    if (flags >> 2) & 0b11 == 0b11 {
+        low |= LEVEL;
+    }
+    write(REDIRECTION + pin * 2 + 1, (dest as u32) << 24);
+    write(REDIRECTION + pin * 2, low);
+}
+
+pub fn mask(gsi: u32) {
+    match pin(gsi) {
+        Some(pin) => write(REDIRECTION + pin * 2, read(REDIRECTION + pin * 2) | MASKED),
+        None => {}
+    }
+}
+
+pub fn unmask(gsi: u32) {
+    match pin(gsi) {
+        Some(pin) => write(REDIRECTION + pin * 2, read(REDIRECTION + pin * 2) & !MASKED),
+        None => {}
+    }
+}
@@ -1,90 +1,389 @@
-use cpu::io;
+//! PS/2 keyboard in scancode set 1.
+//!
+//! Scancodes become key events: a key code, whether the key went down or up,
+//! and the modifiers held at the time. Events are queued until read. Key
+//! presses are also translated, through the keyboard layout in
+//! `kernel::keymap`, to characters or VT100 escape sequences for the terminal
+//! on screen.
+
+use core::prelude::*;
+
+use cpu;
+use kernel::keymap;
+use kernel::keymap::{Composer, Level};
 use super::keydown;
+use super::ps2;
+use super::ps2::Port;
+use super::vga;
+use super::vt;
+
+pub const IRQ: u8 = 1;
+
+/// Identifies a key: its scancode, with bit 7 set for keys sent with the
+/// 0xE0 prefix.
+pub type KeyCode = u8;
+
+pub const ESC: KeyCode = 0x01;
+pub const BACKSPACE: KeyCode = 0x0E;
+pub const TAB: KeyCode = 0x0F;
+pub const ENTER: KeyCode = 0x1C;
+pub const LCTRL: KeyCode = 0x1D;
+pub const LSHIFT: KeyCode = 0x2A;
+pub const RSHIFT: KeyCode = 0x36;
+pub const LALT: KeyCode = 0x38;
+pub const SPACE: KeyCode = 0x39;
+pub const CAPS_LOCK: KeyCode = 0x3A;
+pub const F1: KeyCode = 0x3B;
+pub const F10: KeyCode = 0x44;
+pub const NUM_LOCK: KeyCode = 0x45;
+pub const SCROLL_LOCK: KeyCode = 0x46;
+/// Keypad 7 to keypad `.`, in scancode order.
+pub const KP_7: KeyCode = 0x47;
+pub const KP_DOT: KeyCode = 0x53;
+pub const KP_MINUS: KeyCode = 0x4A;
+pub const KP_PLUS: KeyCode = 0x4E;
+pub const F11: KeyCode = 0x57;
+pub const F12: KeyCode = 0x58;
+pub const KP_ENTER: KeyCode = 0x80 | 0x1C;
+pub const RCTRL: KeyCode = 0x80 | 0x1D;
+pub const KP_SLASH: KeyCode = 0x80 | 0x35;
+pub const RALT: KeyCode = 0x80 | 0x38;
+pub const HOME: KeyCode = 0x80 | 0x47;
+pub const UP: KeyCode = 0x80 | 0x48;
+pub const PAGE_UP: KeyCode = 0x80 | 0x49;
+pub const LEFT: KeyCode = 0x80 | 0x4B;
+pub const RIGHT: KeyCode = 0x80 | 0x4D;
+pub const END: KeyCode = 0x80 | 0x4F;
+pub const DOWN: KeyCode = 0x80 | 0x50;
+pub const PAGE_DOWN: KeyCode = 0x80 | 0x51;
+pub const INSERT: KeyCode = 0x80 | 0x52;
+pub const DELETE: KeyCode = 0x80 | 0x53;
+pub const LMETA: KeyCode = 0x80 | 0x5B;
+pub const RMETA: KeyCode = 0x80 | 0x5C;
+pub const MENU: KeyCode = 0x80 | 0x5D;
+
+bitflags!(flags Modifiers: u8 {
+    const SHIFT = 1 << 0,
+    const CTRL  = 1 << 1,
+    const ALT   = 1 << 2,
+    const META  = 1 << 3,
+    const CAPS  = 1 << 4,
+    const NUM   = 1 << 5,
+    const SCROLL = 1 << 6,
+    /// Right Alt, on layouts that have an AltGr level.
+    const ALTGR = 1 << 7
+});
+
+#[derive(Copy)]
+pub struct KeyEvent {
+    pub code: KeyCode,
+    pub pressed: bool,
+    /// Modifiers and lock keys in effect, including this key's own change.
+    pub mods: Modifiers
+}
+
+/// Keypad keys from `KP_7`, with Num Lock on.
+static Keypad: &'static [u8] = b"789-456+1230.";
+
+/// Escape sequences of the keys from `HOME` to `DELETE`, with the same
+/// sequences on the keypad when Num Lock is off.
+static NAVIGATION: [&'static [u8]; ..13] = [
+    b"\x1B[H", b"\x1B[A", b"\x1B[5~", b"", b"\x1B[D", b"", b"\x1B[C",
+    b"", b"\x1B[F", b"\x1B[B", b"\x1B[6~", b"\x1B[2~", b"\x1B[3~"
+];
+
+static FUNCTION: [&'static [u8]; ..12] = [
+    b"\x1BOP", b"\x1BOQ", b"\x1BOR", b"\x1BOS", b"\x1B[15~", b"\x1B[17~",
+    b"\x1B[18~", b"\x1B[19~", b"\x1B[20~", b"\x1B[21~", b"\x1B[23~", b"\x1B[24~"
+];
+
+/// Bytes 0xE1 starts the six-byte Pause sequence with.
+const PAUSE_BYTES: u8 = 6;
+
+/// Keys currently down, one bit per key code.
+static mut down: [u32; ..8] = [0; 8];
+static mut locks: Modifiers = Modifiers { bits: 0 };
+/// Set after the 0xE0 prefix of an extended scancode.
+static mut extended: bool = false;
+static mut composer: Composer = keymap::COMPOSER_INIT;
+/// Bytes of the Pause sequence still to come.
+static mut pause: u8 = 0;
+
+const QUEUE_SIZE: usize = 64;
+static mut queue: [KeyEvent; ..QUEUE_SIZE] = [KeyEvent { code: 0, pressed: false, mods: Modifiers { bits: 0 } }; QUEUE_SIZE];
+static mut head: usize = 0;
+static mut len: usize = 0;
+
+const SET_LEDS: u8 = 0xED;
+
+/// How far a Set LEDs command got. The interrupt handler can't wait for the
+/// keyboard's ACKs, so each byte is sent when the previous one is ACKed.
+#[derive(Copy, PartialEq)]
+enum Leds {
+    Idle,
+    /// `SET_LEDS` sent.
+    Command,
+    /// The LED state sent.
+    State
+}
+
+static mut leds: Leds = Leds::Idle;
+/// The lock keys changed while a command was under way.
+static mut leds_changed: bool = false;
 
-pub static IRQ: usize = 0x20 + 1;
-
-pub static Layout: &'static [u8] = b"\
-\x00\x1B1234567890-=\x08\
-\tqwertyuiop[]\n\
-\x00asdfghjkl;'`\
-\x00\\zxcvbnm,./\x00\
-*\x00 ";
-
-static LayoutShift: &'static [u8] = b"\
-\x00\x1B!@#$%^&*()_+\x08\
-\tQWERTYUIOP{}\n\
-\x00ASDFGHJKL:\"~\
-\x00|ZXCVBNM<>?\x00\
-*\x00 ";
-
-static mut shift: bool = false;
-static mut caps_lock: bool = false;
-static mut led_state: u8 = 0;
-
-fn led(state: u8) {
-    io::wait(0x64, 2);
-    io::out(0x60, 0xEDu8);
-    io::wait(0x64, 2);
+fn led_state() -> u8 {
+    let locks = unsafe { locks };
+    let mut state = 0u8;
+    if locks.contains(SCROLL) { state |= 0b001 }
+    if locks.contains(NUM) { state |= 0b010 }
+    if locks.contains(CAPS) { state |= 0b100 }
+    state
+}
+
+fn send_leds(next: Leds, byte: u8) {
+    match ps2::write(Port::Keyboard, byte) {
+        Ok(()) => unsafe { leds = next },
+        Err(e) => {
+            unsafe { leds = Leds::Idle }
+            println!("keyboard: setting LEDs: {}", e);
+        }
+    }
+}
+
+/// Starts updating the LEDs to the lock keys, or notes to do it again once
+/// the update under way finishes.
+fn update_leds() {
+    unsafe {
+        if leds != Leds::Idle {
+            leds_changed = true;
+            return;
+        }
+    }
+    send_leds(Leds::Command, SET_LEDS);
+}
+
+/// Takes the keyboard's reply to the byte last sent.
+fn leds_reply(reply: u8) {
     unsafe {
-        led_state ^= state;
-        io::out(0x60, led_state);
+        match (leds, reply) {
+            (Leds::Command, ps2::ACK) => send_leds(Leds::State, led_state()),
+            (Leds::State, ps2::ACK) => {
+                leds = Leds::Idle;
+                if leds_changed {
+                    leds_changed = false;
+                    update_leds();
+                }
+            }
+            (Leds::Command, ps2::RESEND) => send_leds(Leds::Command, SET_LEDS),
+            (Leds::State, ps2::RESEND) => send_leds(Leds::State, led_state()),
+            _ => {}
+        }
     }
 }
 
-fn isalpha(c: u8) -> bool {
-    ((c | 0x20) - 'a' as u8) < 26
+/// Whether the key is held down.
+pub fn is_down(code: KeyCode) -> bool {
+    unsafe { down[(code >> 5) as usize] & 1 << (code & 31) as usize != 0 }
 }
 
-#[no_stack_check]
-fn keypress(code: u8) {
-    match (code & 0x7f, code & 0x80 == 0) {
-        (0x2A, down) | (0x36, down) => unsafe { shift = down },
-        (0x3A, true) => unsafe { // Caps lock
-            caps_lock = !caps_lock;
-            led(0b100)
-        },
-        (0x45, true) => led(0b010), // Number lock
-        (0x46, true) => led(0b001), // Scroll lock
-        (c, true) if c < 0x3A => unsafe {
-            // handle character
-            let mut ch = if shift { LayoutShift[c as usize] } else { Layout[c as usize] };
-            if ch != 0 {
-                if caps_lock && isalpha(ch) {
-                    ch ^= 1 << 5;
+/// The modifiers held and lock keys on.
+pub fn modifiers() -> Modifiers {
+    let mut mods = unsafe { locks };
+    if is_down(LSHIFT) || is_down(RSHIFT) { mods = mods | SHIFT }
+    if is_down(LCTRL) || is_down(RCTRL) { mods = mods | CTRL }
+    if is_down(LALT) { mods = mods | ALT }
+    if is_down(RALT) {
+        mods = mods | if keymap::keymap().has_altgr() { ALTGR } else { ALT };
+    }
+    if is_down(LMETA) || is_down(RMETA) { mods = mods | META }
+    mods
+}
+
+/// Takes the oldest key event not yet read.
+pub fn read() -> Option<KeyEvent> {
+    cpu::without_interrupts(|| unsafe {
+        if len == 0 {
+            return None;
+        }
+        let event = queue[head];
+        head = (head + 1) % QUEUE_SIZE;
+        len -= 1;
+        Some(event)
+    })
+}
+
+/// Queues an event. When nobody reads them, the newest are dropped.
+fn push(event: KeyEvent) {
+    unsafe {
+        if len < QUEUE_SIZE {
+            queue[(head + len) % QUEUE_SIZE] = event;
+            len += 1;
+        }
+    }
+}
+
+/// What a key press types.
+pub enum Input {
+    Nothing,
+    Byte(u8),
+    /// A character from the layout, possibly a dead key.
+    Char(char),
+    Sequence(&'static [u8])
+}
+
+/// Translates a key press to a character or an escape sequence. Ctrl with a
+/// letter gives a control character; Alt is left to the caller, which sends
+/// ESC before the result.
+pub fn translate(event: &KeyEvent) -> Input {
+    let code = event.code;
+    let mods = event.mods;
+    if !event.pressed {
+        return Input::Nothing;
+    }
+    match code {
+        KP_ENTER => Input::Byte(b'\n'),
+        KP_SLASH => Input::Byte(b'/'),
+        // among the keys from `KP_7`, these two never navigate
+        KP_MINUS => Input::Byte(b'-'),
+        KP_PLUS => Input::Byte(b'+'),
+        HOME...DELETE => Input::Sequence(NAVIGATION[(code - HOME) as usize]),
+        KP_7...KP_DOT if mods.contains(NUM) => Input::Byte(Keypad[(code - KP_7) as usize]),
+        KP_7...KP_DOT => Input::Sequence(NAVIGATION[(code - KP_7) as usize]),
+        F1...F10 => Input::Sequence(FUNCTION[(code - F1) as usize]),
+        F11 | F12 => Input::Sequence(FUNCTION[(code - F11) as usize + 10]),
+        c => {
+            let keymap = keymap::keymap();
+            let shift = mods.contains(SHIFT) != (mods.contains(CAPS) && keymap.caps(c));
+            let level = if mods.contains(ALTGR) {
+                Level::AltGr
+            } else if shift {
+                Level::Shift
+            } else {
+                Level::Plain
+            };
+            match keymap.lookup(c, level) {
+                Some(ch @ 'a'...'z') | Some(ch @ 'A'...'Z') if mods.contains(CTRL) => {
+                    Input::Byte(ch as u8 & 0x1F)
                 }
-                keydown.map(|f| f(ch) );
+                Some(ch) => Input::Char(ch),
+                None => Input::Nothing
+            }
+        }
+    }
+}
+
+/// Sends a character from the layout, through the dead keys, in the
+/// console's character set.
+fn emit_char(c: char) {
+    let (first, second) = unsafe { composer.feed(c) };
+    for c in first.iter().chain(second.iter()) {
+        emit(vga::encode(*c));
+    }
+}
+
+fn emit(c: u8) {
+    vt::input(c);
+    unsafe {
+        keydown.map(|f| f(c));
+    }
+}
+
+/// Handles keys the console itself uses. Returns true if the key was used.
+fn console_key(event: &KeyEvent) -> bool {
+    let mods = event.mods;
+    match event.code {
+        // Alt+F1 to Alt+F6
+        c @ F1...0x40 if mods.contains(ALT) => vt::switch((c - F1) as usize),
+        PAGE_UP if mods.contains(SHIFT) => {
+            vt::current_terminal().map(|t| t.console.scroll_back(vga::HEIGHT / 2));
+        }
+        PAGE_DOWN if mods.contains(SHIFT) => {
+            vt::current_terminal().map(|t| t.console.scroll_forward(vga::HEIGHT / 2));
+        }
+        _ => return false
+    }
+    true
+}
+
+fn key(code: KeyCode, pressed: bool) {
+    let index = (code >> 5) as usize;
+    let bit = 1 << (code & 31) as usize;
+    unsafe {
+        // a key held down repeats its press, but doesn't toggle again
+        let repeat = pressed && down[index] & bit != 0;
+        if pressed { down[index] |= bit } else { down[index] &= !bit }
+        let lock = match code {
+            CAPS_LOCK => CAPS,
+            NUM_LOCK => NUM,
+            SCROLL_LOCK => SCROLL,
+            _ => Modifiers::empty()
+        };
+        if pressed && !repeat && !lock.is_empty() {
+            locks = locks ^ lock;
+            update_leds();
+        }
+    }
+
+    let event = KeyEvent { code: code, pressed: pressed, mods: modifiers() };
+    push(event);
+    if !pressed || console_key(&event) {
+        return;
+    }
+    let alt = event.mods.contains(ALT);
+    match translate(&event) {
+        Input::Byte(c) => {
+            if alt {
+                emit(0x1B);
+            }
+            emit(c);
+        }
+        Input::Char(c) => {
+            if alt {
+                emit(0x1B);
             }
+            emit_char(c);
+        }
+        Input::Sequence(s) => for &c in s.iter() {
+            emit(c);
         },
-        _ => {}
+        Input::Nothing => {}
     }
 }
 
 #[no_stack_check]
-#[inline(never)]
-pub unsafe fn isr_addr() -> unsafe extern "C" fn() {
-    asm!("jmp skip_isr_addr
-      isr_addr_asm:
-          push gs
-          push fs
-          .byte 0x06
-          .byte 0x1e
-          pusha"
-        :::: "intel");
-
-          keypress(io::inb(0x60));
-          io::out(0x20, 0x20u8);
-
-    asm!("popa
-          .byte 0x1f // pop ds
-          .byte 0x07 // pop es
-          pop fs
-          pop gs
-          iretd
-      skip_isr_addr:"
-        :::: "intel");
-
-    // it must be referenced in code
-    isr_addr_asm
-}
-
-extern "C" { fn isr_addr_asm(); }
+fn keypress(code: u8) {
+    unsafe {
+        if pause > 0 {
+            pause -= 1;
+            return;
+        }
+        match code {
+            0xE0 => {
+                extended = true;
+                return;
+            }
+            0xE1 => {
+                pause = PAUSE_BYTES - 1;
+                return;
+            }
+            _ => {}
+        }
+        let ext = extended;
+        extended = false;
+        let pressed = code & 0x80 == 0;
+        match (code & 0x7F, ext) {
+            // the controller sends fake shifts around some extended keys
+            (0x2A, true) | (0x36, true) => {}
+            (c, true) => key(0x80 | c, pressed),
+            (c, false) => key(c, pressed)
+        }
+    }
+}
+
+pub fn handler() {
+    match ps2::poll(Port::Keyboard) {
+        Some(reply @ ps2::ACK) | Some(reply @ ps2::RESEND) => leds_reply(reply),
+        Some(code) => keypress(code),
+        None => {}
+    }
+}
@@ -1,21 +1,92 @@
 use core::option::Option;
 use core::option::Option::{Some, None};
 
+use cpu;
+use cpu::{irq, smp};
 use kernel;
+use platform::io;
 
 pub mod pic;
+pub mod pit;
+pub mod rtc;
+pub mod clock;
+pub mod acpi;
+pub mod apic;
+pub mod ioapic;
+pub mod mp;
+pub mod pci;
+pub mod ansi;
 pub mod vga;
+pub mod fb;
+pub mod bga;
+pub mod psf;
+pub mod fbcon;
+pub mod vt;
+pub mod ps2;
 pub mod keyboard;
+pub mod mouse;
+pub mod serial;
+pub mod ata;
+pub mod dma;
+pub mod floppy;
+pub mod virtio;
 
 pub static mut keydown: Option<fn(u8)> = None;
 
 pub fn init() {
-    vga::clear_screen(vga::Color::LightRed);
-    vga::cursor_at(0);
+    serial::init();
+    io::add_console(serial::putc);
 
-    unsafe {
-        kernel::int_table.map(|mut t| {
-            t.enable_maskable(keyboard::IRQ, keyboard::isr_addr());
-        });
+    pci::init();
+    if cfg!(console_fb) && !fbcon::init() {
+        println!("fbcon: no Bochs display or font, staying in text mode");
     }
+    io::clear(vga::Color::LightRed);
+
+    // MADT, then the older MP tables
+    let topology = match if acpi::init() { acpi::madt() } else { None } {
+        None => mp::find(),
+        some => some
+    };
+    match topology {
+        Some(ref topology) if !topology.ioapics.is_empty() && cpu::info().has(cpu::APIC) => unsafe {
+            kernel::int_table.map(|mut t| match irq::use_apic(&mut t, topology) {
+                Ok(()) => {
+                    println!("irq: using IO-APIC, {} CPUs", topology.cpus.len());
+                    smp::start(&mut t, topology);
+                }
+                Err(_) => println!("irq: out of memory mapping the APIC, using PIC")
+            });
+        },
+        _ => println!("irq: using PIC")
+    }
+    // the drivers below wait with timeouts, which need the clock
+    kernel::time::init();
+
+    match ps2::init() {
+        Ok(ports) => {
+            if ports.keyboard {
+                irq::register(keyboard::IRQ, keyboard::handler);
+            }
+            if ports.mouse {
+                match mouse::init() {
+                    Ok(()) => {
+                        irq::register(mouse::IRQ, mouse::handler);
+                        println!("ps2: mouse{}", if mouse::has_wheel() { " with wheel" } else { "" });
+                    }
+                    Err(e) => println!("ps2: mouse: {}", e)
+                }
+            }
+        }
+        Err(e) => {
+            // ps2::init gave the keyboard back as the BIOS left it
+            println!("ps2: {}", e);
+            irq::register(keyboard::IRQ, keyboard::handler);
+        }
+    }
+
+    ata::init();
+    floppy::init();
+    virtio::init();
+    pci::print_devices();
 }
@@ -0,0 +1,95 @@
+//! PS/2 mouse on the second port of the 8042. Packets become
+//! `kernel::input` events.
+
+use core::prelude::*;
+
+use kernel::input;
+use kernel::input::MouseEvent;
+use super::ps2;
+use super::ps2::{Port, Error};
+
+pub const IRQ: u8 = 12;
+
+const SET_SAMPLE_RATE: u8 = 0xF3;
+const GET_ID: u8 = 0xF2;
+const ENABLE_REPORTING: u8 = 0xF4;
+const SET_DEFAULTS: u8 = 0xF6;
+
+/// The ID of a mouse with a wheel, which sends 4-byte packets.
+const ID_WHEEL: u8 = 3;
+
+// first byte of a packet
+const ALWAYS_SET: u8 = 1 << 3;
+const X_SIGN: u8 = 1 << 4;
+const Y_SIGN: u8 = 1 << 5;
+const X_OVERFLOW: u8 = 1 << 6;
+const Y_OVERFLOW: u8 = 1 << 7;
+
+static mut packet: [u8; ..4] = [0; 4];
+static mut received: usize = 0;
+static mut packet_size: usize = 3;
+
+fn set_sample_rate(rate: u8) -> Result<(), Error> {
+    try!(ps2::send(Port::Mouse, SET_SAMPLE_RATE));
+    ps2::send(Port::Mouse, rate)
+}
+
+/// Sets the mouse up after `ps2::init` found it, turning on the wheel where
+/// there is one, and starts reporting.
+pub fn init() -> Result<(), Error> {
+    try!(ps2::send(Port::Mouse, SET_DEFAULTS));
+
+    // this sequence of rates switches IntelliMouse-compatible mice to 4-byte
+    // packets with wheel movement
+    try!(set_sample_rate(200));
+    try!(set_sample_rate(100));
+    try!(set_sample_rate(80));
+    try!(ps2::send(Port::Mouse, GET_ID));
+    if try!(ps2::read_from(Port::Mouse)) == ID_WHEEL {
+        unsafe { packet_size = 4; }
+    }
+    try!(set_sample_rate(100));
+
+    ps2::send(Port::Mouse, ENABLE_REPORTING)
+}
+
+/// Whether the mouse reports wheel movement.
+pub fn has_wheel() -> bool {
+    unsafe { packet_size == 4 }
+}
+
+fn decode(p: &[u8; ..4], size: usize) -> Option<MouseEvent> {
+    let flags = p[0];
+    if flags & (X_OVERFLOW | Y_OVERFLOW) != 0 {
+        return None;
+    }
+    // 9-bit two's complement, with the sign bit in the first byte
+    let dx = p[1] as i32 - if flags & X_SIGN != 0 { 0x100 } else { 0 };
+    let dy = p[2] as i32 - if flags & Y_SIGN != 0 { 0x100 } else { 0 };
+    // 4-bit two's complement
+    let wheel = if size == 4 { ((p[3] << 4) as i8 >> 4) as i32 } else { 0 };
+    Some(MouseEvent { dx: dx, dy: dy, wheel: wheel, buttons: (flags & 0b111) as u32 })
+}
+
+pub fn handler() {
+    let byte = match ps2::poll(Port::Mouse) {
+        Some(byte) => byte,
+        None => return
+    };
+    unsafe {
+        // the first byte always has bit 3 set; drop bytes until one does to
+        // get back in step
+        if received == 0 && byte & ALWAYS_SET == 0 {
+            return;
+        }
+        packet[received] = byte;
+        received += 1;
+        if received == packet_size {
+            received = 0;
+            match decode(&packet, packet_size) {
+                Some(event) => input::push_mouse(event),
+                None => {}
+            }
+        }
+    }
+}
@@ -0,0 +1,189 @@
+//! Intel MultiProcessor Specification tables. Older firmware describes
+//! processors and IO-APICs only here.
+//!
+//! 1. [MultiProcessor Specification 1.4][[1]]
+//! [1]: http://www.intel.com/design/pentium/datashts/24201606.pdf
+
+use core::prelude::*;
+
+use super::apic::{IoApicEntry, Override, Topology};
+
+#[repr(packed)]
+struct FloatingPointer {
+    signature: [u8; ..4],   // "_MP_"
+    config: u32,
+    length: u8,             // in 16-byte units
+    revision: u8,
+    checksum: u8,
+    features: [u8; ..5]
+}
+
+#[repr(packed)]
+struct ConfigHeader {
+    signature: [u8; ..4],   // "PCMP"
+    length: u16,
+    revision: u8,
+    checksum: u8,
+    oem: [u8; ..20],
+    oem_table: u32,
+    oem_table_size: u16,
+    entries: u16,
+    lapic_addr: u32,
+    ext_length: u16,
+    ext_checksum: u8,
+    reserved: u8
+}
+
+#[repr(packed)]
+struct ProcessorEntry {
+    kind: u8,
+    lapic_id: u8,
+    lapic_version: u8,
+    flags: u8,              // bit 0: enabled
+    signature: u32,
+    features: u32,
+    reserved: [u32; ..2]
+}
+
+#[repr(packed)]
+struct BusEntry {
+    kind: u8,
+    id: u8,
+    name: [u8; ..6]
+}
+
+#[repr(packed)]
+struct IoApicEntryRaw {
+    kind: u8,
+    id: u8,
+    version: u8,
+    flags: u8,              // bit 0: enabled
+    addr: u32
+}
+
+#[repr(packed)]
+struct InterruptEntry {
+    kind: u8,
+    int_type: u8,           // 0: vectored interrupt
+    flags: u16,
+    src_bus: u8,
+    src_irq: u8,
+    dst_ioapic: u8,
+    dst_pin: u8
+}
+
+const PROCESSOR: u8 = 0;
+const BUS: u8 = 1;
+const IO_APIC: u8 = 2;
+const IO_INTERRUPT: u8 = 3;
+
+/// Firmware tables are valid when all of their bytes sum to zero.
+pub fn checksum(start: usize, len: usize) -> bool {
+    let mut sum = 0u8;
+    for addr in start..start + len {
+        sum += unsafe { *(addr as *const u8) };
+    }
+    sum == 0
+}
+
+/// Scans `[start, start+len)` on 16-byte boundaries for the floating pointer.
+fn scan(start: usize, len: usize) -> Option<&'static FloatingPointer> {
+    let mut addr = start & !0xF;
+    while addr + 16 <= start + len {
+        let fp = unsafe { &*(addr as *const FloatingPointer) };
+        if &fp.signature == b"_MP_" && checksum(addr, fp.length as usize * 16) {
+            return Some(fp);
+        }
+        addr += 16;
+    }
+    None
+}
+
+fn find_pointer() -> Option<&'static FloatingPointer> {
+    unsafe {
+        let ebda = (*(0x40E as *const u16) as usize) << 4;
+        let base_kb = *(0x413 as *const u16) as usize;
+
+        let found = if ebda != 0 { scan(ebda, 1024) } else { None };
+        let found = match found {
+            None => scan(base_kb * 1024 - 1024, 1024),
+            some => some
+        };
+        match found {
+            None => scan(0xF0000, 0x10000),
+            some => some
+        }
+    }
+}
+
+/// Returns the processors and the first IO-APIC listed in the MP
+/// configuration table. ISA interrupts not wired to the same pin of that
+/// IO-APIC become overrides.
+pub fn find() -> Option<Topology> {
+    let fp = match find_pointer() {
+        Some(fp) => fp,
+        // a default configuration is not supported
+        None => return None
+    };
+    if fp.config == 0 {
+        return None;
+    }
+
+    let header = unsafe { &*(fp.config as *const ConfigHeader) };
+    if &header.signature != b"PCMP" || !checksum(fp.config as usize, header.length as usize) {
+        return None;
+    }
+
+    let mut topology = Topology::new(header.lapic_addr as usize);
+    let mut isa_bus = None;
+    let mut entry = fp.config as usize + ::core::mem::size_of::<ConfigHeader>();
+
+    for _ in 0..header.entries {
+        let kind = unsafe { *(entry as *const u8) };
+        entry += match kind {
+            PROCESSOR => {
+                let cpu = unsafe { &*(entry as *const ProcessorEntry) };
+                if cpu.flags & 1 != 0 {
+                    topology.cpus.push(cpu.lapic_id);
+                }
+                20
+            }
+            BUS => {
+                let bus = unsafe { &*(entry as *const BusEntry) };
+                if &bus.name[..3] == b"ISA" {
+                    isa_bus = Some(bus.id);
+                }
+                8
+            }
+            IO_APIC => {
+                let ioapic = unsafe { &*(entry as *const IoApicEntryRaw) };
+                // only the pins of the first IO-APIC get global numbers
+                if ioapic.flags & 1 != 0 && topology.ioapics.is_empty() {
+                    topology.ioapics.push(IoApicEntry {
+                        id: ioapic.id,
+                        addr: ioapic.addr as usize,
+                        gsi_base: 0
+                    });
+                }
+                8
+            }
+            IO_INTERRUPT => {
+                let int = unsafe { &*(entry as *const InterruptEntry) };
+                let first = topology.ioapics.first().map(|io| io.id);
+                if int.int_type == 0 && Some(int.src_bus) == isa_bus
+                        && Some(int.dst_ioapic) == first
+                        && (int.src_irq != int.dst_pin || int.flags != 0) {
+                    topology.overrides.push(Override {
+                        isa_irq: int.src_irq,
+                        gsi: int.dst_pin as u32,
+                        flags: int.flags
+                    });
+                }
+                8
+            }
+            _ => 8
+        };
+    }
+
+    Some(topology)
+}
@@ -0,0 +1,368 @@
+//! PCI configuration space through mechanism #1 (ports 0xCF8 and 0xCFC).
+//!
+//! `init` walks the buses from bus 0 down through PCI-to-PCI bridges,
+//! sizes each function's BARs and reads its interrupt line. Drivers register
+//! the IDs or classes they handle, and their probe function is called for
+//! each matching device not yet bound to a driver.
+
+use core::fmt;
+use core::prelude::*;
+
+use cpu::{io, mmu};
+use kernel::collections::{Box, Vec};
+use kernel::mm::OutOfMemory;
+
+const CONFIG_ADDRESS: u16 = 0xCF8;
+const CONFIG_DATA: u16 = 0xCFC;
+
+// configuration space registers
+const VENDOR_ID: u8 = 0x00;
+const DEVICE_ID: u8 = 0x02;
+const COMMAND: u8 = 0x04;
+const STATUS: u8 = 0x06;
+const REVISION: u8 = 0x08;
+const HEADER_TYPE: u8 = 0x0E;
+const BAR0: u8 = 0x10;
+const SECONDARY_BUS: u8 = 0x19;
+const CAPABILITIES: u8 = 0x34;
+const INTERRUPT_LINE: u8 = 0x3C;
+const INTERRUPT_PIN: u8 = 0x3D;
+
+const NO_DEVICE: u16 = 0xFFFF;
+const MULTI_FUNCTION: u8 = 0x80;
+const HEADER_BRIDGE: u8 = 0x01;
+const STATUS_CAPABILITIES: u16 = 1 << 4;
+
+const CLASS_BRIDGE: u8 = 0x06;
+const SUBCLASS_PCI_BRIDGE: u8 = 0x04;
+
+const BAR_IO: u32 = 1 << 0;
+const BAR_64: u32 = 2 << 1;
+const BAR_PREFETCHABLE: u32 = 1 << 3;
+
+/// Interrupt line value of devices not connected to the interrupt
+/// controller.
+const NO_LINE: u8 = 0xFF;
+
+bitflags!(flags Command: u16 {
+    const IO_SPACE = 1 << 0,
+    const MEMORY_SPACE = 1 << 1,
+    const BUS_MASTER = 1 << 2,
+    const INTX_DISABLE = 1 << 10
+});
+
+/// Where a function is: bus, device slot and function number.
+#[derive(Copy, PartialEq)]
+pub struct Address {
+    pub bus: u8,
+    pub slot: u8,
+    pub func: u8
+}
+
+impl Address {
+    fn config(&self, offset: u8) -> u32 {
+        0x80000000 | (self.bus as u32) << 16 | (self.slot as u32) << 11
+            | (self.func as u32) << 8 | (offset & 0xFC) as u32
+    }
+
+    pub fn read32(&self, offset: u8) -> u32 {
+        io::outl(CONFIG_ADDRESS, self.config(offset));
+        io::inl(CONFIG_DATA)
+    }
+
+    pub fn read16(&self, offset: u8) -> u16 {
+        (self.read32(offset) >> ((offset & 2) * 8) as usize) as u16
+    }
+
+    pub fn read8(&self, offset: u8) -> u8 {
+        (self.read32(offset) >> ((offset & 3) * 8) as usize) as u8
+    }
+
+    pub fn write32(&self, offset: u8, val: u32) {
+        io::outl(CONFIG_ADDRESS, self.config(offset));
+        io::outl(CONFIG_DATA, val);
+    }
+
+    pub fn write16(&self, offset: u8, val: u16) {
+        let shift = ((offset & 2) * 8) as usize;
+        let old = self.read32(offset) & !(0xFFFF << shift);
+        self.write32(offset, old | (val as u32) << shift);
+    }
+}
+
+impl fmt::Show for Address {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:02x}:{:02x}.{}", self.bus, self.slot, self.func)
+    }
+}
+
+#[derive(Copy)]
+pub enum Bar {
+    None,
+    Memory { addr: usize, size: usize, prefetchable: bool },
+    Io { port: u16, size: u16 }
+}
+
+pub struct Device {
+    pub addr: Address,
+    pub vendor: u16,
+    pub device: u16,
+    pub class: u8,
+    pub subclass: u8,
+    pub prog_if: u8,
+    pub revision: u8,
+    pub bars: [Bar; ..6],
+    /// The interrupt line the firmware routed INTx to, for `irq::register`.
+    pub irq: Option<u8>,
+    /// The driver bound to the device.
+    pub driver: Option<&'static str>
+}
+
+impl Device {
+    fn new(addr: Address) -> Device {
+        let class = addr.read32(REVISION);
+        let mut device = Device {
+            addr: addr,
+            vendor: addr.read16(VENDOR_ID),
+            device: addr.read16(DEVICE_ID),
+            class: (class >> 24) as u8,
+            subclass: (class >> 16) as u8,
+            prog_if: (class >> 8) as u8,
+            revision: class as u8,
+            bars: [Bar::None; 6],
+            irq: None,
+            driver: None
+        };
+        device.size_bars();
+        let line = addr.read8(INTERRUPT_LINE);
+        if addr.read8(INTERRUPT_PIN) != 0 && line != NO_LINE && line < 16 {
+            device.irq = Some(line);
+        }
+        device
+    }
+
+    /// Finds the size of each BAR by writing all ones to it and reading back
+    /// which bits stick, with decoding off in the meantime.
+    fn size_bars(&mut self) {
+        let addr = self.addr;
+        // bridges have only two BARs
+        let count = if addr.read8(HEADER_TYPE) & !MULTI_FUNCTION == HEADER_BRIDGE { 2 } else { 6 };
+        let command = addr.read16(COMMAND);
+        addr.write16(COMMAND, command & !(IO_SPACE | MEMORY_SPACE).bits());
+
+        let mut i = 0;
+        while i < count {
+            let offset = BAR0 + i as u8 * 4;
+            let bar = addr.read32(offset);
+            addr.write32(offset, !0);
+            let mask = addr.read32(offset);
+            addr.write32(offset, bar);
+
+            if bar & BAR_IO != 0 {
+                let size = !(mask & !0x3) + 1;
+                if mask != 0 {
+                    self.bars[i] = Bar::Io { port: (bar & !0x3) as u16, size: size as u16 };
+                }
+            } else if mask != 0 {
+                let size = !(mask & !0xF) + 1;
+                let mut base = (bar & !0xF) as usize;
+                let index = i;
+                if bar & BAR_64 != 0 {
+                    // a 64-bit BAR takes the next slot too; above 4 GiB is
+                    // out of reach
+                    i += 1;
+                    if addr.read32(offset + 4) != 0 {
+                        base = 0;
+                    }
+                }
+                if base != 0 {
+                    self.bars[index] = Bar::Memory {
+                        addr: base,
+                        size: size as usize,
+                        prefetchable: bar & BAR_PREFETCHABLE != 0
+                    };
+                }
+            }
+            i += 1;
+        }
+        addr.write16(COMMAND, command);
+    }
+
+    /// Turns on decoding and bus mastering as given.
+    pub fn enable(&self, flags: Command) {
+        let command = self.addr.read16(COMMAND);
+        self.addr.write16(COMMAND, command | flags.bits());
+    }
+
+    /// Maps a memory BAR uncached and returns its address.
+    pub fn map_bar(&self, i: usize) -> Result<*mut u8, OutOfMemory> {
+        match self.bars[i] {
+            Bar::Memory { addr, size, .. } => unsafe { mmu::map_device(addr, size) },
+            _ => Err(OutOfMemory)
+        }
+    }
+
+    /// The base port of an I/O BAR.
+    pub fn io_bar(&self, i: usize) -> Option<u16> {
+        match self.bars[i] {
+            Bar::Io { port, .. } => Some(port),
+            _ => None
+        }
+    }
+
+    /// The offset of the first capability `id` in configuration space.
+    pub fn capability(&self, id: u8) -> Option<u8> {
+        if self.addr.read16(STATUS) & STATUS_CAPABILITIES == 0 {
+            return None;
+        }
+        self.find_capability(self.addr.read8(CAPABILITIES), id)
+    }
+
+    /// The offset of the next capability `id` after the one at `offset`.
+    pub fn next_capability(&self, offset: u8, id: u8) -> Option<u8> {
+        self.find_capability(self.addr.read8(offset + 1), id)
+    }
+
+    fn find_capability(&self, start: u8, id: u8) -> Option<u8> {
+        let mut offset = start & !3;
+        // the list can't be longer than configuration space
+        for _ in 0..48 {
+            if offset == 0 {
+                break;
+            }
+            if self.addr.read8(offset) == id {
+                return Some(offset);
+            }
+            offset = self.addr.read8(offset + 1) & !3;
+        }
+        None
+    }
+}
+
+impl fmt::Show for Device {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        try!(write!(f, "{} {:04x}:{:04x} class {:02x}.{:02x}.{:02x}", self.addr,
+                    self.vendor, self.device, self.class, self.subclass, self.prog_if));
+        match self.irq {
+            Some(irq) => try!(write!(f, " irq {}", irq)),
+            None => {}
+        }
+        match self.driver {
+            Some(name) => write!(f, " [{}]", name),
+            None => Ok(())
+        }
+    }
+}
+
+/// Which devices a driver handles.
+#[derive(Copy)]
+pub enum Match {
+    Id(u16, u16),
+    Class(u8, u8)
+}
+
+impl Match {
+    fn matches(&self, device: &Device) -> bool {
+        match *self {
+            Match::Id(vendor, id) => device.vendor == vendor && device.device == id,
+            Match::Class(class, subclass) => device.class == class && device.subclass == subclass
+        }
+    }
+}
+
+pub struct Driver {
+    pub name: &'static str,
+    pub matches: &'static [Match],
+    /// Sets up a matching device. Returns false if it can't drive it.
+    pub probe: fn(&Device) -> bool
+}
+
+const MAX_DRIVERS: usize = 16;
+static mut drivers: [Option<&'static Driver>; ..MAX_DRIVERS] = [None; MAX_DRIVERS];
+static mut devices: *mut Vec<Device> = 0 as *mut Vec<Device>;
+
+fn scan_bus(bus: u8, found: &mut Vec<Device>) {
+    for slot in 0..32u8 {
+        let addr = Address { bus: bus, slot: slot, func: 0 };
+        if addr.read16(VENDOR_ID) == NO_DEVICE {
+            continue;
+        }
+        let funcs = if addr.read8(HEADER_TYPE) & MULTI_FUNCTION != 0 { 8 } else { 1 };
+        for func in 0..funcs {
+            let addr = Address { bus: bus, slot: slot, func: func };
+            if addr.read16(VENDOR_ID) == NO_DEVICE {
+                continue;
+            }
+            let device = Device::new(addr);
+            if device.class == CLASS_BRIDGE && device.subclass == SUBCLASS_PCI_BRIDGE {
+                let secondary = addr.read8(SECONDARY_BUS);
+                if secondary > bus {
+                    scan_bus(secondary, found);
+                }
+            }
+            found.push(device);
+        }
+    }
+}
+
+/// Enumerates every bus. A multi-function host bridge at 00:00 means one
+/// host controller per function, each with its own bus.
+pub fn init() {
+    let mut found = Vec::new();
+    let host = Address { bus: 0, slot: 0, func: 0 };
+    if host.read8(HEADER_TYPE) & MULTI_FUNCTION == 0 {
+        scan_bus(0, &mut found);
+    } else {
+        for func in 0..8u8 {
+            if (Address { func: func, ..host }).read16(VENDOR_ID) != NO_DEVICE {
+                scan_bus(func, &mut found);
+            }
+        }
+    }
+    unsafe {
+        devices = Box::new(found).into_raw();
+    }
+}
+
+/// The devices found by `init`.
+pub fn devices() -> &'static mut [Device] {
+    unsafe {
+        match devices.as_mut() {
+            Some(found) => found.as_mut_slice(),
+            None => &mut []
+        }
+    }
+}
+
+/// The first device with the given vendor and device IDs.
+pub fn find(vendor: u16, id: u16) -> Option<&'static mut Device> {
+    devices().iter_mut().find(|d| d.vendor == vendor && d.device == id)
+}
+
+fn bind(driver: &'static Driver, device: &mut Device) {
+    if device.driver.is_none() && driver.matches.iter().any(|m| m.matches(device))
+    && (driver.probe)(device) {
+        device.driver = Some(driver.name);
+    }
+}
+
+/// Adds a driver and probes the devices it matches that have none yet.
+/// Returns false when there is no room for more drivers.
+pub fn register(driver: &'static Driver) -> bool {
+    unsafe {
+        match drivers.iter_mut().find(|slot| slot.is_none()) {
+            Some(slot) => *slot = Some(driver),
+            None => return false
+        }
+    }
+    for device in devices().iter_mut() {
+        bind(driver, device);
+    }
+    true
+}
+
+pub fn print_devices() {
+    for device in devices().iter() {
+        println!("pci: {}", device);
+    }
+}
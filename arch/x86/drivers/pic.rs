@@ -2,6 +2,17 @@
 
 use cpu::io;
 
+const MASTER_CMD: u16 = 0x20;
+const MASTER_DATA: u16 = 0x21;
+const SLAVE_CMD: u16 = 0xA0;
+const SLAVE_DATA: u16 = 0xA1;
+
+const EOI: u8 = 0x20;
+const READ_ISR: u8 = 0x0B;
+
+/// The slave PIC is cascaded through this line of the master.
+pub const CASCADE: u8 = 2;
+
 pub fn remap() {
     io::out(0x20, 0x11u16); // WARNING verify should be u16
     io::out(0xA0, 0x11u16);
@@ -23,7 +34,36 @@ pub fn enable(irq: u8) {
     io::out(port, io::inb(port) & mask);
 }
 
+pub fn disable(irq: u8) {
+    let port: u16 = if (irq & 0b1000) == 0 { MASTER_DATA } else { SLAVE_DATA };
+    let bit: u8 = 1u8 << (irq & 0b111) as usize;
+
+    io::out(port, io::inb(port) | bit);
+}
+
 pub fn mask(mask: u16) {
     io::out(0x21, (mask & 0xFF) as u8);
     io::out(0xA1, ((mask >> 8) & 0xFF) as u8);
 }
+
+/// Signals the end of an interrupt. Lines of the slave need both PICs
+/// acknowledged.
+pub fn eoi(irq: u8) {
+    if irq >= 8 {
+        io::out(SLAVE_CMD, EOI);
+    }
+    io::out(MASTER_CMD, EOI);
+}
+
+/// Reads the in-service registers of both PICs.
+pub fn in_service() -> u16 {
+    io::out(MASTER_CMD, READ_ISR);
+    io::out(SLAVE_CMD, READ_ISR);
+    (io::inb(SLAVE_CMD) as u16) << 8 | io::inb(MASTER_CMD) as u16
+}
+
+/// IRQ 7 and 15 fire spuriously when a request goes away before the CPU
+/// acknowledges it. Such interrupts aren't marked as in service.
+pub fn is_spurious(irq: u8) -> bool {
+    (irq == 7 || irq == 15) && in_service() & (1 << irq as usize) == 0
+}
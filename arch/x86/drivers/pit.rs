@@ -0,0 +1,82 @@
+//! Programmable interval timer (8253/8254). Channel 0 ticks on IRQ 0 and
+//! channel 2 serves busy-waits.
+
+use core::atomic::{AtomicUsize, ATOMIC_USIZE_INIT, Ordering};
+use core::intrinsics::{volatile_load, volatile_store};
+
+use cpu::{io, irq};
+
+/// Input clock of all channels.
+pub const PIT_HZ: u32 = 1193182;
+/// Rate of channel 0, and of the local APIC timer when it replaces it.
+pub const HZ: u32 = 1000;
+pub const IRQ: u8 = 0;
+/// Channel 0 counts down from this, so it really runs at
+/// `PIT_HZ / DIVISOR`, a little over `HZ`.
+const DIVISOR: u32 = PIT_HZ / HZ;
+
+const CHANNEL0: u16 = 0x40;
+const CHANNEL2: u16 = 0x42;
+const COMMAND: u16 = 0x43;
+
+static mut ticks: u64 = 0;
+/// Odd while `tick` updates `ticks`, so that readers can retry instead of
+/// seeing half of it.
+static sequence: AtomicUsize = ATOMIC_USIZE_INIT;
+
+/// Starts channel 0 at `HZ` as a rate generator.
+pub fn init() {
+    io::out(COMMAND, 0b00110100u8); // channel 0, low then high byte, mode 2
+    io::out(CHANNEL0, DIVISOR as u8);
+    io::out(CHANNEL0, (DIVISOR >> 8) as u8);
+    irq::register(IRQ, tick);
+}
+
+/// Nanoseconds between ticks of channel 0.
+pub fn period_ns() -> u64 {
+    1000000000 * DIVISOR as u64 / PIT_HZ as u64
+}
+
+fn tick() {
+    sequence.fetch_add(1, Ordering::SeqCst);
+    unsafe {
+        volatile_store(&mut ticks, ticks + 1);
+    }
+    sequence.fetch_add(1, Ordering::SeqCst);
+}
+
+/// Ticks of line 0 since `init`, from channel 0 or the local APIC timer.
+pub fn ticks() -> u64 {
+    // the two halves can change in between, even on other processors
+    loop {
+        let before = sequence.load(Ordering::SeqCst);
+        if before & 1 == 0 {
+            let t = unsafe { volatile_load(&ticks as *const u64) };
+            if sequence.load(Ordering::SeqCst) == before {
+                return t;
+            }
+        }
+    }
+}
+
+/// Busy-waits using channel 2, which works without interrupts.
+pub fn delay(us: u32) {
+    let mut left = us;
+    while left > 0 {
+        // the 16-bit counter lasts up to 54 ms
+        let step = if left > 50000 { 50000 } else { left };
+        let count = PIT_HZ / 1000 * step / 1000;
+        left -= step;
+
+        // gate channel 2 off, speaker off
+        let port61 = io::inb(0x61) & !0b11;
+        io::out(0x61, port61);
+        io::out(COMMAND, 0b10110000u8); // channel 2, low then high byte, mode 0
+        io::out(CHANNEL2, count as u8);
+        io::out(CHANNEL2, (count >> 8) as u8);
+        io::out(0x61, port61 | 1); // start counting
+
+        // wait for the output of channel 2 to go high
+        while io::inb(0x61) & 0x20 == 0 {}
+    }
+}
@@ -0,0 +1,271 @@
+//! The 8042 PS/2 controller, with the keyboard on its first port and the
+//! mouse on its second. Every wait on the controller gives up after about
+//! 100 ms, so a missing or stuck device can't hang the kernel.
+
+use core::fmt;
+use core::prelude::*;
+
+use cpu::io;
+
+const DATA: u16 = 0x60;
+const STATUS: u16 = 0x64;
+const COMMAND: u16 = 0x64;
+
+const OUTPUT_FULL: u8 = 1 << 0;
+const INPUT_FULL: u8 = 1 << 1;
+/// The byte in the output buffer is from the mouse.
+const AUX_DATA: u8 = 1 << 5;
+
+const READ_CONFIG: u8 = 0x20;
+const WRITE_CONFIG: u8 = 0x60;
+const DISABLE_AUX: u8 = 0xA7;
+const ENABLE_AUX: u8 = 0xA8;
+const TEST_AUX: u8 = 0xA9;
+const SELF_TEST: u8 = 0xAA;
+const TEST_KEYBOARD: u8 = 0xAB;
+const DISABLE_KEYBOARD: u8 = 0xAD;
+const ENABLE_KEYBOARD: u8 = 0xAE;
+const WRITE_AUX: u8 = 0xD4;
+
+const SELF_TEST_PASSED: u8 = 0x55;
+const PORT_TEST_PASSED: u8 = 0x00;
+
+const RESET: u8 = 0xFF;
+const RESET_PASSED: u8 = 0xAA;
+pub const ACK: u8 = 0xFA;
+pub const RESEND: u8 = 0xFE;
+const TRIES: usize = 3;
+
+bitflags!(flags Config: u8 {
+    const KEYBOARD_IRQ = 1 << 0,
+    const AUX_IRQ = 1 << 1,
+    const AUX_CLOCK_OFF = 1 << 5,
+    /// Translate the keyboard's scancode set 2 to set 1.
+    const TRANSLATE = 1 << 6
+});
+
+#[derive(Copy, PartialEq)]
+pub enum Port {
+    Keyboard,
+    Mouse
+}
+
+#[derive(Copy)]
+pub enum Error {
+    Timeout,
+    /// The controller or a port failed its self-test with this result.
+    SelfTest(u8),
+    /// A device kept answering a command with this instead of ACK.
+    NoAck(u8)
+}
+
+impl fmt::Show for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Timeout => write!(f, "timeout"),
+            Error::SelfTest(r) => write!(f, "self-test failed ({:02x})", r),
+            Error::NoAck(r) => write!(f, "command not acknowledged ({:02x})", r)
+        }
+    }
+}
+
+/// The ports with a working device after `init`.
+#[derive(Copy)]
+pub struct Ports {
+    pub keyboard: bool,
+    pub mouse: bool
+}
+
+fn wait_write() -> Result<(), Error> {
+    if io::wait(STATUS, INPUT_FULL) { Ok(()) } else { Err(Error::Timeout) }
+}
+
+fn wait_read() -> Result<(), Error> {
+    for _ in 0..io::WAIT_LOOPS {
+        if io::inb(STATUS) & OUTPUT_FULL != 0 {
+            return Ok(());
+        }
+        io::inb(0x80);
+    }
+    Err(Error::Timeout)
+}
+
+/// Waits for a byte from the controller.
+fn read() -> Result<u8, Error> {
+    try!(wait_read());
+    Ok(io::inb(DATA))
+}
+
+/// Waits for a byte from the device on `port`. Bytes from the other device
+/// are dropped meanwhile, so this is only for setting devices up.
+pub fn read_from(port: Port) -> Result<u8, Error> {
+    for _ in 0..io::WAIT_LOOPS {
+        match poll(port) {
+            Some(byte) => return Ok(byte),
+            None => {}
+        }
+        if io::inb(STATUS) & OUTPUT_FULL != 0 {
+            io::inb(DATA);
+        }
+        io::inb(0x80);
+    }
+    Err(Error::Timeout)
+}
+
+/// A byte waiting from the device on `port`, for interrupt handlers.
+pub fn poll(port: Port) -> Option<u8> {
+    let status = io::inb(STATUS);
+    let from_mouse = status & AUX_DATA != 0;
+    if status & OUTPUT_FULL != 0 && from_mouse == (port == Port::Mouse) {
+        Some(io::inb(DATA))
+    } else {
+        None
+    }
+}
+
+/// Drops the bytes waiting in the output buffer. A device that keeps
+/// sending is given up on after `io::WAIT_LOOPS` bytes.
+fn flush() {
+    for _ in 0..io::WAIT_LOOPS {
+        if io::inb(STATUS) & OUTPUT_FULL == 0 {
+            return;
+        }
+        io::inb(DATA);
+    }
+}
+
+fn command(cmd: u8) -> Result<(), Error> {
+    try!(wait_write());
+    io::out(COMMAND, cmd);
+    Ok(())
+}
+
+fn command_read(cmd: u8) -> Result<u8, Error> {
+    try!(command(cmd));
+    read()
+}
+
+fn write_config(config: Config) -> Result<(), Error> {
+    try!(command(WRITE_CONFIG));
+    try!(wait_write());
+    io::out(DATA, config.bits());
+    Ok(())
+}
+
+/// Sends a byte to the device on `port` without waiting for its reply,
+/// which arrives like any other byte from the device.
+pub fn write(port: Port, byte: u8) -> Result<(), Error> {
+    if port == Port::Mouse {
+        try!(command(WRITE_AUX));
+    }
+    try!(wait_write());
+    io::out(DATA, byte);
+    Ok(())
+}
+
+/// Sends a byte to the device on `port` and waits for its ACK, repeating
+/// it when the device asks to.
+pub fn send(port: Port, byte: u8) -> Result<(), Error> {
+    let mut reply = 0;
+    for _ in 0..TRIES {
+        try!(write(port, byte));
+        reply = try!(read_from(port));
+        if reply == ACK {
+            return Ok(());
+        }
+        if reply != RESEND {
+            break;
+        }
+    }
+    Err(Error::NoAck(reply))
+}
+
+fn test(cmd: u8) -> Result<(), Error> {
+    match try!(command_read(cmd)) {
+        PORT_TEST_PASSED => Ok(()),
+        r => Err(Error::SelfTest(r))
+    }
+}
+
+/// Resets the device on `port`. The mouse also sends its ID afterwards.
+fn reset(port: Port) -> Result<(), Error> {
+    try!(send(port, RESET));
+    match try!(read_from(port)) {
+        RESET_PASSED => {}
+        r => return Err(Error::SelfTest(r))
+    }
+    if port == Port::Mouse {
+        try!(read_from(port));
+    }
+    Ok(())
+}
+
+/// Tests the controller and its ports, resets the devices found, and turns
+/// on the interrupts of the ports that work. On failure, the keyboard is
+/// given back as the BIOS left it, as far as the controller still listens.
+pub fn init() -> Result<Ports, Error> {
+    let mut original = None;
+    match setup(&mut original) {
+        Ok(ports) => Ok(ports),
+        Err(e) => {
+            match original {
+                Some(config) => { let _ = write_config(config); }
+                None => {}
+            }
+            let _ = command(ENABLE_KEYBOARD);
+            Err(e)
+        }
+    }
+}
+
+/// `init`, recording the configuration it found in `original`.
+fn setup(original: &mut Option<Config>) -> Result<Ports, Error> {
+    try!(command(DISABLE_KEYBOARD));
+    try!(command(DISABLE_AUX));
+    flush();
+
+    let mut config = Config::from_bits_truncate(try!(command_read(READ_CONFIG)));
+    *original = Some(config);
+    config.remove(KEYBOARD_IRQ | AUX_IRQ);
+    // the keyboard driver decodes set 1
+    config.insert(TRANSLATE);
+    try!(write_config(config));
+
+    match try!(command_read(SELF_TEST)) {
+        SELF_TEST_PASSED => {}
+        r => return Err(Error::SelfTest(r))
+    }
+    // the self-test may reset the controller
+    try!(write_config(config));
+
+    // the second port's clock turns on with it only if the port exists
+    try!(command(ENABLE_AUX));
+    let dual = !Config::from_bits_truncate(try!(command_read(READ_CONFIG))).contains(AUX_CLOCK_OFF);
+    try!(command(DISABLE_AUX));
+
+    let mut ports = Ports {
+        keyboard: test(TEST_KEYBOARD).is_ok(),
+        mouse: dual && test(TEST_AUX).is_ok()
+    };
+
+    if ports.keyboard {
+        try!(command(ENABLE_KEYBOARD));
+        match reset(Port::Keyboard) {
+            Ok(()) => config.insert(KEYBOARD_IRQ),
+            Err(e) => {
+                println!("ps2: keyboard reset: {}", e);
+                ports.keyboard = false;
+            }
+        }
+    }
+    if ports.mouse {
+        try!(command(ENABLE_AUX));
+        match reset(Port::Mouse) {
+            Ok(()) => config.insert(AUX_IRQ),
+            Err(_) => ports.mouse = false
+        }
+    }
+    flush();
+    try!(write_config(config));
+    Ok(ports)
+}
@@ -0,0 +1,68 @@
+//! PC Screen Font, the bitmap font format of the Linux console. Both
+//! versions are read; Unicode tables are ignored and glyphs are indexed by
+//! byte.
+
+use core::prelude::*;
+use core::mem::transmute;
+use core::num::Int;
+use core::raw;
+
+const PSF1_MAGIC: [u8; ..2] = [0x36, 0x04];
+const PSF1_MODE512: u8 = 0x01;
+const PSF2_MAGIC: [u8; ..4] = [0x72, 0xb5, 0x4a, 0x86];
+
+#[derive(Copy)]
+pub struct Font {
+    glyphs: *const u8,
+    count: usize,
+    /// Bytes per glyph.
+    size: usize,
+    pub width: usize,
+    pub height: usize
+}
+
+fn le32(data: &[u8], at: usize) -> usize {
+    data[at] as usize | (data[at + 1] as usize) << 8
+        | (data[at + 2] as usize) << 16 | (data[at + 3] as usize) << 24
+}
+
+impl Font {
+    /// Reads the header of a PSF file. Returns `None` if it isn't one or if
+    /// the glyphs don't fit in `data`.
+    pub fn new(data: &'static [u8]) -> Option<Font> {
+        let (header, count, size, width, height) =
+            if data.len() >= 4 && data[0] == PSF1_MAGIC[0] && data[1] == PSF1_MAGIC[1] {
+                let count = if data[2] & PSF1_MODE512 != 0 { 512 } else { 256 };
+                (4, count, data[3] as usize, 8, data[3] as usize)
+            } else if data.len() >= 32 && &data[..4] == &PSF2_MAGIC[..] {
+                (le32(data, 8), le32(data, 16), le32(data, 20), le32(data, 28), le32(data, 24))
+            } else {
+                return None;
+            };
+        // the PSF2 fields come from the file and can be anything
+        let bitmap = (width / 8 + if width % 8 != 0 { 1 } else { 0 }).checked_mul(height);
+        let end = count.checked_mul(size).and_then(|glyphs| glyphs.checked_add(header));
+        match (bitmap, end) {
+            (Some(bitmap), Some(end)) if count > 0 && size >= bitmap && end <= data.len() => {}
+            _ => return None
+        }
+        Some(Font {
+            glyphs: unsafe { data.as_ptr().offset(header as isize) },
+            count: count,
+            size: size,
+            width: width,
+            height: height
+        })
+    }
+
+    /// The bitmap of glyph `c`, `height` lines of `(width + 7) / 8` bytes.
+    pub fn glyph(&self, c: u8) -> &'static [u8] {
+        let index = if (c as usize) < self.count { c as usize } else { 0 };
+        unsafe {
+            transmute(raw::Slice {
+                data: self.glyphs.offset((index * self.size) as isize),
+                len: self.size
+            })
+        }
+    }
+}
@@ -0,0 +1,92 @@
+//! CMOS real-time clock.
+
+use cpu::io;
+use kernel::time::DateTime;
+use super::acpi;
+
+const INDEX: u16 = 0x70;
+const DATA: u16 = 0x71;
+
+const SECONDS: u8 = 0x00;
+const MINUTES: u8 = 0x02;
+const HOURS: u8 = 0x04;
+const DAY: u8 = 0x07;
+const MONTH: u8 = 0x08;
+const YEAR: u8 = 0x09;
+const STATUS_A: u8 = 0x0A;
+const STATUS_B: u8 = 0x0B;
+
+const UPDATE_IN_PROGRESS: u8 = 1 << 7;
+const HOURS_24: u8 = 1 << 1;
+const BINARY: u8 = 1 << 2;
+const PM: u8 = 1 << 7;
+
+/// Reads a CMOS register.
+pub fn read_reg(reg: u8) -> u8 {
+    // bit 7 keeps NMIs enabled
+    io::out(INDEX, reg & 0x7F);
+    io::inb(DATA)
+}
+
+fn read_raw(century: u8) -> [u8; ..7] {
+    // an update takes about 2 ms; a broken clock could report one forever
+    for _ in 0..io::WAIT_LOOPS {
+        if read_reg(STATUS_A) & UPDATE_IN_PROGRESS == 0 {
+            break;
+        }
+        io::inb(0x80);
+    }
+    [read_reg(SECONDS), read_reg(MINUTES), read_reg(HOURS), read_reg(DAY),
+     read_reg(MONTH), read_reg(YEAR), if century != 0 { read_reg(century) } else { 0 }]
+}
+
+fn from_bcd(x: u8) -> u8 {
+    (x >> 4) * 10 + (x & 0xF)
+}
+
+/// Reads the date and time, which the BIOS keeps in UTC on most machines.
+pub fn read() -> DateTime {
+    // the FADT tells where the century is kept, if anywhere
+    let century = match acpi::fadt() {
+        Some(fadt) => fadt.century,
+        None => 0
+    };
+
+    // read until two reads agree, to avoid a torn update
+    let mut raw = read_raw(century);
+    for _ in 0..10u8 {
+        let again = read_raw(century);
+        if again == raw {
+            break;
+        }
+        raw = again;
+    }
+
+    let status = read_reg(STATUS_B);
+    let pm = raw[2] & PM != 0;
+    raw[2] &= !PM;
+    if status & BINARY == 0 {
+        for x in raw.iter_mut() {
+            *x = from_bcd(*x);
+        }
+    }
+    let mut hour = raw[2];
+    if status & HOURS_24 == 0 {
+        hour = hour % 12 + if pm { 12 } else { 0 };
+    }
+    let year = if century != 0 { raw[6] as u16 * 100 } else { 2000 } + raw[5] as u16;
+
+    DateTime {
+        year: year,
+        month: raw[4],
+        day: raw[3],
+        hour: hour,
+        minute: raw[1],
+        second: raw[0]
+    }
+}
+
+/// Seconds since the Unix epoch.
+pub fn now() -> u64 {
+    read().to_unix()
+}
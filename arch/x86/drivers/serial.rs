@@ -0,0 +1,22 @@
+//! 16550 UART on the first serial port. QEMU's `-serial stdio` shows its
+//! output on the host.
+
+use cpu::io;
+
+const COM1: u16 = 0x3F8;
+
+pub fn init() {
+    io::out(COM1 + 1, 0x00u8); // disable interrupts
+    io::out(COM1 + 3, 0x80u8); // enable DLAB to set the baud rate divisor
+    io::out(COM1 + 0, 0x03u8); // divisor 3: 38400 baud
+    io::out(COM1 + 1, 0x00u8);
+    io::out(COM1 + 3, 0x03u8); // 8 bits, no parity, one stop bit
+    io::out(COM1 + 2, 0xC7u8); // enable and clear FIFOs, 14-byte threshold
+    io::out(COM1 + 4, 0x03u8); // DTR, RTS
+}
+
+pub fn putc(c: u8) {
+    // wait until the transmitter holding register is empty
+    while io::inb(COM1 + 5) & 0x20 == 0 {}
+    io::out(COM1, c);
+}
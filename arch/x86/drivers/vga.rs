@@ -1,9 +1,20 @@
+//! VGA text mode and the console drawn on it.
+//!
+//! The console keeps its lines in a ring buffer larger than the screen, so
+//! that lines scrolled off the top can be viewed again. Several consoles can
+//! exist at once; only the visible one draws to the screen.
+
 use core::mem::transmute;
+use core::prelude::*;
 
 use cpu::io;
-use platform::runtime::wmemset;
+use kernel::heap;
+use platform::runtime::{memcpy, wmemset};
+use super::{ansi, fbcon};
+use super::ansi::{Action, Csi};
 
 #[repr(u8)]
+#[derive(Copy, PartialEq)]
 pub enum Color {
     Black       = 0,
     Blue        = 1,
@@ -23,7 +34,16 @@ pub enum Color {
     White       = 15,
 }
 
+/// VGA colours in the order of ANSI colour codes 0 to 7, normal then bright.
+static ANSI_COLORS: [Color; ..16] = [
+    Color::Black, Color::Red, Color::Green, Color::Brown,
+    Color::Blue, Color::Pink, Color::Cyan, Color::LightGray,
+    Color::DarkGray, Color::LightRed, Color::LightGreen, Color::Yellow,
+    Color::LightBlue, Color::LightPink, Color::LightCyan, Color::White
+];
+
 #[repr(packed)]
+#[derive(Copy)]
 struct Char {
     pub char: u8,
     attr: u8,
@@ -36,19 +56,406 @@ impl Char {
     }
 }
 
-pub const SCREEN_SIZE: usize = 80*25;
+pub const WIDTH: usize = 80;
+pub const HEIGHT: usize = 25;
+pub const SCREEN_SIZE: usize = WIDTH * HEIGHT;
 type Screen = [Char; ..SCREEN_SIZE];
 pub static SCREEN: *mut Screen = 0xb8000 as *mut Screen;
 
+/// Lines kept above the screen.
+const SCROLLBACK: usize = 200;
+const LINES: usize = HEIGHT + SCROLLBACK;
+const TAB: usize = 4;
+
 pub fn clear_screen(bg: Color) {
     unsafe {
         wmemset(SCREEN as *mut u8, transmute(Char::new(' ', Color::Black, bg)), SCREEN_SIZE);
     }
+    redraw_graphics(0, SCREEN_SIZE);
+}
+
+/// Where `write_raw` puts the next character, and its background.
+static mut raw_pos: usize = 0;
+static mut raw_bg: Color = Color::Black;
+
+/// Writes to the text screen without a console, for messages from before
+/// the consoles exist. Starts over at the top once the screen is full.
+pub fn write_raw(c: u8) {
+    unsafe {
+        match c {
+            b'\n' => raw_pos += WIDTH - raw_pos % WIDTH,
+            b'\r' => raw_pos -= raw_pos % WIDTH,
+            _ => {
+                set_cell(raw_pos, Char::new(c as char, Color::LightGray, raw_bg));
+                raw_pos += 1;
+            }
+        }
+        if raw_pos >= SCREEN_SIZE {
+            raw_pos = 0;
+        }
+        cursor_at(raw_pos);
+    }
+}
+
+/// Clears the screen for `write_raw`.
+pub fn clear_raw(bg: Color) {
+    clear_screen(bg);
+    unsafe {
+        raw_pos = 0;
+        raw_bg = bg;
+    }
+    cursor_at(0);
+}
+
+/// The byte for `c` in code page 437, the character set of the text screen.
+/// Characters it lacks become a close one, such as the letter without its
+/// accent, or else `?`.
+pub fn encode(c: char) -> u8 {
+    if (c as u32) < 0x80 {
+        return c as u8;
+    }
+    match c {
+        'Ç' => 0x80, 'ü' => 0x81, 'é' => 0x82, 'â' => 0x83, 'ä' => 0x84, 'à' => 0x85,
+        'å' => 0x86, 'ç' => 0x87, 'ê' => 0x88, 'ë' => 0x89, 'è' => 0x8A, 'ï' => 0x8B,
+        'î' => 0x8C, 'ì' => 0x8D, 'Ä' => 0x8E, 'Å' => 0x8F, 'É' => 0x90, 'æ' => 0x91,
+        'Æ' => 0x92, 'ô' => 0x93, 'ö' => 0x94, 'ò' => 0x95, 'û' => 0x96, 'ù' => 0x97,
+        'ÿ' => 0x98, 'Ö' => 0x99, 'Ü' => 0x9A, '¢' => 0x9B, '£' => 0x9C, '¥' => 0x9D,
+        'á' => 0xA0, 'í' => 0xA1, 'ó' => 0xA2, 'ú' => 0xA3, 'ñ' => 0xA4, 'Ñ' => 0xA5,
+        '¿' => 0xA8, '¬' => 0xAA, '½' => 0xAB, '¼' => 0xAC, '¡' => 0xAD, '«' => 0xAE,
+        '»' => 0xAF, 'ß' => 0xE1, 'µ' => 0xE6, '±' => 0xF1, '°' => 0xF8, '·' => 0xFA,
+        '²' => 0xFD, '§' => 0x15, '¶' => 0x14,
+        'À' | 'Á' | 'Â' | 'Ã' => b'A', 'È' | 'Ê' | 'Ë' => b'E', 'Ì' | 'Í' | 'Î' | 'Ï' => b'I',
+        'Ò' | 'Ó' | 'Ô' | 'Õ' => b'O', 'Ù' | 'Ú' | 'Û' => b'U', 'Ý' => b'Y',
+        'ã' => b'a', 'õ' => b'o', 'ý' => b'y',
+        '´' => b'\'', '¨' => b'"', '³' => b'3', '€' => b'E',
+        _ => b'?'
+    }
+}
+
+/// The character and attribute at cell `pos` of the screen.
+pub fn cell(pos: usize) -> (u8, u8) {
+    let c = unsafe { (*SCREEN)[pos] };
+    (c.char, c.attr)
+}
+
+fn set_cell(pos: usize, c: Char) {
+    unsafe {
+        (*SCREEN)[pos] = c;
+    }
+    fbcon::draw(pos, c.char, c.attr);
+}
+
+/// Draws cells `from..to` of the text screen on the framebuffer, if the
+/// console is in graphics mode.
+fn redraw_graphics(from: usize, to: usize) {
+    if fbcon::enabled() {
+        for pos in from..to {
+            let (c, attr) = cell(pos);
+            fbcon::draw(pos, c, attr);
+        }
+    }
 }
 
 pub fn cursor_at(pos: usize) {
+    fbcon::cursor_at(pos);
     io::out(0x3D4, 15u16); // WARNING verify should be u16
     io::out(0x3D5, pos as u8);
     io::out(0x3D4, 14u16);
     io::out(0x3D5, (pos >> 8) as u8);
 }
+
+pub struct Console {
+    /// `LINES` lines of `WIDTH` cells.
+    lines: *mut Char,
+    /// Ring index of the first line of the screen.
+    top: usize,
+    /// Lines of history above the screen.
+    history: usize,
+    /// Lines the view is scrolled back by.
+    view: usize,
+    /// Whether this console owns the screen.
+    visible: bool,
+    row: usize,
+    col: usize,
+    saved: (usize, usize),
+    fg: Color,
+    bg: Color,
+    default_fg: Color,
+    default_bg: Color,
+    parser: ansi::Parser
+}
+
+impl Console {
+    pub fn new() -> Console {
+        let mut console = Console {
+            lines: unsafe { heap::alloc::<Char>(LINES * WIDTH) },
+            top: 0,
+            history: 0,
+            view: 0,
+            visible: false,
+            row: 0,
+            col: 0,
+            saved: (0, 0),
+            fg: Color::LightGray,
+            bg: Color::Black,
+            default_fg: Color::LightGray,
+            default_bg: Color::Black,
+            parser: ansi::Parser::new()
+        };
+        console.clear();
+        console
+    }
+
+    #[inline]
+    fn blank(&self) -> Char {
+        Char::new(' ', self.fg, self.bg)
+    }
+
+    /// The cells of a line of the screen, `row` lines below its top.
+    fn line(&self, row: usize) -> *mut Char {
+        unsafe { self.lines.offset((((self.top + row) % LINES) * WIDTH) as isize) }
+    }
+
+    fn clear_cells(&mut self, row: usize, from: usize, to: usize) {
+        let blank = self.blank();
+        unsafe {
+            wmemset(self.line(row).offset(from as isize) as *mut u8, transmute(blank), to - from);
+        }
+    }
+
+    /// Copies the lines in view to the screen.
+    fn redraw(&self) {
+        if !self.visible {
+            return;
+        }
+        for row in 0..HEIGHT {
+            // `view` lines back from the first line of the screen
+            let line = (self.top + LINES - self.view + row) % LINES;
+            unsafe {
+                memcpy((SCREEN as *mut Char).offset((row * WIDTH) as isize) as *mut u8,
+                       self.lines.offset((line * WIDTH) as isize) as *const u8, WIDTH * 2);
+            }
+        }
+        redraw_graphics(0, SCREEN_SIZE);
+        self.update_cursor();
+    }
+
+    fn update_cursor(&self) {
+        if !self.visible {
+            return;
+        }
+        if self.view == 0 {
+            cursor_at(self.row * WIDTH + self.col);
+        } else {
+            // past the end of the screen hides it
+            cursor_at(SCREEN_SIZE);
+        }
+    }
+
+    /// Gives the screen to this console and draws it.
+    pub fn show(&mut self) {
+        self.visible = true;
+        self.redraw();
+    }
+
+    /// Stops drawing to the screen, for another console to take it.
+    pub fn hide(&mut self) {
+        self.visible = false;
+    }
+
+    pub fn default_fg(&self) -> Color {
+        self.default_fg
+    }
+
+    /// Sets the colours used after a reset and by `clear`.
+    pub fn set_default(&mut self, fg: Color, bg: Color) {
+        self.default_fg = fg;
+        self.default_bg = bg;
+        self.fg = fg;
+        self.bg = bg;
+    }
+
+    /// Clears the screen in the default colours, keeping the history.
+    pub fn clear(&mut self) {
+        self.fg = self.default_fg;
+        self.bg = self.default_bg;
+        for row in 0..HEIGHT {
+            self.clear_cells(row, 0, WIDTH);
+        }
+        self.row = 0;
+        self.col = 0;
+        self.view = 0;
+        self.redraw();
+    }
+
+    fn scroll(&mut self) {
+        self.top = (self.top + 1) % LINES;
+        if self.history < SCROLLBACK {
+            self.history += 1;
+        }
+        self.clear_cells(HEIGHT - 1, 0, WIDTH);
+        self.redraw();
+    }
+
+    fn newline(&mut self) {
+        self.col = 0;
+        if self.row + 1 < HEIGHT {
+            self.row += 1;
+        } else {
+            self.scroll();
+        }
+    }
+
+    fn put(&mut self, c: u8) {
+        if self.col >= WIDTH {
+            self.newline();
+        }
+        let cell = Char { char: c, attr: self.fg as u8 | ((self.bg as u8) << 4) };
+        unsafe {
+            *self.line(self.row).offset(self.col as isize) = cell;
+        }
+        if self.visible {
+            set_cell(self.row * WIDTH + self.col, cell);
+        }
+        self.col += 1;
+    }
+
+    fn control(&mut self, c: u8) {
+        match c {
+            b'\n' => self.newline(),
+            b'\r' => self.col = 0,
+            // ¶ and §, which `encode` puts among the control codes
+            0x14 | 0x15 => self.put(c),
+            b'\t' => {
+                self.col = (self.col / TAB + 1) * TAB;
+                if self.col >= WIDTH {
+                    self.newline();
+                }
+            }
+            // backspace erases, as on the console's own line editor
+            0x08 => {
+                if self.col > 0 {
+                    self.col -= 1;
+                } else if self.row > 0 {
+                    self.row -= 1;
+                    self.col = WIDTH - 1;
+                }
+                let col = self.col;
+                self.clear_cells(self.row, col, col + 1);
+                if self.visible {
+                    let cell = unsafe { *self.line(self.row).offset(col as isize) };
+                    set_cell(self.row * WIDTH + col, cell);
+                }
+            }
+            _ => {}
+        }
+    }
+
+    fn sgr(&mut self, csi: &Csi) {
+        let count = if csi.count() == 0 { 1 } else { csi.count() };
+        for i in 0..count {
+            match csi.param(i, 0) {
+                0 => {
+                    self.fg = self.default_fg;
+                    self.bg = self.default_bg;
+                }
+                // bold shows as the bright variant
+                1 => self.fg = unsafe { transmute(self.fg as u8 | 8) },
+                22 => self.fg = unsafe { transmute(self.fg as u8 & 7) },
+                n @ 30...37 => self.fg = ANSI_COLORS[n as usize - 30],
+                39 => self.fg = self.default_fg,
+                n @ 40...47 => self.bg = ANSI_COLORS[n as usize - 40],
+                49 => self.bg = self.default_bg,
+                n @ 90...97 => self.fg = ANSI_COLORS[n as usize - 90 + 8],
+                n @ 100...107 => self.bg = ANSI_COLORS[n as usize - 100 + 8],
+                _ => {}
+            }
+        }
+    }
+
+    fn csi(&mut self, csi: &Csi) {
+        let n = csi.param(0, 1) as usize;
+        match csi.command {
+            b'A' => self.row = if self.row > n { self.row - n } else { 0 },
+            b'B' => self.row = if self.row + n < HEIGHT { self.row + n } else { HEIGHT - 1 },
+            b'C' => self.col = if self.col + n < WIDTH { self.col + n } else { WIDTH - 1 },
+            b'D' => self.col = if self.col > n { self.col - n } else { 0 },
+            b'H' | b'f' => {
+                let row = csi.param(0, 1) as usize;
+                let col = csi.param(1, 1) as usize;
+                self.row = if row <= HEIGHT { row - 1 } else { HEIGHT - 1 };
+                self.col = if col <= WIDTH { col - 1 } else { WIDTH - 1 };
+            }
+            b'J' => {
+                let (row, col) = (self.row, self.col);
+                match csi.param(0, 0) {
+                    0 => {
+                        self.clear_cells(row, col, WIDTH);
+                        for r in row + 1..HEIGHT {
+                            self.clear_cells(r, 0, WIDTH);
+                        }
+                    }
+                    1 => {
+                        for r in 0..row {
+                            self.clear_cells(r, 0, WIDTH);
+                        }
+                        self.clear_cells(row, 0, col + 1);
+                    }
+                    _ => for r in 0..HEIGHT {
+                        self.clear_cells(r, 0, WIDTH);
+                    }
+                }
+                self.redraw();
+            }
+            b'K' => {
+                let (row, col) = (self.row, self.col);
+                match csi.param(0, 0) {
+                    0 => self.clear_cells(row, col, WIDTH),
+                    1 => self.clear_cells(row, 0, col + 1),
+                    _ => self.clear_cells(row, 0, WIDTH)
+                }
+                self.redraw();
+            }
+            b'm' => self.sgr(csi),
+            b's' => self.saved = (self.row, self.col),
+            b'u' => {
+                let (row, col) = self.saved;
+                self.row = row;
+                self.col = col;
+            }
+            _ => {}
+        }
+    }
+
+    /// Writes a byte, interpreting control characters and escape sequences.
+    pub fn putc(&mut self, c: u8) {
+        if self.view != 0 {
+            // output returns the view to the bottom
+            self.view = 0;
+            self.redraw();
+        }
+        match self.parser.feed(c) {
+            Action::Print(c) => self.put(c),
+            Action::Control(c) => self.control(c),
+            Action::Csi(ref csi) => self.csi(csi),
+            Action::Reset => {
+                self.parser = ansi::Parser::new();
+                self.clear();
+            }
+            Action::Pending => {}
+        }
+        self.update_cursor();
+    }
+
+    /// Moves the view `n` lines into the history.
+    pub fn scroll_back(&mut self, n: usize) {
+        self.view = if self.view + n < self.history { self.view + n } else { self.history };
+        self.redraw();
+    }
+
+    /// Moves the view `n` lines back towards the bottom.
+    pub fn scroll_forward(&mut self, n: usize) {
+        self.view = if self.view > n { self.view - n } else { 0 };
+        self.redraw();
+    }
+}
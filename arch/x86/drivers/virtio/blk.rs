@@ -0,0 +1,216 @@
+//! Virtio block devices. One request is in flight at a time; the driver
+//! polls the queue for its completion, so it works with interrupts off.
+//!
+//! Data goes through a bounce buffer in DMA memory, since the buffers
+//! callers pass can be anywhere.
+
+use core::cmp::min;
+use core::prelude::*;
+use core::ptr;
+
+use cpu;
+use kernel::block;
+use kernel::block::{BlockDevice, Error};
+use kernel::heap;
+use kernel::time::Timeout;
+use super::{dma_alloc, Buffer, Transport, Virtqueue, VENDOR};
+use super::super::pci;
+
+// features
+const F_RO: u64 = 1 << 5;
+const F_FLUSH: u64 = 1 << 9;
+
+// device configuration
+const CONFIG_CAPACITY: usize = 0;
+
+// request types
+const T_IN: u32 = 0;
+const T_OUT: u32 = 1;
+const T_FLUSH: u32 = 4;
+
+const S_OK: u8 = 0;
+
+/// Capacity is always counted in 512-byte sectors.
+const SECTOR_SIZE: usize = 512;
+const BOUNCE_SIZE: usize = 0x8000;
+/// The status byte follows the request header in the same page.
+const HEADER_SIZE: usize = 16;
+const REQUEST_TIMEOUT_MS: u32 = 5000;
+
+static NAMES: [&'static str; ..4] = ["vda", "vdb", "vdc", "vdd"];
+static mut count: usize = 0;
+
+pub static DRIVER: pci::Driver = pci::Driver {
+    name: "virtio-blk",
+    matches: &[pci::Match::Id(VENDOR, 0x1001), pci::Match::Id(VENDOR, 0x1042)],
+    probe: probe
+};
+
+#[repr(C)]
+struct Header {
+    kind: u32,
+    reserved: u32,
+    sector: u64
+}
+
+pub struct Disk {
+    name: &'static str,
+    transport: Transport,
+    queue: Virtqueue,
+    /// The request header, then the status byte.
+    request: *mut u8,
+    bounce: *mut u8,
+    sectors: u64,
+    read_only: bool,
+    flush: bool
+}
+
+impl Disk {
+    /// Sends a request of `len` bytes through the bounce buffer and waits
+    /// for the device to answer.
+    fn request(&mut self, kind: u32, sector: u64, len: usize) -> Result<(), Error> {
+        unsafe {
+            ptr::write(self.request as *mut Header, Header { kind: kind, reserved: 0, sector: sector });
+            *self.request.offset(HEADER_SIZE as isize) = 0xFF;
+        }
+        let header = Buffer { addr: self.request, len: HEADER_SIZE, writable: false };
+        let data = Buffer { addr: self.bounce, len: len, writable: kind == T_IN };
+        let status = Buffer {
+            addr: unsafe { self.request.offset(HEADER_SIZE as isize) },
+            len: 1,
+            writable: true
+        };
+        let added = if len == 0 {
+            self.queue.add(&[header, status])
+        } else {
+            self.queue.add(&[header, data, status])
+        };
+        if added.is_none() {
+            return Err(Error::Io);
+        }
+        self.transport.notify(&self.queue);
+
+        let timeout = Timeout::ms(REQUEST_TIMEOUT_MS);
+        while !self.queue.has_used() {
+            if timeout.expired() {
+                return Err(Error::Timeout);
+            }
+            cpu::relax();
+        }
+        self.queue.pop();
+        match unsafe { *self.request.offset(HEADER_SIZE as isize) } {
+            S_OK => Ok(()),
+            _ => Err(Error::Io)
+        }
+    }
+}
+
+impl BlockDevice for Disk {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn sector_size(&self) -> usize {
+        SECTOR_SIZE
+    }
+
+    fn sectors(&self) -> u64 {
+        self.sectors
+    }
+
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), Error> {
+        try!(self.check(lba, buf.len()));
+        let mut done = 0;
+        while done < buf.len() {
+            let len = min(buf.len() - done, BOUNCE_SIZE);
+            try!(self.request(T_IN, lba + (done / SECTOR_SIZE) as u64, len));
+            unsafe {
+                ptr::copy_nonoverlapping(buf[done..].as_mut_ptr(), self.bounce as *const u8, len);
+            }
+            done += len;
+        }
+        Ok(())
+    }
+
+    fn write(&mut self, lba: u64, buf: &[u8]) -> Result<(), Error> {
+        try!(self.check(lba, buf.len()));
+        if self.read_only {
+            return Err(Error::ReadOnly);
+        }
+        let mut done = 0;
+        while done < buf.len() {
+            let len = min(buf.len() - done, BOUNCE_SIZE);
+            unsafe {
+                ptr::copy_nonoverlapping(self.bounce, buf[done..].as_ptr(), len);
+            }
+            try!(self.request(T_OUT, lba + (done / SECTOR_SIZE) as u64, len));
+            done += len;
+        }
+        if self.flush {
+            try!(self.request(T_FLUSH, 0, 0));
+        }
+        Ok(())
+    }
+}
+
+fn probe(device: &pci::Device) -> bool {
+    let name = match NAMES.get(unsafe { count }) {
+        Some(&name) => name,
+        None => return false
+    };
+    let transport = match Transport::new(device) {
+        Ok(transport) => transport,
+        Err(e) => {
+            println!("virtio-blk: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let features = match transport.init(F_RO | F_FLUSH) {
+        Ok(features) => features,
+        Err(e) => {
+            println!("virtio-blk: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let mut queue = match transport.setup_queue(0) {
+        Ok(queue) => queue,
+        Err(e) => {
+            transport.fail();
+            println!("virtio-blk: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    queue.disable_interrupts();
+    let (request, bounce) = match (dma_alloc(HEADER_SIZE + 1), dma_alloc(BOUNCE_SIZE)) {
+        (Ok(request), Ok(bounce)) => (request, bounce),
+        _ => {
+            transport.fail();
+            println!("virtio-blk: {}: out of memory", device.addr);
+            return false;
+        }
+    };
+    transport.ready();
+
+    let disk = Disk {
+        name: name,
+        transport: transport,
+        queue: queue,
+        request: request,
+        bounce: bounce,
+        sectors: transport.config64(CONFIG_CAPACITY),
+        read_only: features & F_RO != 0,
+        flush: features & F_FLUSH != 0
+    };
+    println!("virtio-blk: {} at {}, {} sectors{}{}", name, device.addr, disk.sectors,
+             if disk.read_only { ", read-only" } else { "" },
+             if transport.is_modern() { "" } else { ", legacy" });
+    unsafe {
+        count += 1;
+        let ptr = heap::alloc::<Disk>(1);
+        ptr::write(ptr, disk);
+        if block::register(&mut *ptr).is_none() {
+            println!("virtio-blk: too many block devices");
+        }
+    }
+    true
+}
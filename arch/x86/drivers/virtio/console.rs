@@ -0,0 +1,199 @@
+//! The virtio console: a copy of the kernel log goes out on its port, and
+//! what comes in is typed on the terminal on screen, like the keyboard.
+//!
+//! Only the first port of the first device is used.
+
+use core::atomic::{AtomicBool, ATOMIC_BOOL_INIT, Ordering};
+use core::prelude::*;
+
+use cpu;
+use cpu::irq;
+use platform::io;
+use super::{dma_alloc, Buffer, Transport, Virtqueue, VENDOR};
+use super::super::{keydown, pci, vt};
+
+const RECEIVE: u16 = 0;
+const TRANSMIT: u16 = 1;
+
+const RX_BUFFERS: usize = 16;
+const RX_SIZE: usize = 64;
+const TX_SIZE: usize = 128;
+
+pub static DRIVER: pci::Driver = pci::Driver {
+    name: "virtio-console",
+    matches: &[pci::Match::Id(VENDOR, 0x1003), pci::Match::Id(VENDOR, 0x1043)],
+    probe: probe
+};
+
+struct Console {
+    transport: Transport,
+    rx: Virtqueue,
+    tx: Virtqueue,
+    /// One buffer fills while the device sends the other.
+    tx_buffers: [*mut u8; ..2],
+    /// Index of the buffer being filled.
+    filling: usize,
+    /// Bytes waiting in the filling buffer for the end of the line.
+    tx_len: usize,
+    /// The device hasn't given the other buffer back yet.
+    sending: bool
+}
+
+static mut console: Option<Console> = None;
+/// Taken while a processor uses `console`.
+static busy: AtomicBool = ATOMIC_BOOL_INIT;
+
+impl Console {
+    /// Takes the sent buffer back, if the device is done with it.
+    fn reclaim(&mut self) {
+        if self.sending && self.tx.pop().is_some() {
+            self.sending = false;
+        }
+    }
+
+    /// Starts sending the filling buffer. It stays pending while the other
+    /// one is still out, and the transmit interrupt sends it later.
+    fn flush(&mut self) {
+        self.reclaim();
+        if self.sending || self.tx_len == 0 {
+            return;
+        }
+        let buf = Buffer { addr: self.tx_buffers[self.filling], len: self.tx_len, writable: false };
+        if self.tx.add(&[buf]).is_none() {
+            return;
+        }
+        self.transport.notify(&self.tx);
+        self.sending = true;
+        self.filling ^= 1;
+        self.tx_len = 0;
+    }
+
+    fn putc(&mut self, c: u8) {
+        if self.tx_len == TX_SIZE {
+            self.flush();
+            if self.tx_len == TX_SIZE {
+                // both buffers are full; the device is too slow
+                return;
+            }
+        }
+        unsafe {
+            *self.tx_buffers[self.filling].offset(self.tx_len as isize) = c;
+        }
+        self.tx_len += 1;
+        if c == b'\n' || self.tx_len == TX_SIZE {
+            self.flush();
+        }
+    }
+
+    /// Types what came in and gives the buffers back to the device.
+    fn receive(&mut self) {
+        loop {
+            let (id, len) = match self.rx.pop() {
+                Some(used) => used,
+                None => break
+            };
+            let addr = self.rx.buffer(id);
+            for i in 0..len as isize {
+                let c = unsafe { *addr.offset(i) };
+                vt::input(c);
+                unsafe {
+                    keydown.map(|f| f(c));
+                }
+            }
+            self.rx.add(&[Buffer { addr: addr, len: RX_SIZE, writable: true }]);
+        }
+        self.transport.notify(&self.rx);
+    }
+}
+
+/// Runs `f` on the console with interrupts off. When another processor, or
+/// the code a panic interrupted, holds it, nothing is done.
+fn locked<F: FnOnce(&mut Console)>(f: F) {
+    cpu::without_interrupts(|| unsafe {
+        if busy.swap(true, Ordering::SeqCst) {
+            return;
+        }
+        match console {
+            Some(ref mut console) => f(console),
+            None => {}
+        }
+        busy.store(false, Ordering::SeqCst);
+    })
+}
+
+/// Drops `c` rather than wait when the console is in use.
+fn putc(c: u8) {
+    locked(|console| console.putc(c));
+}
+
+fn handler() {
+    locked(|console| {
+        // reading the ISR acknowledges the interrupt
+        if console.transport.isr() & 1 != 0 {
+            console.receive();
+            console.flush();
+        }
+    });
+}
+
+fn probe(device: &pci::Device) -> bool {
+    if unsafe { console.is_some() } {
+        return false;
+    }
+    let irq = match device.irq {
+        Some(irq) => irq,
+        None => return false
+    };
+    let transport = match Transport::new(device) {
+        Ok(transport) => transport,
+        Err(e) => {
+            println!("virtio-console: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let queues = match transport.init(0) {
+        Ok(_) => (transport.setup_queue(RECEIVE), transport.setup_queue(TRANSMIT)),
+        Err(e) => {
+            println!("virtio-console: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let (mut rx, tx) = match queues {
+        (Ok(rx), Ok(tx)) => (rx, tx),
+        (Err(e), _) | (_, Err(e)) => {
+            transport.fail();
+            println!("virtio-console: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let (rx_buffers, tx_buffers) = match (dma_alloc(RX_BUFFERS * RX_SIZE), dma_alloc(2 * TX_SIZE)) {
+        (Ok(rx_buffers), Ok(tx_buffers)) => (rx_buffers, tx_buffers),
+        _ => {
+            transport.fail();
+            println!("virtio-console: {}: out of memory", device.addr);
+            return false;
+        }
+    };
+    for i in 0..RX_BUFFERS {
+        let addr = unsafe { rx_buffers.offset((i * RX_SIZE) as isize) };
+        rx.add(&[Buffer { addr: addr, len: RX_SIZE, writable: true }]);
+    }
+
+    unsafe {
+        console = Some(Console {
+            transport: transport,
+            rx: rx,
+            tx: tx,
+            tx_buffers: [tx_buffers, tx_buffers.offset(TX_SIZE as isize)],
+            filling: 0,
+            tx_len: 0,
+            sending: false
+        });
+    }
+    irq::register(irq, handler);
+    transport.ready();
+    transport.notify(unsafe { &console.as_ref().unwrap().rx });
+    io::add_console(putc);
+    println!("virtio-console: {}{}", device.addr, if transport.is_modern() { "" } else { ", legacy" });
+    true
+}
@@ -0,0 +1,515 @@
+//! Virtio devices on PCI, through the legacy transport (registers in I/O
+//! BAR 0) or the modern one (registers in memory BARs named by vendor
+//! capabilities).
+//!
+//! Virtqueues and the buffers handed to devices live in `dma_alloc` memory:
+//! whole frames from the physical allocator, mapped at their own address so
+//! that pointers double as bus addresses.
+
+use core::atomic::{fence, Ordering};
+use core::fmt;
+use core::intrinsics::{volatile_load, volatile_store};
+use core::prelude::*;
+use core::ptr;
+
+use cpu::{io, mmu};
+use kernel::mm;
+use kernel::mm::OutOfMemory;
+use kernel::mm::physical;
+use kernel::mm::physical::Phys;
+use super::pci;
+
+pub mod blk;
+pub mod console;
+pub mod net;
+
+pub const VENDOR: u16 = 0x1AF4;
+
+const PAGE_SIZE: usize = 0x1000;
+
+// device status
+const ACKNOWLEDGE: u8 = 1;
+const DRIVER: u8 = 2;
+const DRIVER_OK: u8 = 4;
+const FEATURES_OK: u8 = 8;
+const FAILED: u8 = 128;
+
+/// Devices that follow the 1.0 specification.
+const F_VERSION_1: u64 = 1 << 32;
+
+// legacy registers, from I/O BAR 0
+const LEGACY_DEVICE_FEATURES: u16 = 0x00;
+const LEGACY_DRIVER_FEATURES: u16 = 0x04;
+const LEGACY_QUEUE_PFN: u16 = 0x08;
+const LEGACY_QUEUE_SIZE: u16 = 0x0C;
+const LEGACY_QUEUE_SELECT: u16 = 0x0E;
+const LEGACY_QUEUE_NOTIFY: u16 = 0x10;
+const LEGACY_STATUS: u16 = 0x12;
+const LEGACY_ISR: u16 = 0x13;
+const LEGACY_CONFIG: u16 = 0x14;
+
+// modern common configuration
+const DEVICE_FEATURE_SELECT: usize = 0x00;
+const DEVICE_FEATURE: usize = 0x04;
+const DRIVER_FEATURE_SELECT: usize = 0x08;
+const DRIVER_FEATURE: usize = 0x0C;
+const DEVICE_STATUS: usize = 0x14;
+const QUEUE_SELECT: usize = 0x16;
+const QUEUE_SIZE: usize = 0x18;
+const QUEUE_ENABLE: usize = 0x1C;
+const QUEUE_NOTIFY_OFF: usize = 0x1E;
+const QUEUE_DESC: usize = 0x20;
+const QUEUE_DRIVER: usize = 0x28;
+const QUEUE_DEVICE: usize = 0x30;
+
+// vendor capabilities of the modern transport
+const CAP_VENDOR: u8 = 0x09;
+const CAP_TYPE: u8 = 3;
+const CAP_BAR: u8 = 4;
+const CAP_OFFSET: u8 = 8;
+const CAP_NOTIFY_MULTIPLIER: u8 = 16;
+const CAP_COMMON: u8 = 1;
+const CAP_NOTIFY: u8 = 2;
+const CAP_ISR: u8 = 3;
+const CAP_DEVICE: u8 = 4;
+
+/// Queues are made no longer than this when the device lets us choose.
+const MAX_QUEUE_SIZE: u16 = 256;
+
+#[derive(Copy)]
+pub enum Error {
+    OutOfMemory,
+    /// Neither transport's registers were found.
+    NoTransport,
+    NoQueue(u16),
+    FeaturesRejected
+}
+
+impl fmt::Show for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::OutOfMemory => write!(f, "out of memory"),
+            Error::NoTransport => write!(f, "no registers"),
+            Error::NoQueue(q) => write!(f, "no queue {}", q),
+            Error::FeaturesRejected => write!(f, "features rejected")
+        }
+    }
+}
+
+/// Memory a device can reach by DMA, zeroed.
+pub fn dma_alloc(bytes: usize) -> Result<*mut u8, OutOfMemory> {
+    let count = (bytes + PAGE_SIZE - 1) / PAGE_SIZE;
+    unsafe {
+        let frames: Phys<u8> = match physical::try_alloc_frames(count) {
+            Some(frames) => frames,
+            None => return Err(OutOfMemory)
+        };
+        match mmu::map_physical(frames.as_ptr() as usize, count * PAGE_SIZE, mm::RW) {
+            Ok(ptr) => {
+                ptr::write_bytes(ptr, 0, count * PAGE_SIZE);
+                Ok(ptr)
+            }
+            Err(e) => {
+                physical::free_frames(frames);
+                Err(e)
+            }
+        }
+    }
+}
+
+#[inline]
+unsafe fn read<T>(base: *mut u8, offset: usize) -> T {
+    volatile_load(base.offset(offset as isize) as *const T)
+}
+
+#[inline]
+unsafe fn write<T>(base: *mut u8, offset: usize, val: T) {
+    volatile_store(base.offset(offset as isize) as *mut T, val)
+}
+
+#[derive(Copy)]
+pub struct Modern {
+    common: *mut u8,
+    notify: *mut u8,
+    notify_multiplier: u32,
+    isr: *mut u8,
+    device: *mut u8
+}
+
+#[derive(Copy)]
+pub enum Transport {
+    Legacy(u16),
+    Modern(Modern)
+}
+
+impl Transport {
+    /// Finds the registers of a virtio PCI function, preferring the modern
+    /// transport.
+    pub fn new(device: &pci::Device) -> Result<Transport, Error> {
+        device.enable(pci::IO_SPACE | pci::MEMORY_SPACE | pci::BUS_MASTER);
+        match Transport::modern(device) {
+            Some(modern) => return Ok(Transport::Modern(modern)),
+            None => {}
+        }
+        match device.io_bar(0) {
+            Some(port) => Ok(Transport::Legacy(port)),
+            None => Err(Error::NoTransport)
+        }
+    }
+
+    fn modern(device: &pci::Device) -> Option<Modern> {
+        let mut modern = Modern {
+            common: ptr::null_mut(),
+            notify: ptr::null_mut(),
+            notify_multiplier: 0,
+            isr: ptr::null_mut(),
+            device: ptr::null_mut()
+        };
+        // capabilities often share a BAR, which is mapped only once
+        let mut bars = [ptr::null_mut(); 6];
+        let mut cap = device.capability(CAP_VENDOR);
+        loop {
+            let offset = match cap {
+                Some(offset) => offset,
+                None => break
+            };
+            let addr = device.addr;
+            let kind = addr.read8(offset + CAP_TYPE);
+            let bar = addr.read8(offset + CAP_BAR) as usize;
+            // other kinds, such as configuration access, aren't used
+            if kind >= CAP_COMMON && kind <= CAP_DEVICE && bar < 6 {
+                if bars[bar].is_null() {
+                    bars[bar] = match device.map_bar(bar) {
+                        Ok(base) => base,
+                        Err(_) => return None
+                    };
+                }
+                let base = unsafe { bars[bar].offset(addr.read32(offset + CAP_OFFSET) as isize) };
+                match kind {
+                    CAP_COMMON => modern.common = base,
+                    CAP_NOTIFY => {
+                        modern.notify = base;
+                        modern.notify_multiplier = addr.read32(offset + CAP_NOTIFY_MULTIPLIER);
+                    }
+                    CAP_ISR => modern.isr = base,
+                    _ => modern.device = base
+                }
+            }
+            cap = device.next_capability(offset, CAP_VENDOR);
+        }
+        if modern.common.is_null() || modern.notify.is_null() || modern.isr.is_null() {
+            return None;
+        }
+        Some(modern)
+    }
+
+    pub fn is_modern(&self) -> bool {
+        match *self {
+            Transport::Modern(_) => true,
+            Transport::Legacy(_) => false
+        }
+    }
+
+    fn status(&self) -> u8 {
+        match *self {
+            Transport::Legacy(port) => io::inb(port + LEGACY_STATUS),
+            Transport::Modern(m) => unsafe { read(m.common, DEVICE_STATUS) }
+        }
+    }
+
+    fn set_status(&self, status: u8) {
+        match *self {
+            Transport::Legacy(port) => io::out(port + LEGACY_STATUS, status),
+            Transport::Modern(m) => unsafe { write(m.common, DEVICE_STATUS, status) }
+        }
+    }
+
+    fn add_status(&self, status: u8) {
+        let old = self.status();
+        self.set_status(old | status);
+    }
+
+    fn device_features(&self) -> u64 {
+        match *self {
+            Transport::Legacy(port) => io::inl(port + LEGACY_DEVICE_FEATURES) as u64,
+            Transport::Modern(m) => unsafe {
+                write(m.common, DEVICE_FEATURE_SELECT, 0u32);
+                let low: u32 = read(m.common, DEVICE_FEATURE);
+                write(m.common, DEVICE_FEATURE_SELECT, 1u32);
+                let high: u32 = read(m.common, DEVICE_FEATURE);
+                low as u64 | (high as u64) << 32
+            }
+        }
+    }
+
+    fn set_driver_features(&self, features: u64) {
+        match *self {
+            Transport::Legacy(port) => io::outl(port + LEGACY_DRIVER_FEATURES, features as u32),
+            Transport::Modern(m) => unsafe {
+                write(m.common, DRIVER_FEATURE_SELECT, 0u32);
+                write(m.common, DRIVER_FEATURE, features as u32);
+                write(m.common, DRIVER_FEATURE_SELECT, 1u32);
+                write(m.common, DRIVER_FEATURE, (features >> 32) as u32);
+            }
+        }
+    }
+
+    /// Resets the device and agrees on the features in `wanted` that it
+    /// offers. Returns the features agreed on.
+    pub fn init(&self, wanted: u64) -> Result<u64, Error> {
+        self.set_status(0);
+        self.add_status(ACKNOWLEDGE);
+        self.add_status(DRIVER);
+        let wanted = if self.is_modern() { wanted | F_VERSION_1 } else { wanted };
+        let features = self.device_features() & wanted;
+        self.set_driver_features(features);
+        if self.is_modern() {
+            self.add_status(FEATURES_OK);
+            if self.status() & FEATURES_OK == 0 || features & F_VERSION_1 == 0 {
+                self.fail();
+                return Err(Error::FeaturesRejected);
+            }
+        }
+        Ok(features)
+    }
+
+    /// Tells the device that its queues are set up.
+    pub fn ready(&self) {
+        self.add_status(DRIVER_OK);
+    }
+
+    pub fn fail(&self) {
+        self.add_status(FAILED);
+    }
+
+    /// Creates queue `index` and gives it to the device.
+    pub fn setup_queue(&self, index: u16) -> Result<Virtqueue, Error> {
+        match *self {
+            Transport::Legacy(port) => {
+                io::outw(port + LEGACY_QUEUE_SELECT, index);
+                // legacy devices decide the size
+                let size = io::inw(port + LEGACY_QUEUE_SIZE);
+                if size == 0 {
+                    return Err(Error::NoQueue(index));
+                }
+                let queue = try!(Virtqueue::new(index, size, 0));
+                io::outl(port + LEGACY_QUEUE_PFN, (queue.desc as usize / PAGE_SIZE) as u32);
+                Ok(queue)
+            }
+            Transport::Modern(m) => unsafe {
+                write(m.common, QUEUE_SELECT, index);
+                let max: u16 = read(m.common, QUEUE_SIZE);
+                if max == 0 {
+                    return Err(Error::NoQueue(index));
+                }
+                let size = if max > MAX_QUEUE_SIZE { MAX_QUEUE_SIZE } else { max };
+                write(m.common, QUEUE_SIZE, size);
+                let off: u16 = read(m.common, QUEUE_NOTIFY_OFF);
+                let notify = m.notify as usize + off as usize * m.notify_multiplier as usize;
+                let queue = try!(Virtqueue::new(index, size, notify));
+                write(m.common, QUEUE_DESC, queue.desc as u64);
+                write(m.common, QUEUE_DRIVER, queue.avail as u64);
+                write(m.common, QUEUE_DEVICE, queue.used as u64);
+                write(m.common, QUEUE_ENABLE, 1u16);
+                Ok(queue)
+            }
+        }
+    }
+
+    /// Tells the device there are new buffers in `queue`.
+    pub fn notify(&self, queue: &Virtqueue) {
+        fence(Ordering::SeqCst);
+        match *self {
+            Transport::Legacy(port) => io::outw(port + LEGACY_QUEUE_NOTIFY, queue.index),
+            Transport::Modern(_) => unsafe {
+                volatile_store(queue.notify as *mut u16, queue.index)
+            }
+        }
+    }
+
+    /// Reads and clears the interrupt status.
+    pub fn isr(&self) -> u8 {
+        match *self {
+            Transport::Legacy(port) => io::inb(port + LEGACY_ISR),
+            Transport::Modern(m) => unsafe { read(m.isr, 0) }
+        }
+    }
+
+    pub fn config8(&self, offset: usize) -> u8 {
+        match *self {
+            Transport::Legacy(port) => io::inb(port + LEGACY_CONFIG + offset as u16),
+            Transport::Modern(m) => unsafe { read(m.device, offset) }
+        }
+    }
+
+    pub fn config32(&self, offset: usize) -> u32 {
+        match *self {
+            Transport::Legacy(port) => io::inl(port + LEGACY_CONFIG + offset as u16),
+            Transport::Modern(m) => unsafe { read(m.device, offset) }
+        }
+    }
+
+    pub fn config64(&self, offset: usize) -> u64 {
+        self.config32(offset) as u64 | (self.config32(offset + 4) as u64) << 32
+    }
+}
+
+// descriptor flags
+const DESC_NEXT: u16 = 1;
+const DESC_WRITE: u16 = 2;
+
+/// Asks the device not to interrupt when it uses buffers.
+const AVAIL_NO_INTERRUPT: u16 = 1;
+
+#[repr(C)]
+#[derive(Copy)]
+struct Desc {
+    addr: u64,
+    len: u32,
+    flags: u16,
+    next: u16
+}
+
+#[repr(C)]
+#[derive(Copy)]
+struct UsedElem {
+    id: u32,
+    len: u32
+}
+
+/// A buffer to hand to a device, in `dma_alloc` memory.
+#[derive(Copy)]
+pub struct Buffer {
+    pub addr: *mut u8,
+    pub len: usize,
+    /// Whether the device writes to it rather than reads it.
+    pub writable: bool
+}
+
+/// A split virtqueue: a descriptor table, the ring of chains available to
+/// the device and the ring of chains it has used.
+pub struct Virtqueue {
+    index: u16,
+    size: u16,
+    desc: *mut Desc,
+    /// flags, idx, ring
+    avail: *mut u16,
+    /// flags, idx, then `UsedElem`s
+    used: *mut u16,
+    /// Head of the list of free descriptors, chained through `next`.
+    free: u16,
+    num_free: u16,
+    last_used: u16,
+    /// Notification address of the modern transport.
+    notify: usize
+}
+
+fn align(n: usize) -> usize {
+    (n + PAGE_SIZE - 1) & !(PAGE_SIZE - 1)
+}
+
+impl Virtqueue {
+    fn new(index: u16, size: u16, notify: usize) -> Result<Virtqueue, Error> {
+        let n = size as usize;
+        // the legacy layout, which suits the modern transport as well
+        let avail_offset = 16 * n;
+        let used_offset = align(avail_offset + 6 + 2 * n);
+        let mem = match dma_alloc(used_offset + align(6 + 8 * n)) {
+            Ok(mem) => mem,
+            Err(_) => return Err(Error::OutOfMemory)
+        };
+        let queue = Virtqueue {
+            index: index,
+            size: size,
+            desc: mem as *mut Desc,
+            avail: unsafe { mem.offset(avail_offset as isize) as *mut u16 },
+            used: unsafe { mem.offset(used_offset as isize) as *mut u16 },
+            free: 0,
+            num_free: size,
+            last_used: 0,
+            notify: notify
+        };
+        for i in 0..size {
+            unsafe { (*queue.desc.offset(i as isize)).next = i + 1; }
+        }
+        Ok(queue)
+    }
+
+    pub fn size(&self) -> u16 {
+        self.size
+    }
+
+    pub fn disable_interrupts(&mut self) {
+        unsafe { volatile_store(self.avail, AVAIL_NO_INTERRUPT); }
+    }
+
+    /// Makes a chain of `bufs` available to the device. Returns the ID of
+    /// the chain, or `None` if there aren't enough free descriptors.
+    pub fn add(&mut self, bufs: &[Buffer]) -> Option<u16> {
+        if bufs.is_empty() || bufs.len() > self.num_free as usize {
+            return None;
+        }
+        let head = self.free;
+        for (i, buf) in bufs.iter().enumerate() {
+            let desc = unsafe { &mut *self.desc.offset(self.free as isize) };
+            self.free = desc.next;
+            desc.addr = buf.addr as u64;
+            desc.len = buf.len as u32;
+            desc.flags = if buf.writable { DESC_WRITE } else { 0 }
+                | if i + 1 < bufs.len() { DESC_NEXT } else { 0 };
+        }
+        self.num_free -= bufs.len() as u16;
+
+        unsafe {
+            let idx = volatile_load(self.avail.offset(1));
+            volatile_store(self.avail.offset(2 + (idx % self.size) as isize), head);
+            // the entry must be visible before the index that publishes it
+            fence(Ordering::SeqCst);
+            volatile_store(self.avail.offset(1), idx + 1);
+        }
+        Some(head)
+    }
+
+    /// Whether the device has used a chain not yet taken with `pop`.
+    pub fn has_used(&self) -> bool {
+        unsafe { volatile_load(self.used.offset(1)) != self.last_used }
+    }
+
+    /// Takes a chain the device is done with and frees its descriptors.
+    /// Returns its ID and the number of bytes the device wrote.
+    pub fn pop(&mut self) -> Option<(u16, u32)> {
+        if !self.has_used() {
+            return None;
+        }
+        fence(Ordering::SeqCst);
+        let elems = unsafe { self.used.offset(2) as *mut UsedElem };
+        let elem = unsafe { volatile_load(elems.offset((self.last_used % self.size) as isize)) };
+        self.last_used += 1;
+
+        let head = elem.id as u16;
+        let mut id = head;
+        loop {
+            let desc = unsafe { &mut *self.desc.offset(id as isize) };
+            self.num_free += 1;
+            if desc.flags & DESC_NEXT == 0 {
+                desc.next = self.free;
+                break;
+            }
+            id = desc.next;
+        }
+        self.free = head;
+        Some((head, elem.len))
+    }
+
+    /// The address of the first buffer of chain `id`.
+    pub fn buffer(&self, id: u16) -> *mut u8 {
+        unsafe { (*self.desc.offset(id as isize)).addr as usize as *mut u8 }
+    }
+}
+
+/// Registers the virtio drivers with the PCI bus.
+pub fn init() {
+    pci::register(&blk::DRIVER);
+    pci::register(&console::DRIVER);
+    pci::register(&net::DRIVER);
+}
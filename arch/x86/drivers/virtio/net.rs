@@ -0,0 +1,210 @@
+//! Virtio network cards. Frames are received into buffers handed to the
+//! device up front and sent from a small set of transmit buffers; both are
+//! polled through `kernel::net::NetDevice`, without interrupts.
+
+use core::prelude::*;
+use core::ptr;
+
+use kernel::heap;
+use kernel::net;
+use kernel::net::{Error, NetDevice, MAX_FRAME};
+use super::{dma_alloc, Buffer, Transport, Virtqueue, VENDOR};
+use super::super::pci;
+
+const RECEIVE: u16 = 0;
+const TRANSMIT: u16 = 1;
+
+// features
+const F_MAC: u64 = 1 << 5;
+
+// device configuration
+const CONFIG_MAC: usize = 0;
+
+/// Each frame is preceded by a header, left zeroed: no checksum offload and
+/// no segmentation. Modern devices add a buffer count to it.
+const LEGACY_HEADER_SIZE: usize = 10;
+const HEADER_SIZE: usize = 12;
+
+const BUFFER_SIZE: usize = 2048;
+const RX_BUFFERS: usize = 16;
+/// At most 32, one bit each in `tx_busy`.
+const TX_BUFFERS: usize = 16;
+
+/// A locally administered address for devices that don't have their own.
+const DEFAULT_MAC: [u8; ..6] = [0x02, 0, 0, 0, 0, 0x01];
+
+static NAMES: [&'static str; ..4] = ["eth0", "eth1", "eth2", "eth3"];
+static mut count: usize = 0;
+
+pub static DRIVER: pci::Driver = pci::Driver {
+    name: "virtio-net",
+    matches: &[pci::Match::Id(VENDOR, 0x1000), pci::Match::Id(VENDOR, 0x1041)],
+    probe: probe
+};
+
+pub struct Card {
+    name: &'static str,
+    mac: [u8; ..6],
+    transport: Transport,
+    header_size: usize,
+    rx: Virtqueue,
+    tx: Virtqueue,
+    tx_buffers: *mut u8,
+    /// Transmit buffers the device hasn't finished with, one bit each.
+    tx_busy: u32
+}
+
+impl Card {
+    /// Gives a receive buffer to the device: the header, then the frame.
+    fn give(&mut self, addr: *mut u8) {
+        let frame = unsafe { addr.offset(self.header_size as isize) };
+        self.rx.add(&[
+            Buffer { addr: addr, len: self.header_size, writable: true },
+            Buffer { addr: frame, len: BUFFER_SIZE - self.header_size, writable: true }
+        ]);
+    }
+
+    /// Frees the transmit buffers of frames the device has sent.
+    fn reclaim(&mut self) {
+        loop {
+            let id = match self.tx.pop() {
+                Some((id, _)) => id,
+                None => break
+            };
+            let slot = (self.tx.buffer(id) as usize - self.tx_buffers as usize) / BUFFER_SIZE;
+            self.tx_busy &= !(1 << slot);
+        }
+    }
+}
+
+impl NetDevice for Card {
+    fn name(&self) -> &str {
+        self.name
+    }
+
+    fn mac(&self) -> [u8; ..6] {
+        self.mac
+    }
+
+    fn send(&mut self, frame: &[u8]) -> Result<(), Error> {
+        if frame.len() > MAX_FRAME {
+            return Err(Error::TooLong);
+        }
+        self.reclaim();
+        let slot = match (0..TX_BUFFERS).find(|&i| self.tx_busy & 1 << i == 0) {
+            Some(slot) => slot,
+            None => return Err(Error::Busy)
+        };
+        let header = unsafe { self.tx_buffers.offset((slot * BUFFER_SIZE) as isize) };
+        let data = unsafe { header.offset(self.header_size as isize) };
+        unsafe {
+            ptr::write_bytes(header, 0, self.header_size);
+            ptr::copy_nonoverlapping(data, frame.as_ptr(), frame.len());
+        }
+        let added = self.tx.add(&[
+            Buffer { addr: header, len: self.header_size, writable: false },
+            Buffer { addr: data, len: frame.len(), writable: false }
+        ]);
+        if added.is_none() {
+            return Err(Error::Busy);
+        }
+        self.tx_busy |= 1 << slot;
+        self.transport.notify(&self.tx);
+        Ok(())
+    }
+
+    fn receive(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Error> {
+        let (id, len) = match self.rx.pop() {
+            Some(used) => used,
+            None => return Ok(None)
+        };
+        let addr = self.rx.buffer(id);
+        let len = (len as usize).saturating_sub(self.header_size);
+        let result = if len > buf.len() {
+            Err(Error::TooLong)
+        } else {
+            unsafe {
+                ptr::copy_nonoverlapping(buf.as_mut_ptr(), addr.offset(self.header_size as isize) as *const u8, len);
+            }
+            Ok(Some(len))
+        };
+        // the frame is dropped if it didn't fit
+        self.give(addr);
+        self.transport.notify(&self.rx);
+        result
+    }
+}
+
+fn probe(device: &pci::Device) -> bool {
+    let name = match NAMES.get(unsafe { count }) {
+        Some(&name) => name,
+        None => return false
+    };
+    let transport = match Transport::new(device) {
+        Ok(transport) => transport,
+        Err(e) => {
+            println!("virtio-net: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let features = match transport.init(F_MAC) {
+        Ok(features) => features,
+        Err(e) => {
+            println!("virtio-net: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    let (mut rx, mut tx) = match (transport.setup_queue(RECEIVE), transport.setup_queue(TRANSMIT)) {
+        (Ok(rx), Ok(tx)) => (rx, tx),
+        (Err(e), _) | (_, Err(e)) => {
+            transport.fail();
+            println!("virtio-net: {}: {}", device.addr, e);
+            return false;
+        }
+    };
+    rx.disable_interrupts();
+    tx.disable_interrupts();
+    let (rx_buffers, tx_buffers) = match (dma_alloc(RX_BUFFERS * BUFFER_SIZE), dma_alloc(TX_BUFFERS * BUFFER_SIZE)) {
+        (Ok(rx_buffers), Ok(tx_buffers)) => (rx_buffers, tx_buffers),
+        _ => {
+            transport.fail();
+            println!("virtio-net: {}: out of memory", device.addr);
+            return false;
+        }
+    };
+
+    let mut mac = DEFAULT_MAC;
+    if features & F_MAC != 0 {
+        for i in 0..mac.len() {
+            mac[i] = transport.config8(CONFIG_MAC + i);
+        }
+    }
+    let mut card = Card {
+        name: name,
+        mac: mac,
+        transport: transport,
+        header_size: if transport.is_modern() { HEADER_SIZE } else { LEGACY_HEADER_SIZE },
+        rx: rx,
+        tx: tx,
+        tx_buffers: tx_buffers,
+        tx_busy: 0
+    };
+    for i in 0..RX_BUFFERS {
+        card.give(unsafe { rx_buffers.offset((i * BUFFER_SIZE) as isize) });
+    }
+    transport.ready();
+    transport.notify(&card.rx);
+
+    println!("virtio-net: {} at {}, {:02x}:{:02x}:{:02x}:{:02x}:{:02x}:{:02x}{}", name, device.addr,
+             mac[0], mac[1], mac[2], mac[3], mac[4], mac[5],
+             if transport.is_modern() { "" } else { ", legacy" });
+    unsafe {
+        count += 1;
+        let ptr = heap::alloc::<Card>(1);
+        ptr::write(ptr, card);
+        if net::register(&mut *ptr).is_none() {
+            println!("virtio-net: too many network devices");
+        }
+    }
+    true
+}
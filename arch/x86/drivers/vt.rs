@@ -0,0 +1,131 @@
+//! Virtual terminals, each with its own console and input queue. Alt+F1 to
+//! Alt+F6 switch between them. The kernel log goes to the first one.
+
+use core::prelude::*;
+
+use super::vga;
+use super::vga::Console;
+
+pub const COUNT: usize = 6;
+/// The terminal kernel messages are written to.
+pub const LOG: usize = 0;
+
+const INPUT_SIZE: usize = 256;
+
+pub struct Terminal {
+    pub console: Console,
+    /// Keys typed while this terminal was active, not yet read.
+    input: [u8; ..INPUT_SIZE],
+    head: usize,
+    len: usize
+}
+
+impl Terminal {
+    fn new() -> Terminal {
+        Terminal {
+            console: Console::new(),
+            input: [0; INPUT_SIZE],
+            head: 0,
+            len: 0
+        }
+    }
+
+    /// Queues a key. Keys are dropped when the queue is full.
+    pub fn push(&mut self, c: u8) {
+        if self.len < INPUT_SIZE {
+            self.input[(self.head + self.len) % INPUT_SIZE] = c;
+            self.len += 1;
+        }
+    }
+
+    /// Takes the oldest queued key.
+    pub fn read(&mut self) -> Option<u8> {
+        if self.len == 0 {
+            return None;
+        }
+        let c = self.input[self.head];
+        self.head = (self.head + 1) % INPUT_SIZE;
+        self.len -= 1;
+        Some(c)
+    }
+}
+
+static mut terminals: [Option<Terminal>; ..COUNT] = [None, None, None, None, None, None];
+static mut current: usize = LOG;
+
+/// Creates the terminals. Their consoles allocate from the heap, which
+/// interrupt handlers must not do, so `io::init` calls this right after
+/// `heap::init`, before any handler is registered.
+pub fn init() {
+    unsafe {
+        for t in terminals.iter_mut() {
+            if t.is_none() {
+                *t = Some(Terminal::new());
+            }
+        }
+    }
+    get(active()).map(|t| t.console.show());
+}
+
+/// Terminal `n`, once `init` has created it.
+pub fn get(n: usize) -> Option<&'static mut Terminal> {
+    unsafe {
+        if n >= COUNT {
+            return None;
+        }
+        match terminals[n] {
+            Some(ref mut t) => Some(t),
+            None => None
+        }
+    }
+}
+
+/// The number of the terminal on screen.
+pub fn active() -> usize {
+    unsafe { current }
+}
+
+/// The terminal on screen.
+pub fn current_terminal() -> Option<&'static mut Terminal> {
+    get(active())
+}
+
+/// Puts terminal `n` on screen.
+pub fn switch(n: usize) {
+    if n == active() {
+        return;
+    }
+    match (get(active()), get(n)) {
+        (Some(old), Some(new)) => {
+            old.console.hide();
+            unsafe {
+                current = n;
+            }
+            new.console.show();
+        }
+        _ => {}
+    }
+}
+
+/// Queues a key typed on the keyboard for the terminal on screen.
+pub fn input(c: u8) {
+    current_terminal().map(|t| t.push(c));
+}
+
+/// Writes a byte to terminal `n`. Before `init`, the kernel log goes
+/// straight to the text screen.
+pub fn write(n: usize, c: u8) {
+    match get(n) {
+        Some(t) => t.console.putc(c),
+        None if n == LOG => vga::write_raw(c),
+        None => {}
+    }
+}
+
+/// Takes the oldest key queued for terminal `n`.
+pub fn read(n: usize) -> Option<u8> {
+    match get(n) {
+        Some(t) => t.read(),
+        None => None
+    }
+}
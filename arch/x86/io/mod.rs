@@ -3,9 +3,9 @@
 use core::fmt;
 use core::prelude::*;
 
-use super::drivers::vga;
+use super::drivers::{vga, vt};
 
-/// A format writer that writes out to the VGA screen.
+/// A format writer that writes out to the kernel log terminal.
 struct Stdout;
 
 impl Stdout {
@@ -31,44 +31,60 @@ pub fn println_args(fmt: &fmt::Arguments) {
     writeln!(&mut Stdout, "{}", fmt);
 }
 
-static mut pos: isize = 0;
-
-unsafe fn seek(offset: isize) {
-    pos += offset;
+/// Creates the terminals, once the heap is up.
+pub fn init() {
+    vt::init();
 }
 
-unsafe fn write_char(c: char) {
-    if c == '\x08' {
-        if pos > 0 {
-            if pos % 80 == 0 {
-                while (*vga::SCREEN)[(pos-1) as usize].char == 0 {
-                    pos -= 1;
-                }
-            }
-            else if pos > 0 {
-                pos -= 1;
-                (*vga::SCREEN)[pos as usize].char = 0;
+/// Consoles that receive a copy of everything written to the screen.
+static mut consoles: [Option<fn(u8)>; ..4] = [None, None, None, None];
+
+pub fn add_console(putc: fn(u8)) {
+    unsafe {
+        for slot in consoles.iter_mut() {
+            if slot.is_none() {
+                *slot = Some(putc);
+                return;
             }
         }
     }
-    else if c == '\n' {
-        seek(80 - pos % 80);
-    }
-    else if c == '\t' {
-        seek(4 - pos % 4);
-    }
-    else {
-        (*vga::SCREEN)[pos as usize].char = c as u8;
-        pos += 1;
+}
+
+/// Puts the kernel log on screen, clears it to `bg` and moves the cursor
+/// back to the top.
+pub fn clear(bg: vga::Color) {
+    vt::switch(vt::LOG);
+    match vt::get(vt::LOG) {
+        Some(t) => {
+            let fg = t.console.default_fg();
+            t.console.set_default(fg, bg);
+            t.console.clear();
+        }
+        None => vga::clear_raw(bg)
     }
+}
+
+/// Echoes a typed key on the terminal on screen.
+pub fn echo(c: u8) {
+    vt::write(vt::active(), c);
+}
 
-    pos %= vga::SCREEN_SIZE as isize;
-    vga::cursor_at(pos as usize);
+/// Writes a byte for a program to terminal `n`.
+pub fn write_terminal(n: usize, c: u8) {
+    vt::write(n, c);
+}
+
+/// Takes a key typed on terminal `n`, for a program.
+pub fn read_terminal(n: usize) -> Option<u8> {
+    vt::read(n)
 }
 
 pub fn putc(c: u8) {
+    vt::write(vt::LOG, c);
     unsafe {
-        write_char(c as char);
+        for console in consoles.iter() {
+            console.map(|f| f(c));
+        }
     }
 }
 
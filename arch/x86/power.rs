@@ -0,0 +1,97 @@
+//! Powering off and resetting the machine.
+
+use core::prelude::*;
+
+use cpu;
+use cpu::io;
+use platform::drivers::acpi;
+use platform::drivers::acpi::{Fadt, SdtHeader, SPACE_IO};
+
+const SCI_EN: u16 = 1 << 0;
+const SLP_EN: u16 = 1 << 13;
+
+#[inline]
+fn byte(addr: usize) -> u8 {
+    unsafe { *(addr as *const u8) }
+}
+
+/// Reads a ByteConst, or a ZeroOp, OneOp or similar single-byte constant.
+fn byte_const(addr: &mut usize) -> u16 {
+    if byte(*addr) == 0x0A { // BytePrefix
+        *addr += 1;
+    }
+    let value = byte(*addr) as u16;
+    *addr += 1;
+    value
+}
+
+/// Reads the sleep types of the S5 state from the `\_S5_` package in the
+/// DSDT. Evaluating AML properly needs an interpreter, but this package is
+/// a plain list of constants on all known firmware.
+fn s5_sleep_types(dsdt: &SdtHeader) -> Option<(u16, u16)> {
+    let start = dsdt as *const SdtHeader as usize;
+    let end = start + dsdt.length as usize;
+    let mut addr = start;
+    while addr + 4 < end {
+        if byte(addr) == b'_' && byte(addr + 1) == b'S' && byte(addr + 2) == b'5' && byte(addr + 3) == b'_' {
+            break;
+        }
+        addr += 1;
+    }
+    // NameOp, optionally followed by the root prefix, then PackageOp
+    let named = byte(addr - 1) == 0x08 || (byte(addr - 2) == 0x08 && byte(addr - 1) == b'\\');
+    if addr + 4 >= end || !named || byte(addr + 4) != 0x12 {
+        return None;
+    }
+
+    // skip PkgLength, whose top two bits count its extra bytes, and NumElements
+    addr += 5;
+    addr += ((byte(addr) >> 6) + 1) as usize + 1;
+
+    let a = byte_const(&mut addr);
+    let b = byte_const(&mut addr);
+    Some((a, b))
+}
+
+/// Switches from legacy to ACPI mode, where the PM1 registers work.
+fn enable_acpi(fadt: &Fadt) {
+    if io::inw(fadt.pm1a_cnt_blk as u16) & SCI_EN != 0 || fadt.smi_cmd == 0 || fadt.acpi_enable == 0 {
+        return;
+    }
+    io::out(fadt.smi_cmd as u16, fadt.acpi_enable);
+    for _ in 0..1000000u32 {
+        if io::inw(fadt.pm1a_cnt_blk as u16) & SCI_EN != 0 {
+            return;
+        }
+    }
+}
+
+/// Enters the S5 soft-off state through the PM1 control registers. Halts
+/// when the firmware doesn't describe how.
+pub fn shutdown() -> ! {
+    let fadt = match acpi::fadt() {
+        Some(fadt) if fadt.pm1a_cnt_blk != 0 => fadt,
+        _ => cpu::halt()
+    };
+    match fadt.dsdt().and_then(s5_sleep_types) {
+        Some((slp_typ_a, slp_typ_b)) => {
+            enable_acpi(fadt);
+            io::outw(fadt.pm1a_cnt_blk as u16, (slp_typ_a << 10) | SLP_EN);
+            if fadt.pm1b_cnt_blk != 0 {
+                io::outw(fadt.pm1b_cnt_blk as u16, (slp_typ_b << 10) | SLP_EN);
+            }
+        }
+        None => {}
+    }
+    cpu::halt()
+}
+
+/// Resets through the ACPI reset register, then the keyboard controller,
+/// then a triple fault.
+pub fn reboot() -> ! {
+    match acpi::fadt().and_then(|fadt| fadt.reset_register()) {
+        Some((reg, value)) if reg.space == SPACE_IO => io::out(reg.address as u16, value),
+        _ => {}
+    }
+    cpu::reboot()
+}
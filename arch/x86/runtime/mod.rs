@@ -1,6 +1,6 @@
 // use rust_core::c_types::c_int;
 
-mod stack;
+pub mod stack;
 
 // TODO: use SSE
 
@@ -1,4 +1,6 @@
-pub static RED_ZONE: usize = 5 * 4 * 1024;
+/// Space kept below the recorded limit of each stack, so that `__morestack`
+/// can still report an overflow.
+pub static RED_ZONE: usize = 8 * 1024;
 
 #[cfg(target_arch = "arm")] #[inline(always)]
 fn get_tls() -> usize {
@@ -0,0 +1,74 @@
+//! Names of code addresses for backtraces. The build embeds the kernel's own
+//! ELF symbol table into the image, between `_binary_symbols_elf_start` and
+//! `_binary_symbols_elf_end`.
+
+use core::fmt;
+use core::prelude::*;
+
+use kernel::elf::Symbols;
+
+/// Shows a mangled Rust name as a path, such as `kernel::main`.
+struct Demangle<'a>(&'a str);
+
+impl<'a> fmt::Show for Demangle<'a> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let Demangle(name) = *self;
+        if !name.starts_with("_ZN") || !name.ends_with("E") {
+            return write!(f, "{}", name);
+        }
+
+        // _ZN <len><segment>... E, with a trailing hash segment `h<16 digits>`
+        let mut rest = &name[3..name.len() - 1];
+        let mut first = true;
+        while !rest.is_empty() {
+            let mut len = 0;
+            let mut digits = 0;
+            for &c in rest.as_bytes().iter() {
+                if c < b'0' || c > b'9' {
+                    break;
+                }
+                len = len * 10 + (c - b'0') as usize;
+                digits += 1;
+            }
+            if digits == 0 || digits + len > rest.len() {
+                return write!(f, "{}", name);
+            }
+            let segment = &rest[digits..digits + len];
+            rest = &rest[digits + len..];
+            if rest.is_empty() && len == 17 && segment.starts_with("h") {
+                break;
+            }
+            if !first {
+                try!(write!(f, "::"));
+            }
+            try!(write!(f, "{}", segment));
+            first = false;
+        }
+        Ok(())
+    }
+}
+
+/// Finds the kernel function containing `addr` and the offset into it.
+pub fn lookup(addr: usize) -> Option<(&'static str, usize)> {
+    unsafe {
+        let start = &_binary_symbols_elf_start as *const u8;
+        let size = &_binary_symbols_elf_end as *const u8 as usize - start as usize;
+        if size == 0 {
+            return None;
+        }
+        Symbols::new(start, size).and_then(|symbols| symbols.lookup(addr))
+    }
+}
+
+/// Prints one line of a backtrace.
+pub fn print_frame(addr: usize) {
+    match lookup(addr) {
+        Some((name, offset)) => println!("  {:08x} {}+0x{:x}", addr, Demangle(name), offset),
+        None => println!("  {:08x} ??", addr)
+    }
+}
+
+extern {
+    static _binary_symbols_elf_start: u8;
+    static _binary_symbols_elf_end: u8;
+}
@@ -0,0 +1,94 @@
+//! Block devices: disks read and written in whole sectors. Drivers register
+//! each disk they find; the rest of the kernel looks them up by number.
+
+use core::fmt;
+use core::prelude::*;
+
+#[derive(Copy, PartialEq)]
+pub enum Error {
+    /// The request goes past the end of the device, or isn't a whole
+    /// number of sectors.
+    OutOfRange,
+    /// The device reported an error.
+    Io,
+    /// The device didn't answer in time.
+    Timeout,
+    ReadOnly
+}
+
+impl fmt::Show for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Error::OutOfRange => "out of range",
+            Error::Io => "I/O error",
+            Error::Timeout => "timeout",
+            Error::ReadOnly => "read-only device"
+        })
+    }
+}
+
+pub trait BlockDevice {
+    /// A name to show to people, such as "ata0".
+    fn name(&self) -> &str;
+
+    /// Bytes per sector.
+    fn sector_size(&self) -> usize;
+
+    /// The number of sectors.
+    fn sectors(&self) -> u64;
+
+    /// Reads the sectors starting at `lba` into `buf`, whose length is a
+    /// multiple of the sector size.
+    fn read(&mut self, lba: u64, buf: &mut [u8]) -> Result<(), Error>;
+
+    /// Writes `buf` to the sectors starting at `lba`.
+    fn write(&mut self, lba: u64, buf: &[u8]) -> Result<(), Error>;
+
+    /// The size in bytes.
+    fn size(&self) -> u64 {
+        self.sectors() * self.sector_size() as u64
+    }
+
+    /// Checks a request for `len` bytes at `lba` and returns its sector
+    /// count.
+    fn check(&self, lba: u64, len: usize) -> Result<u64, Error> {
+        let size = self.sector_size();
+        let count = (len / size) as u64;
+        if len % size != 0 || lba > self.sectors() || count > self.sectors() - lba {
+            return Err(Error::OutOfRange);
+        }
+        Ok(count)
+    }
+}
+
+const MAX_DEVICES: usize = 8;
+static mut devices: [Option<&'static mut (BlockDevice + 'static)>; ..MAX_DEVICES] =
+    [None, None, None, None, None, None, None, None];
+
+/// Adds a device, which lives for the rest of the kernel's life. Returns
+/// its number, or `None` when there is no room.
+pub fn register(device: &'static mut (BlockDevice + 'static)) -> Option<usize> {
+    unsafe {
+        for (i, slot) in devices.iter_mut().enumerate() {
+            if slot.is_none() {
+                *slot = Some(device);
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Device number `n`.
+pub fn get(n: usize) -> Option<&'static mut (BlockDevice + 'static)> {
+    unsafe {
+        if n >= MAX_DEVICES {
+            return None;
+        }
+        match devices[n] {
+            Some(ref mut device) => Some(&mut **device),
+            None => None
+        }
+    }
+}
+
@@ -0,0 +1,68 @@
+use core::mem::forget;
+use core::ops::{Deref, DerefMut, Drop};
+use core::ptr;
+
+use kernel::heap;
+
+/// A pointer to a value owned on the kernel heap, freed when dropped.
+pub struct Box<T> {
+    ptr: *mut T
+}
+
+impl<T> Box<T> {
+    pub fn new(x: T) -> Box<T> {
+        unsafe {
+            let ptr = heap::alloc::<T>(1);
+            ptr::write(ptr, x);
+            Box { ptr: ptr }
+        }
+    }
+
+    /// Takes ownership of a value allocated with `heap::alloc`.
+    pub unsafe fn from_raw(ptr: *mut T) -> Box<T> {
+        Box { ptr: ptr }
+    }
+
+    /// Gives up ownership. The value stays on the heap until `heap::free`.
+    pub fn into_raw(self) -> *mut T {
+        let ptr = self.ptr;
+        unsafe { forget(self); }
+        ptr
+    }
+
+    /// Moves the value out and frees its memory.
+    pub fn into_inner(self) -> T {
+        unsafe {
+            let ptr = self.into_raw();
+            let value = ptr::read(ptr as *const T);
+            heap::free(ptr);
+            value
+        }
+    }
+}
+
+impl<T> Deref for Box<T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.ptr }
+    }
+}
+
+impl<T> DerefMut for Box<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.ptr }
+    }
+}
+
+#[unsafe_destructor]
+impl<T> Drop for Box<T> {
+    fn drop(&mut self) {
+        unsafe {
+            ptr::read(self.ptr as *const T);
+            heap::free(self.ptr);
+        }
+    }
+}
@@ -0,0 +1,153 @@
+use core::ops::Drop;
+use core::prelude::*;
+
+use super::Box;
+
+struct Node<T> {
+    value: T,
+    prev: *mut Node<T>,
+    next: *mut Node<T>
+}
+
+/// A doubly-linked list with nodes on the kernel heap.
+pub struct LinkedList<T> {
+    head: *mut Node<T>,
+    tail: *mut Node<T>,
+    len: usize
+}
+
+/// An iterator over references to the elements of a list.
+pub struct Items<'a, T: 'a> {
+    next: Option<&'a Node<T>>
+}
+
+impl<T> LinkedList<T> {
+    #[inline]
+    pub fn new() -> LinkedList<T> {
+        LinkedList { head: 0 as *mut Node<T>, tail: 0 as *mut Node<T>, len: 0 }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    pub fn push_front(&mut self, value: T) {
+        let node = Box::new(Node {
+            value: value,
+            prev: 0 as *mut Node<T>,
+            next: self.head
+        }).into_raw();
+        unsafe {
+            match self.head.as_mut() {
+                Some(head) => head.prev = node,
+                None => self.tail = node
+            }
+        }
+        self.head = node;
+        self.len += 1;
+    }
+
+    pub fn push_back(&mut self, value: T) {
+        let node = Box::new(Node {
+            value: value,
+            prev: self.tail,
+            next: 0 as *mut Node<T>
+        }).into_raw();
+        unsafe {
+            match self.tail.as_mut() {
+                Some(tail) => tail.next = node,
+                None => self.head = node
+            }
+        }
+        self.tail = node;
+        self.len += 1;
+    }
+
+    pub fn pop_front(&mut self) -> Option<T> {
+        if self.head.is_null() {
+            return None;
+        }
+        unsafe {
+            let node = Box::from_raw(self.head).into_inner();
+            self.head = node.next;
+            match self.head.as_mut() {
+                Some(head) => head.prev = 0 as *mut Node<T>,
+                None => self.tail = 0 as *mut Node<T>
+            }
+            self.len -= 1;
+            Some(node.value)
+        }
+    }
+
+    pub fn pop_back(&mut self) -> Option<T> {
+        if self.tail.is_null() {
+            return None;
+        }
+        unsafe {
+            let node = Box::from_raw(self.tail).into_inner();
+            self.tail = node.prev;
+            match self.tail.as_mut() {
+                Some(tail) => tail.next = 0 as *mut Node<T>,
+                None => self.head = 0 as *mut Node<T>
+            }
+            self.len -= 1;
+            Some(node.value)
+        }
+    }
+
+    #[inline]
+    pub fn front<'a>(&'a self) -> Option<&'a T> {
+        unsafe { self.head.as_ref().map(|node| &node.value) }
+    }
+
+    #[inline]
+    pub fn back<'a>(&'a self) -> Option<&'a T> {
+        unsafe { self.tail.as_ref().map(|node| &node.value) }
+    }
+
+    #[inline]
+    pub fn front_mut<'a>(&'a mut self) -> Option<&'a mut T> {
+        unsafe { self.head.as_mut().map(|node| &mut node.value) }
+    }
+
+    #[inline]
+    pub fn back_mut<'a>(&'a mut self) -> Option<&'a mut T> {
+        unsafe { self.tail.as_mut().map(|node| &mut node.value) }
+    }
+
+    #[inline]
+    pub fn iter<'a>(&'a self) -> Items<'a, T> {
+        Items { next: unsafe { self.head.as_ref() } }
+    }
+
+    pub fn clear(&mut self) {
+        while self.pop_front().is_some() {}
+    }
+}
+
+impl<'a, T> Iterator for Items<'a, T> {
+    type Item = &'a T;
+
+    fn next(&mut self) -> Option<&'a T> {
+        match self.next {
+            Some(node) => {
+                self.next = unsafe { node.next.as_ref() };
+                Some(&node.value)
+            }
+            None => None
+        }
+    }
+}
+
+#[unsafe_destructor]
+impl<T> Drop for LinkedList<T> {
+    fn drop(&mut self) {
+        self.clear();
+    }
+}
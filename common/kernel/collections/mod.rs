@@ -0,0 +1,11 @@
+//! Owning pointers and containers backed by the kernel heap.
+
+pub use self::boxed::Box;
+pub use self::vec::Vec;
+pub use self::string::String;
+pub use self::list::LinkedList;
+
+pub mod boxed;
+pub mod vec;
+pub mod string;
+pub mod list;
@@ -0,0 +1,87 @@
+use core::fmt;
+use core::ops::Deref;
+use core::prelude::*;
+use core::str::from_utf8_unchecked;
+
+use super::Vec;
+
+/// A growable UTF-8 string on the kernel heap.
+pub struct String {
+    vec: Vec<u8>
+}
+
+impl String {
+    #[inline]
+    pub fn new() -> String {
+        String { vec: Vec::new() }
+    }
+
+    pub fn with_capacity(capacity: usize) -> String {
+        String { vec: Vec::with_capacity(capacity) }
+    }
+
+    pub fn from_str(s: &str) -> String {
+        let mut string = String::with_capacity(s.len());
+        string.push_str(s);
+        string
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.vec.len()
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.vec.is_empty()
+    }
+
+    pub fn push(&mut self, ch: char) {
+        let mut buf = [0u8; 4];
+        let n = ch.encode_utf8(&mut buf).unwrap_or(0);
+        self.vec.push_all(&buf[..n]);
+    }
+
+    #[inline]
+    pub fn push_str(&mut self, s: &str) {
+        self.vec.push_all(s.as_bytes());
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.vec.clear();
+    }
+
+    #[inline]
+    pub fn as_slice<'a>(&'a self) -> &'a str {
+        unsafe { from_utf8_unchecked(self.vec.as_slice()) }
+    }
+
+    #[inline]
+    pub fn as_bytes<'a>(&'a self) -> &'a [u8] {
+        self.vec.as_slice()
+    }
+}
+
+impl Deref for String {
+    type Target = str;
+
+    #[inline]
+    fn deref(&self) -> &str {
+        self.as_slice()
+    }
+}
+
+/// Allows `write!` into a string.
+impl fmt::FormatWriter for String {
+    fn write(&mut self, bytes: &[u8]) -> fmt::Result {
+        self.vec.push_all(bytes);
+        Ok(())
+    }
+}
+
+impl fmt::Show for String {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        self.as_slice().fmt(f)
+    }
+}
@@ -0,0 +1,227 @@
+use core::cmp::max;
+use core::intrinsics::{copy, copy_nonoverlapping};
+use core::mem::transmute;
+use core::ops::{Deref, DerefMut, Drop};
+use core::prelude::*;
+use core::ptr;
+use core::raw;
+
+use kernel::heap;
+use kernel::mm::OutOfMemory;
+use rust_core::fail::out_of_memory;
+
+/// A growable array on the kernel heap.
+pub struct Vec<T> {
+    ptr: *mut T,
+    len: usize,
+    cap: usize
+}
+
+impl<T> Vec<T> {
+    #[inline]
+    pub fn new() -> Vec<T> {
+        Vec { ptr: 0 as *mut T, len: 0, cap: 0 }
+    }
+
+    pub fn with_capacity(capacity: usize) -> Vec<T> {
+        let mut vec = Vec::new();
+        vec.reserve(capacity);
+        vec
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn capacity(&self) -> usize {
+        self.cap
+    }
+
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.len == 0
+    }
+
+    /// Makes room for at least `additional` more elements.
+    pub fn reserve(&mut self, additional: usize) {
+        let needed = match self.len.checked_add(additional) {
+            Some(n) => n,
+            None => out_of_memory()
+        };
+        if needed <= self.cap {
+            return;
+        }
+
+        let cap = self.grown(needed);
+        unsafe {
+            self.ptr = if self.cap == 0 {
+                heap::alloc::<T>(cap)
+            } else {
+                heap::realloc_raw(self.ptr, cap)
+            };
+        }
+        self.cap = cap;
+    }
+
+    /// Like `reserve`, but fails instead of aborting when the heap is
+    /// exhausted. The elements stay where they are then.
+    pub fn try_reserve(&mut self, additional: usize) -> Result<(), OutOfMemory> {
+        let needed = match self.len.checked_add(additional) {
+            Some(n) => n,
+            None => return Err(OutOfMemory)
+        };
+        if needed <= self.cap {
+            return Ok(());
+        }
+
+        let cap = self.grown(needed);
+        unsafe {
+            // not `realloc_raw`, which frees the old block first
+            let ptr = match heap::try_alloc::<T>(cap) {
+                Some(ptr) => ptr,
+                None => return Err(OutOfMemory)
+            };
+            if self.cap != 0 {
+                copy_nonoverlapping(ptr, self.ptr as *const T, self.len);
+                heap::free(self.ptr);
+            }
+            self.ptr = ptr;
+        }
+        self.cap = cap;
+        Ok(())
+    }
+
+    /// The capacity to grow to for `needed` elements.
+    fn grown(&self, needed: usize) -> usize {
+        let mut cap = max(self.cap * 2, 4);
+        while cap < needed {
+            cap *= 2;
+        }
+        cap
+    }
+
+    pub fn push(&mut self, value: T) {
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+        unsafe {
+            ptr::write(self.ptr.offset(self.len as isize), value);
+        }
+        self.len += 1;
+    }
+
+    /// Like `push`, but gives up when the heap is exhausted.
+    pub fn try_push(&mut self, value: T) -> Result<(), OutOfMemory> {
+        if self.len == self.cap {
+            try!(self.try_reserve(1));
+        }
+        self.push(value);
+        Ok(())
+    }
+
+    pub fn pop(&mut self) -> Option<T> {
+        if self.len == 0 {
+            return None;
+        }
+        self.len -= 1;
+        unsafe {
+            Some(ptr::read(self.ptr.offset(self.len as isize) as *const T))
+        }
+    }
+
+    /// Inserts an element at `index`, shifting the following elements.
+    pub fn insert(&mut self, index: usize, value: T) {
+        assert!(index <= self.len);
+        if self.len == self.cap {
+            self.reserve(1);
+        }
+        unsafe {
+            let p = self.ptr.offset(index as isize);
+            copy(p.offset(1), p as *const T, self.len - index);
+            ptr::write(p, value);
+        }
+        self.len += 1;
+    }
+
+    /// Removes the element at `index`, shifting the following elements.
+    pub fn remove(&mut self, index: usize) -> T {
+        assert!(index < self.len);
+        self.len -= 1;
+        unsafe {
+            let p = self.ptr.offset(index as isize);
+            let value = ptr::read(p as *const T);
+            copy(p, p.offset(1) as *const T, self.len - index);
+            value
+        }
+    }
+
+    /// Removes the element at `index`, replacing it with the last one.
+    pub fn swap_remove(&mut self, index: usize) -> T {
+        assert!(index < self.len);
+        let last = self.len - 1;
+        self.as_mut_slice().swap(index, last);
+        self.pop().unwrap()
+    }
+
+    pub fn truncate(&mut self, len: usize) {
+        while self.len > len {
+            self.pop();
+        }
+    }
+
+    #[inline]
+    pub fn clear(&mut self) {
+        self.truncate(0);
+    }
+
+    #[inline]
+    pub fn as_slice<'a>(&'a self) -> &'a [T] {
+        unsafe {
+            transmute(raw::Slice { data: self.ptr as *const T, len: self.len })
+        }
+    }
+
+    #[inline]
+    pub fn as_mut_slice<'a>(&'a mut self) -> &'a mut [T] {
+        unsafe {
+            transmute(raw::Slice { data: self.ptr as *const T, len: self.len })
+        }
+    }
+}
+
+impl<T: Clone> Vec<T> {
+    pub fn push_all(&mut self, other: &[T]) {
+        self.reserve(other.len());
+        for x in other.iter() {
+            self.push(x.clone());
+        }
+    }
+}
+
+impl<T> Deref for Vec<T> {
+    type Target = [T];
+
+    #[inline]
+    fn deref(&self) -> &[T] {
+        self.as_slice()
+    }
+}
+
+impl<T> DerefMut for Vec<T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut [T] {
+        self.as_mut_slice()
+    }
+}
+
+#[unsafe_destructor]
+impl<T> Drop for Vec<T> {
+    fn drop(&mut self) {
+        self.clear();
+        if self.cap != 0 {
+            unsafe { heap::free(self.ptr); }
+        }
+    }
+}
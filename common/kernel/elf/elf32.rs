@@ -50,25 +50,25 @@ pub struct Phdr {
 
 #[repr(packed)]
 pub struct Elf32_Shdr {
-    sh_name: Elf32_Word,
-    sh_type: Elf32_Word,
-    sh_flags: Elf32_Word,
-    sh_addr: Elf32_Addr,
-    sh_offset: Elf32_Off,
-    sh_size: Elf32_Word,
-    sh_link: Elf32_Word,
-    sh_info: Elf32_Word,
-    sh_addralign: Elf32_Word,
-    sh_entsize: Elf32_Word,
+    pub sh_name: Elf32_Word,
+    pub sh_type: Elf32_Word,
+    pub sh_flags: Elf32_Word,
+    pub sh_addr: Elf32_Addr,
+    pub sh_offset: Elf32_Off,
+    pub sh_size: Elf32_Word,
+    pub sh_link: Elf32_Word,
+    pub sh_info: Elf32_Word,
+    pub sh_addralign: Elf32_Word,
+    pub sh_entsize: Elf32_Word,
 }
 
 pub struct Elf32_Sym {
-    st_name: Elf32_Word,
-    st_value: Elf32_Addr,
-    st_size: Elf32_Word,
-    st_info: c_uchar,
-    st_other: c_uchar,
-    st_shndx: Elf32_Section,
+    pub st_name: Elf32_Word,
+    pub st_value: Elf32_Addr,
+    pub st_size: Elf32_Word,
+    pub st_info: c_uchar,
+    pub st_other: c_uchar,
+    pub st_shndx: Elf32_Section,
 }
 
 pub struct Elf32_Syminfo {
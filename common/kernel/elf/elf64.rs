@@ -49,25 +49,25 @@ pub struct Elf64_Phdr {
 
 #[repr(packed)]
 pub struct Elf64_Shdr {
-    sh_name: Elf64_Word,
-    sh_type: Elf64_Word,
-    sh_flags: Elf64_Xword,
-    sh_addr: Elf64_Addr,
-    sh_offset: Elf64_Off,
-    sh_size: Elf64_Xword,
-    sh_link: Elf64_Word,
-    sh_info: Elf64_Word,
-    sh_addralign: Elf64_Xword,
-    sh_entsize: Elf64_Xword,
+    pub sh_name: Elf64_Word,
+    pub sh_type: Elf64_Word,
+    pub sh_flags: Elf64_Xword,
+    pub sh_addr: Elf64_Addr,
+    pub sh_offset: Elf64_Off,
+    pub sh_size: Elf64_Xword,
+    pub sh_link: Elf64_Word,
+    pub sh_info: Elf64_Word,
+    pub sh_addralign: Elf64_Xword,
+    pub sh_entsize: Elf64_Xword,
 }
 
 pub struct Elf64_Sym {
-    st_name: Elf64_Word,
-    st_info: c_uchar,
-    st_other: c_uchar,
-    st_shndx: Elf64_Section,
-    st_value: Elf64_Addr,
-    st_size: Elf64_Xword,
+    pub st_name: Elf64_Word,
+    pub st_info: c_uchar,
+    pub st_other: c_uchar,
+    pub st_shndx: Elf64_Section,
+    pub st_value: Elf64_Addr,
+    pub st_size: Elf64_Xword,
 }
 pub struct Elf64_Syminfo {
     si_boundto: Elf64_Half,
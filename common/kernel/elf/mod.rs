@@ -1,14 +1,18 @@
 use core::ptr::{copy_nonoverlapping, write_bytes};
-use core::mem::transmute;
+use core::mem::{transmute, size_of};
 use core::prelude::*;
+use core::raw;
 use core;
 
 use kernel::process::Process;
 use kernel::mm;
+use kernel::mm::OutOfMemory;
 use platform::io;
 
 #[cfg(target_pointer_width = "32")] pub use self::elf32::{Ehdr, Phdr, Auxv, AuxvValue, AuxvType};
 #[cfg(target_pointer_width = "64")] pub use self::elf64::{Ehdr, Phdr, Auxv, AuxvValue, AuxvType};
+#[cfg(target_pointer_width = "32")] pub use self::elf32::{Elf32_Shdr as Shdr, Elf32_Sym as Sym};
+#[cfg(target_pointer_width = "64")] pub use self::elf64::{Elf64_Shdr as Shdr, Elf64_Sym as Sym};
 #[cfg(target_pointer_width = "32")] mod elf32;
 #[cfg(target_pointer_width = "64")] mod elf64;
 
@@ -30,6 +34,9 @@ enum HeaderType {
     PT_HIPROC = 0x7fffffff
 }
 
+const SHT_SYMTAB: u32 = 2;
+const STT_FUNC: u8 = 2;
+
 bitflags!(flags HeaderFlags: u32 {
     const PT_X = 1,
     const PT_R = 2,
@@ -48,16 +55,16 @@ struct ELFIdent {
 }
 
 trait EhdrT {
-    unsafe fn spawn_process(&self) -> Process;
+    unsafe fn spawn_process(&self) -> Result<Process, OutOfMemory>;
 }
 
 trait PhdrT {
-    unsafe fn load(&self, task: &Process, buffer: *const u8);
+    unsafe fn load(&self, task: &mut Process, buffer: *const u8) -> Result<(), OutOfMemory>;
 }
 
 impl EhdrT for self::Ehdr {
-    unsafe fn spawn_process(&self) -> Process {
-        let mut task = Process::new();
+    unsafe fn spawn_process(&self) -> Result<Process, OutOfMemory> {
+        let mut task = try!(Process::new());
         //TODO: Verify file integrity
         let buffer: *const u8 = transmute(self);
         let ph_size = self.e_phentsize as isize;
@@ -68,23 +75,40 @@ impl EhdrT for self::Ehdr {
         for i in 0..self.e_phnum {
             let pheader = ph_base.offset(ph_size * i as isize) as *const Phdr;
 
-            match (*pheader).p_type {
-                HeaderType::PT_NULL => {}
-                HeaderType::PT_LOAD => (*pheader).load(&task, buffer),
-                HeaderType::PT_DYNAMIC => (*pheader).load(&task, buffer),
+            let loaded = match (*pheader).p_type {
+                HeaderType::PT_NULL => Ok(()),
+                HeaderType::PT_LOAD => (*pheader).load(&mut task, buffer),
+                HeaderType::PT_DYNAMIC => (*pheader).load(&mut task, buffer),
                 HeaderType::PT_GNU_STACK => {
                     if (*pheader).p_flags.contains(PT_X) {
                         // We don't need an executable stack
                         stack_flags = mm::Flags::empty();
                     }
+                    Ok(())
                 },
-                _ => {}
+                _ => Ok(())
+            };
+
+            match loaded {
+                Err(e) => {
+                    task.kill();
+                    return Err(e);
+                }
+                Ok(()) => {}
             }
         }
 
         static stack_bottom: u32 = 0xC0000000;
-        let stack_vaddr = (stack_bottom - 0x1000) as *mut u8;
-        task.mmap(stack_vaddr, 0x1000, stack_flags);
+        // interrupts run kernel code on this stack too
+        static stack_size: u32 = 0x4000;
+        let stack_vaddr = (stack_bottom - stack_size) as *mut u8;
+        match task.mmap(stack_vaddr, stack_size as usize, stack_flags) {
+            Err(e) => {
+                task.kill();
+                return Err(e);
+            }
+            Ok(()) => {}
+        }
         let stack_ptr = (stack_bottom as *mut u8).offset(-(((4 + 5 + 15) & !0xF) + 8 + 4 + 4 + 4));
         let argv_ptr = stack_ptr as *mut *mut u8;
         let envp_ptr = argv_ptr.offset(2);
@@ -101,13 +125,14 @@ impl EhdrT for self::Ehdr {
 
         // return entry address
         task.esp = stack_ptr as u32;
+        task.stack_end = stack_vaddr as u32;
         task.eip = transmute(self.e_entry);
-        task
+        Ok(task)
     }
 }
 
 impl PhdrT for self::Phdr {
-    unsafe fn load(&self, task: &Process, buffer: *const u8) {
+    unsafe fn load(&self, task: &mut Process, buffer: *const u8) -> Result<(), OutOfMemory> {
         let vaddr = self.p_vaddr as *mut u8;
         let mem_size = self.p_memsz as usize;
         let file_pos = self.p_offset as isize;
@@ -119,10 +144,11 @@ impl PhdrT for self::Phdr {
             mm::Flags::empty()
         };
 
-        task.mmap(vaddr, mem_size, flags);
+        try!(task.mmap(vaddr, mem_size, flags));
 
         copy_nonoverlapping(vaddr, buffer.offset(file_pos), file_size);
         write_bytes(vaddr.offset(file_size as isize), 0, mem_size - file_size);
+        Ok(())
     }
 }
 
@@ -144,9 +170,95 @@ impl ELFIdent {
     }
 }
 
-pub fn exec(buffer: *const u8) {
+/// Runs the program in `buffer`, reading from and writing to `terminal`.
+pub fn exec(buffer: *const u8, terminal: usize) {
     unsafe {
         let ident: &ELFIdent = transmute(buffer);
-        ident.load().map(|e| { e.spawn_process().enter() });
+        ident.load().map(|e| match e.spawn_process() {
+            Ok(mut task) => {
+                task.terminal = terminal;
+                task.enter()
+            }
+            Err(_) => println!("exec: out of memory, process not started")
+        });
+    }
+}
+
+/// The symbol table of an ELF image, with names from its string table.
+pub struct Symbols {
+    syms: *const Sym,
+    count: usize,
+    strtab: *const u8,
+    strtab_size: usize
+}
+
+impl Symbols {
+    /// Finds the symbol table in an ELF image of `size` bytes.
+    pub unsafe fn new(buffer: *const u8, size: usize) -> Option<Symbols> {
+        let ident: &ELFIdent = transmute(buffer);
+        let ehdr = match ident.load() {
+            Some(ehdr) => ehdr,
+            None => return None
+        };
+        let shnum = ehdr.e_shnum as usize;
+        if ehdr.e_shoff as usize + shnum * size_of::<Shdr>() > size {
+            return None;
+        }
+        let shdrs = buffer.offset(ehdr.e_shoff as isize) as *const Shdr;
+
+        for i in 0..shnum {
+            let symtab = &*shdrs.offset(i as isize);
+            if symtab.sh_type != SHT_SYMTAB || symtab.sh_link as usize >= shnum {
+                continue;
+            }
+            let strtab = &*shdrs.offset(symtab.sh_link as isize);
+            if symtab.sh_offset as usize + symtab.sh_size as usize > size
+            || strtab.sh_offset as usize + strtab.sh_size as usize > size {
+                return None;
+            }
+            return Some(Symbols {
+                syms: buffer.offset(symtab.sh_offset as isize) as *const Sym,
+                count: symtab.sh_size as usize / size_of::<Sym>(),
+                strtab: buffer.offset(strtab.sh_offset as isize),
+                strtab_size: strtab.sh_size as usize
+            });
+        }
+        None
+    }
+
+    /// Finds the function containing `addr` and the offset of `addr` into it.
+    pub fn lookup(&self, addr: usize) -> Option<(&'static str, usize)> {
+        let mut found: Option<&Sym> = None;
+        for i in 0..self.count {
+            let sym = unsafe { &*self.syms.offset(i as isize) };
+            let start = sym.st_value as usize;
+            if sym.st_info & 0xF != STT_FUNC || addr < start {
+                continue;
+            }
+            if sym.st_size != 0 && addr >= start + sym.st_size as usize {
+                continue;
+            }
+            // prefer the closest symbol below `addr`
+            match found {
+                Some(best) if best.st_value as usize >= start => {}
+                _ => found = Some(sym)
+            }
+        }
+        found.map(|sym| (self.name(sym), addr - sym.st_value as usize))
+    }
+
+    fn name(&self, sym: &Sym) -> &'static str {
+        let start = sym.st_name as usize;
+        let mut end = start;
+        unsafe {
+            // names are NUL-terminated
+            while end < self.strtab_size && *self.strtab.offset(end as isize) != 0 {
+                end += 1;
+            }
+            if start > end {
+                return "";
+            }
+            transmute(raw::Slice { data: self.strtab.offset(start as isize), len: end - start })
+        }
     }
 }
@@ -2,31 +2,56 @@ use core::mem::size_of;
 use core::num::Int;
 use core::prelude::*;
 
-use kernel::mm::{Allocator, Alloc, BuddyAlloc};
-use util::bitv;
+use kernel::mm;
+use kernel::mm::{Allocator, Alloc, BuddyAlloc, Stats};
+use kernel::sync::Spinlock;
+use platform::cpu;
+use util::bitv::PackedArray;
 
 use rust_core::fail::{abort, out_of_memory};
 
 pub static mut heap: Option<Alloc> = None;
+/// Serializes allocations between processors.
+static mut lock: Option<Spinlock<()>> = None;
 
-pub fn init() -> Alloc {
-    let alloc = Alloc::new(
-        BuddyAlloc::new(17, bitv::Bitv { storage: 0x100_000 as *mut u32 }),
-        0x110_000 as *mut u8,
-        0,
-    );
+pub fn init() {
     unsafe {
-        heap = Some(alloc);
+        let tree = PackedArray::from_raw(0x100_000 as *mut u32, 2, 1 << 18);
+        heap = Some(Alloc::new(BuddyAlloc::new(17, tree), 0x110_000 as *mut u8, 0));
+        lock = Some(Spinlock::new(()));
+    }
+}
+
+/// Runs `f` on the heap while holding its lock. Interrupts stay off
+/// meanwhile, so that a handler on this processor can't wait for the lock
+/// its own processor holds.
+#[inline]
+fn locked<R, F: FnOnce(&mut Alloc) -> R>(f: F) -> R {
+    cpu::without_interrupts(|| unsafe {
+        let _guard = get(lock.as_ref()).lock();
+        f(get(heap.as_mut()))
+    })
+}
+
+pub fn stats() -> Stats {
+    locked(|h| h.stats())
+}
+
+/// Returns `None` when the heap is exhausted.
+#[inline]
+pub unsafe fn try_malloc_raw(size: usize) -> Option<*mut u8> {
+    match locked(|h| h.alloc(size)) {
+        (_, 0) => None,
+        (ptr, _) => Some(ptr)
     }
-    alloc
 }
 
 #[lang = "exchange_malloc"]
 #[inline]
 pub unsafe fn malloc_raw(size: usize) -> *mut u8 {
-    match get(heap).alloc(size) {
-        (_, 0) => out_of_memory(),
-        (ptr, _) => ptr
+    match try_malloc_raw(size) {
+        Some(ptr) => ptr,
+        None => mm::oom("heap", stats())
     }
 }
 
@@ -38,28 +63,46 @@ pub unsafe extern "C" fn rust_allocate(size: usize, _align: usize) -> *mut u8 {
 #[lang = "exchange_free"]
 #[inline]
 pub unsafe fn free<T>(ptr: *mut T) {
-    get(heap).free(ptr as *mut u8);
+    locked(|h| h.free(ptr as *mut u8));
 }
 
+/// Returns `None` when the heap is exhausted or the size overflows.
 #[inline]
-pub unsafe fn alloc<T = u8>(count: usize) -> *mut T {
+pub unsafe fn try_alloc<T = u8>(count: usize) -> Option<*mut T> {
     match count.checked_mul(size_of::<T>()) {
-        None => out_of_memory(),
-        Some(size) => malloc_raw(size) as *mut T
+        None => None,
+        Some(size) => try_malloc_raw(size).map(|ptr| ptr as *mut T)
     }
 }
 
 #[inline]
-pub unsafe fn zero_alloc<T = u8>(count: usize) -> *mut T {
+pub unsafe fn alloc<T = u8>(count: usize) -> *mut T {
+    match try_alloc(count) {
+        Some(ptr) => ptr,
+        None => mm::oom("heap", stats())
+    }
+}
+
+/// Returns `None` when the heap is exhausted or the size overflows.
+#[inline]
+pub unsafe fn try_zero_alloc<T = u8>(count: usize) -> Option<*mut T> {
     match count.checked_mul(size_of::<T>()) {
-        None => out_of_memory(),
-        Some(size) => match get(heap).zero_alloc(size) {
-            (_, 0) => out_of_memory(),
-            (ptr, _) => ptr as *mut T
+        None => None,
+        Some(size) => match locked(|h| h.zero_alloc(size)) {
+            (_, 0) => None,
+            (ptr, _) => Some(ptr as *mut T)
         }
     }
 }
 
+#[inline]
+pub unsafe fn zero_alloc<T = u8>(count: usize) -> *mut T {
+    match try_zero_alloc(count) {
+        Some(ptr) => ptr,
+        None => mm::oom("heap", stats())
+    }
+}
+
 #[inline]
 pub unsafe fn realloc_raw<T>(ptr: *mut T, count: usize) -> *mut T {
     match count.checked_mul(size_of::<T>()) {
@@ -68,8 +111,8 @@ pub unsafe fn realloc_raw<T>(ptr: *mut T, count: usize) -> *mut T {
             free(ptr as *mut u8);
             0 as *mut T
         }
-        Some(size) => match get(heap).realloc(ptr as *mut u8, size) {
-            (_, 0) => out_of_memory(),
+        Some(size) => match locked(|h| h.realloc(ptr as *mut u8, size)) {
+            (_, 0) => mm::oom("heap", stats()),
             (ptr, _) => ptr as *mut T
         }
     }
@@ -0,0 +1,52 @@
+//! Input events from pointing devices, queued by drivers until programs read
+//! them with the `READ_MOUSE` system call.
+
+use core::option::Option;
+use core::option::Option::{Some, None};
+
+use platform::cpu;
+
+pub const BUTTON_LEFT: u32 = 1 << 0;
+pub const BUTTON_RIGHT: u32 = 1 << 1;
+pub const BUTTON_MIDDLE: u32 = 1 << 2;
+
+/// Movement since the previous event, positive to the right and up, and the
+/// buttons held.
+#[repr(C)]
+#[derive(Copy)]
+pub struct MouseEvent {
+    pub dx: i32,
+    pub dy: i32,
+    /// Wheel clicks, positive towards the user.
+    pub wheel: i32,
+    pub buttons: u32
+}
+
+const QUEUE_SIZE: usize = 128;
+static mut queue: [MouseEvent; ..QUEUE_SIZE] = [MouseEvent { dx: 0, dy: 0, wheel: 0, buttons: 0 }; QUEUE_SIZE];
+static mut head: usize = 0;
+static mut len: usize = 0;
+
+/// Queues an event, from an interrupt handler. When nobody reads them, the
+/// newest are dropped.
+pub fn push_mouse(event: MouseEvent) {
+    unsafe {
+        if len < QUEUE_SIZE {
+            queue[(head + len) % QUEUE_SIZE] = event;
+            len += 1;
+        }
+    }
+}
+
+/// Takes the oldest mouse event not yet read.
+pub fn read_mouse() -> Option<MouseEvent> {
+    cpu::without_interrupts(|| unsafe {
+        if len == 0 {
+            return None;
+        }
+        let event = queue[head];
+        head = (head + 1) % QUEUE_SIZE;
+        len -= 1;
+        Some(event)
+    })
+}
@@ -0,0 +1,254 @@
+//! Keyboard layouts, shared by every keyboard driver.
+//!
+//! A layout gives the character of each key in three levels: plain, with
+//! Shift and with AltGr. Keys are numbered like scancode set 1; drivers for
+//! other keyboards translate to these numbers. Combining accents in a layout
+//! are dead keys, which change the next character typed.
+
+use core::prelude::*;
+
+pub struct Keymap {
+    pub name: &'static str,
+    /// Characters of keys 0x00 to 0x39, then the extra key of ISO
+    /// keyboards (0x56). A level may be left empty.
+    plain: &'static str,
+    shift: &'static str,
+    altgr: &'static str
+}
+
+#[derive(Copy, PartialEq)]
+pub enum Level {
+    Plain,
+    Shift,
+    AltGr
+}
+
+pub const DEAD_GRAVE: char = '\u{300}';
+pub const DEAD_ACUTE: char = '\u{301}';
+pub const DEAD_CIRCUMFLEX: char = '\u{302}';
+pub const DEAD_TILDE: char = '\u{303}';
+pub const DEAD_DIAERESIS: char = '\u{308}';
+
+/// The extra key between left Shift and Z on ISO keyboards.
+const ISO_KEY: u8 = 0x56;
+const KEYS: usize = 0x3A;
+
+impl Keymap {
+    /// A layout in the format of the built-in ones, to be passed to `load`.
+    pub fn new(name: &'static str, plain: &'static str, shift: &'static str,
+               altgr: &'static str) -> Keymap {
+        Keymap { name: name, plain: plain, shift: shift, altgr: altgr }
+    }
+
+    /// The character on key `code` at `level`, if any.
+    pub fn lookup(&self, code: u8, level: Level) -> Option<char> {
+        let index = match code {
+            ISO_KEY => KEYS,
+            c if (c as usize) < KEYS => c as usize,
+            _ => return None
+        };
+        let chars = match level {
+            Level::Plain => self.plain,
+            Level::Shift => self.shift,
+            Level::AltGr => self.altgr
+        };
+        match chars.chars().nth(index) {
+            Some('\0') | None => None,
+            some => some
+        }
+    }
+
+    /// Whether the layout has an AltGr level. Without one, right Alt is
+    /// just Alt.
+    pub fn has_altgr(&self) -> bool {
+        !self.altgr.is_empty()
+    }
+
+    /// Whether Caps Lock shifts key `code`: its plain and shifted characters
+    /// are a lowercase and uppercase letter.
+    pub fn caps(&self, code: u8) -> bool {
+        match (self.lookup(code, Level::Plain), self.lookup(code, Level::Shift)) {
+            (Some(lower), Some(upper)) => {
+                let (l, u) = (lower as u32, upper as u32);
+                l == u + 0x20 && (l >= 'a' as u32 && l <= 'z' as u32 || l >= 0xE0 && l <= 0xFE && l != 0xF7)
+            }
+            _ => false
+        }
+    }
+}
+
+pub fn is_dead(c: char) -> bool {
+    match c {
+        DEAD_GRAVE | DEAD_ACUTE | DEAD_CIRCUMFLEX | DEAD_TILDE | DEAD_DIAERESIS => true,
+        _ => false
+    }
+}
+
+/// The character a dead key types on its own, as when followed by space.
+fn spacing(dead: char) -> char {
+    match dead {
+        DEAD_GRAVE => '`',
+        DEAD_ACUTE => '´',
+        DEAD_CIRCUMFLEX => '^',
+        DEAD_TILDE => '~',
+        _ => '¨'
+    }
+}
+
+static COMPOSE: &'static [(char, &'static str, &'static str)] = &[
+    (DEAD_GRAVE, "aeiouAEIOU", "àèìòùÀÈÌÒÙ"),
+    (DEAD_ACUTE, "aeiouyAEIOUY", "áéíóúýÁÉÍÓÚÝ"),
+    (DEAD_CIRCUMFLEX, "aeiouAEIOU", "âêîôûÂÊÎÔÛ"),
+    (DEAD_TILDE, "anoANO", "ãñõÃÑÕ"),
+    (DEAD_DIAERESIS, "aeiouyAEIOU", "äëïöüÿÄËÏÖÜ")
+];
+
+/// Combines dead keys with the character typed after them.
+#[derive(Copy)]
+pub struct Composer {
+    dead: Option<char>
+}
+
+/// A `Composer` with no dead key pending, for statics.
+pub const COMPOSER_INIT: Composer = Composer { dead: None };
+
+impl Composer {
+    pub fn new() -> Composer {
+        COMPOSER_INIT
+    }
+
+    /// Feeds a typed character. Returns up to two characters to deliver: a
+    /// composed character, or the accent followed by `c` when they don't
+    /// combine. A dead key returns nothing until the next character.
+    pub fn feed(&mut self, c: char) -> (Option<char>, Option<char>) {
+        let dead = match self.dead {
+            Some(dead) => dead,
+            None if is_dead(c) => {
+                self.dead = Some(c);
+                return (None, None);
+            }
+            None => return (Some(c), None)
+        };
+        self.dead = None;
+        if c == ' ' || c == dead {
+            return (Some(spacing(dead)), None);
+        }
+        for &(accent, bases, composed) in COMPOSE.iter() {
+            if accent != dead {
+                continue;
+            }
+            for (base, result) in bases.chars().zip(composed.chars()) {
+                if base == c {
+                    return (Some(result), None);
+                }
+            }
+        }
+        if is_dead(c) {
+            self.dead = Some(c);
+            return (Some(spacing(dead)), None);
+        }
+        (Some(spacing(dead)), Some(c))
+    }
+}
+
+static US: &'static str = "\
+\0\x1B1234567890-=\x08\
+\tqwertyuiop[]\n\
+\0asdfghjkl;'`\
+\0\\zxcvbnm,./\0\
+*\0 \
+\\";
+static US_SHIFT: &'static str = "\
+\0\x1B!@#$%^&*()_+\x08\
+\tQWERTYUIOP{}\n\
+\0ASDFGHJKL:\"~\
+\0|ZXCVBNM<>?\0\
+*\0 \
+|";
+static US_ALTGR: &'static str = "";
+
+static DE: &'static str = "\
+\0\x1B1234567890ß\u{301}\x08\
+\tqwertzuiopü+\n\
+\0asdfghjklöä\u{302}\
+\0#yxcvbnm,.-\0\
+*\0 \
+<";
+static DE_SHIFT: &'static str = "\
+\0\x1B!\"§$%&/()=?\u{300}\x08\
+\tQWERTZUIOPÜ*\n\
+\0ASDFGHJKLÖÄ°\
+\0'YXCVBNM;:_\0\
+*\0 \
+>";
+static DE_ALTGR: &'static str = "\
+\0\0\0²³\0\0\0{[]}\\\0\0\
+\0@\0€\0\0\0\0\0\0\0\0~\0\
+\0\0\0\0\0\0\0\0\0\0\0\0\0\
+\0\0\0\0\0\0\0\0µ\0\0\0\0\
+\0\0\0\
+|";
+
+static FR: &'static str = "\
+\0\x1B&é\"'(-è_çà)=\x08\
+\tazertyuiop\u{302}$\n\
+\0qsdfghjklmù²\
+\0*wxcvbn,;:!\0\
+*\0 \
+<";
+static FR_SHIFT: &'static str = "\
+\0\x1B1234567890°+\x08\
+\tAZERTYUIOP\u{308}£\n\
+\0QSDFGHJKLM%\0\
+\0µWXCVBN?./§\0\
+*\0 \
+>";
+static FR_ALTGR: &'static str = "\
+\0\0\0\u{303}#{[|\u{300}\\^@]}\0\
+\0\0\0€\0\0\0\0\0\0\0\0¤\0\
+\0\0\0\0\0\0\0\0\0\0\0\0\0\
+\0\0\0\0\0\0\0\0\0\0\0\0\0\
+\0\0\0\
+\0";
+
+pub static LAYOUTS: [Keymap; ..3] = [
+    Keymap { name: "us", plain: US, shift: US_SHIFT, altgr: US_ALTGR },
+    Keymap { name: "de", plain: DE, shift: DE_SHIFT, altgr: DE_ALTGR },
+    Keymap { name: "fr", plain: FR, shift: FR_SHIFT, altgr: FR_ALTGR }
+];
+
+/// The layout used at boot, set with `KEYMAP=` when building.
+#[cfg(keymap = "de")] const DEFAULT: usize = 1;
+#[cfg(keymap = "fr")] const DEFAULT: usize = 2;
+#[cfg(not(any(keymap = "de", keymap = "fr")))] const DEFAULT: usize = 0;
+
+static mut current: Option<&'static Keymap> = None;
+
+/// The layout in use.
+pub fn keymap() -> &'static Keymap {
+    unsafe {
+        match current {
+            Some(keymap) => keymap,
+            None => &LAYOUTS[DEFAULT]
+        }
+    }
+}
+
+/// Switches to `keymap`.
+pub fn load(keymap: &'static Keymap) {
+    unsafe {
+        current = Some(keymap);
+    }
+}
+
+/// Switches to the built-in layout called `name`. Returns false if there is
+/// none.
+pub fn select(name: &[u8]) -> bool {
+    for layout in LAYOUTS.iter() {
+        if layout.name.as_bytes() == name {
+            load(layout);
+            return true;
+        }
+    }
+    false
+}
@@ -5,7 +5,7 @@ use core::intrinsics::{write_bytes, copy};
 use core::intrinsics::offset;
 use core::intrinsics::ctlz32;
 
-use util::bitv::Bitv;
+use util::bitv::PackedArray;
 
 #[repr(u8)]
 enum Node {
@@ -45,7 +45,14 @@ pub trait Allocator {
 /// [2]: http://dysphoria.net/OperatingSystems1/4_allocation_buddy_system.html
 pub struct BuddyAlloc {
     pub order: usize,
-    pub tree: Bitv
+    /// Two bits per node, see `Node`.
+    pub tree: PackedArray<'static>
+}
+
+/// Usage of an allocator's memory, in bytes.
+pub struct Stats {
+    pub capacity: usize,
+    pub used: usize
 }
 
 pub struct Alloc {
@@ -55,9 +62,11 @@ pub struct Alloc {
 }
 
 impl BuddyAlloc {
-    pub fn new(order: usize, storage: Bitv) -> BuddyAlloc {
-        storage.clear(1 << (order + 1));
-        BuddyAlloc { order: order, tree: storage }
+    /// `tree` holds the nodes of a tree with `1 << order` leaves.
+    pub fn new(order: usize, mut tree: PackedArray<'static>) -> BuddyAlloc {
+        assert!(tree.len() >= 1 << (order + 1));
+        tree.clear();
+        BuddyAlloc { order: order, tree: tree }
     }
 
     #[inline]
@@ -179,14 +188,24 @@ impl BuddyAlloc {
         }
     }
 
+    /// Counts the units taken by used nodes in the subtree at `index`.
+    fn used(&self, index: usize, level: usize) -> usize {
+        match self.get(index) {
+            Node::UNUSED => 0,
+            Node::USED | Node::FULL => 1 << level,
+            Node::SPLIT => self.used(index * 2 + 1, level - 1)
+                         + self.used(index * 2 + 2, level - 1)
+        }
+    }
+
     fn get(&self, i: usize) -> Node {
         unsafe {
-            transmute(self.tree.get(i))
+            transmute(self.tree.get(i) as u8)
         }
     }
 
-    fn set(&self, i: usize, x: Node) {
-        self.tree.set(i, x as u8);
+    fn set(&mut self, i: usize, x: Node) {
+        self.tree.set(i, x as u32);
     }
 }
 
@@ -219,4 +238,11 @@ impl Alloc {
     pub fn new(parent: BuddyAlloc, base: *mut u8, el_size: usize) -> Alloc {
         Alloc { parent: parent, base: base, el_size: el_size }
     }
+
+    pub fn stats(&self) -> Stats {
+        Stats {
+            capacity: 1 << self.parent.order << self.el_size,
+            used: self.parent.used(0, self.parent.order) << self.el_size
+        }
+    }
 }
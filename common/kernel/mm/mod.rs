@@ -1,9 +1,12 @@
 //! The memory management.
 
+use rust_core::fail::abort;
+
 pub use self::allocator::{
 	Allocator,
 	BuddyAlloc,
 	Alloc,
+	Stats,
 };
 
 pub use cpu::mmu::{
@@ -16,3 +19,13 @@ pub use cpu::mmu::{
 
 pub mod allocator;
 pub mod physical;
+
+/// Returned by fallible allocations when the allocator is exhausted.
+pub struct OutOfMemory;
+
+/// Reports which allocator ran out of memory and how it was used, then aborts.
+pub fn oom(allocator: &str, stats: Stats) -> ! {
+	println!("Out of memory: {} allocator, {} of {} bytes used",
+	         allocator, stats.used, stats.capacity);
+	abort()
+}
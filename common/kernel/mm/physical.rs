@@ -1,22 +1,17 @@
 use core::mem::transmute;
 use core::option::Option;
+use core::option::Option::{Some, None};
 
 use kernel::heap;
 use kernel::mm;
 use kernel::mm::Allocator;
 use cpu::mmu::Frame;
-use util::bitv;
+use util::bitv::PackedArray;
 
 use rust_core::fail::abort;
 
-pub static mut frames: mm::Alloc = mm::Alloc {
-    base: 0x200_000 as *mut u8,
-    el_size: 12,
-    parent: mm::BuddyAlloc {
-        order: 13,
-        tree: bitv::Bitv { storage: 0 as *mut u32 }
-    }
-};
+/// Set up by `init`, once the heap can hold its tree.
+pub static mut frames: Option<mm::Alloc> = None;
 
 pub struct Phys<T> {
     ptr: *mut T
@@ -58,25 +53,48 @@ impl<T> RawPtr<T> for Phys<T> {
 */
 pub fn init() {
     unsafe {
-        frames.parent.tree.storage = heap::zero_alloc::<u32>(1024);
+        let tree = PackedArray::from_raw(heap::zero_alloc::<u32>(1024), 2, 1 << 14);
+        frames = Some(mm::Alloc::new(mm::BuddyAlloc::new(13, tree), 0x200_000 as *mut u8, 12));
+    }
+}
+
+unsafe fn allocator() -> &'static mut mm::Alloc {
+    match frames {
+        Some(ref mut alloc) => alloc,
+        None => abort()
+    }
+}
+
+/// Returns `None` when no run of `count` free frames is left.
+pub unsafe fn try_alloc_frames<T = Frame>(count: usize) -> Option<Phys<T>> {
+    match allocator().alloc(count) {
+        (_, 0) => None,
+        (ptr, _) => Some(Phys { ptr: ptr as *mut T })
     }
 }
 
 pub unsafe fn alloc_frames<T = Frame>(count: usize) -> Phys<T> {
-    match frames.alloc(count) {
-        (_, 0) => abort(),
-        (ptr, _) => Phys { ptr: ptr as *mut T }
+    match try_alloc_frames(count) {
+        Some(phys) => phys,
+        None => mm::oom("frame", allocator().stats())
+    }
+}
+
+pub unsafe fn try_zero_alloc_frames<T = Frame>(count: usize) -> Option<Phys<T>> {
+    match allocator().zero_alloc(count) {
+        (_, 0) => None,
+        (ptr, _) => Some(Phys { ptr: ptr as *mut T })
     }
 }
 
 pub unsafe fn zero_alloc_frames<T = Frame>(count: usize) -> Phys<T> {
-    match frames.zero_alloc(count) {
-        (_, 0) => abort(),
-        (ptr, _) => Phys { ptr: ptr as *mut T }
+    match try_zero_alloc_frames(count) {
+        Some(phys) => phys,
+        None => mm::oom("frame", allocator().stats())
     }
 }
 
 #[inline]
 pub unsafe fn free_frames<T>(ptr: Phys<T>) {
-    frames.free(ptr.to_usize() as *mut u8);
+    allocator().free(ptr.as_ptr() as *mut u8);
 }
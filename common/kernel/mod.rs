@@ -8,6 +8,17 @@ pub use cpu::interrupt::Table;
 pub mod util;
 pub mod mm;
 pub mod heap;
+pub mod collections;
+pub mod panic;
+pub mod backtrace;
+pub mod syscall;
+pub mod sync;
+pub mod sched;
+pub mod time;
+pub mod keymap;
+pub mod input;
+pub mod block;
+pub mod net;
 mod process;
 #[allow(dead_code)]
 #[allow(non_camel_case_types)]
@@ -25,23 +36,30 @@ pub static mut int_table: Option<Table> = None;
 /// | ---------------------- | -------- | ----------- |
 /// | 0x0000 ... 0x7BFF      | 31 KiB   | Stack       |
 /// | 0x7C00 ... 0x7DFF      | 0.5 KiB  | Bootloader  |
-/// | 0x07E00 ... 0x0FFFF    | 32.5 KiB | _unused_    |
-/// | 0x10000 ... 0x1FFFF    | 64 KiB   | Kernel      |
+/// | 0x07E00 ... 0x07FFF    | 0.5 KiB  | _unused_    |
+/// | 0x08000 ... 0x08FFF    | 4 KiB    | SMP trampoline, copied by `cpu::smp` |
+/// | 0x09000 ... 0x0D7FF    | 18 KiB   | Floppy DMA buffer, used by `drivers::floppy` |
+/// | 0x0D800 ... 0x0FFFF    | 10 KiB   | _unused_    |
+/// | 0x10000 ... 0x2FFFF    | 128 KiB  | Kernel      |
 #[lang="start"]
 #[no_mangle]
 pub fn main() {
     heap::init();
+    // before anything that can fail, so that panics and faults show up
+    io::init();
     mm::physical::init();
 
     let table = interrupt::Table::new();
     unsafe {
         table.load();
         int_table = Some(table);
-        drivers::keydown = Some(io::putc);
+        drivers::keydown = Some(io::echo);
     }
     cpu::init();
 
     drivers::init();
-    elf::exec(&_binary_initram_elf_start);
+    println!("rustboot on {}", cpu::info());
+    // on the first terminal, next to the kernel log
+    elf::exec(&_binary_initram_elf_start, 0);
     extern { static _binary_initram_elf_start: u8; }
 }
@@ -0,0 +1,76 @@
+//! Network devices: Ethernet interfaces that send and receive whole frames.
+//! Drivers register each interface they find; the rest of the kernel looks
+//! them up by number.
+
+use core::fmt;
+use core::prelude::*;
+
+/// Longest frame without its checksum: header and 1500-byte payload.
+pub const MAX_FRAME: usize = 1514;
+
+#[derive(Copy, PartialEq)]
+pub enum Error {
+    /// The frame is longer than `MAX_FRAME`, or the buffer is too short
+    /// for the frame received.
+    TooLong,
+    /// No buffer is free to send the frame; try again later.
+    Busy,
+    /// The device reported an error.
+    Io
+}
+
+impl fmt::Show for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.pad(match *self {
+            Error::TooLong => "frame too long",
+            Error::Busy => "device busy",
+            Error::Io => "I/O error"
+        })
+    }
+}
+
+pub trait NetDevice {
+    /// A name to show to people, such as "eth0".
+    fn name(&self) -> &str;
+
+    /// The hardware address.
+    fn mac(&self) -> [u8; ..6];
+
+    /// Sends an Ethernet frame, starting with its header.
+    fn send(&mut self, frame: &[u8]) -> Result<(), Error>;
+
+    /// Takes the oldest frame received into `buf`. Returns its length, or
+    /// `None` when nothing came in.
+    fn receive(&mut self, buf: &mut [u8]) -> Result<Option<usize>, Error>;
+}
+
+const MAX_DEVICES: usize = 4;
+static mut devices: [Option<&'static mut (NetDevice + 'static)>; ..MAX_DEVICES] =
+    [None, None, None, None];
+
+/// Adds a device, which lives for the rest of the kernel's life. Returns
+/// its number, or `None` when there is no room.
+pub fn register(device: &'static mut (NetDevice + 'static)) -> Option<usize> {
+    unsafe {
+        for (i, slot) in devices.iter_mut().enumerate() {
+            if slot.is_none() {
+                *slot = Some(device);
+                return Some(i);
+            }
+        }
+    }
+    None
+}
+
+/// Device number `n`.
+pub fn get(n: usize) -> Option<&'static mut (NetDevice + 'static)> {
+    unsafe {
+        if n >= MAX_DEVICES {
+            return None;
+        }
+        match devices[n] {
+            Some(ref mut device) => Some(&mut **device),
+            None => None
+        }
+    }
+}
@@ -0,0 +1,69 @@
+//! Kernel panics.
+//!
+//! A panic is reported on every console, followed by a register dump. The
+//! kernel then halts, shuts down, reboots or exits QEMU, as it does after a
+//! fatal CPU exception. The action is chosen at build time with `PANIC=`
+//! (`--cfg panic="..."`) and can be changed with `set_action`.
+
+use core::fmt;
+
+use platform::{cpu, power};
+
+/// What the kernel does once a panic is reported.
+#[derive(Copy)]
+pub enum Action {
+    /// Stops the processor, leaving the report on screen.
+    Halt,
+    Shutdown,
+    Reboot,
+    /// Exits QEMU with a failure status, for automated runs.
+    ExitQemu(u8)
+}
+
+#[cfg(panic = "shutdown")] const DEFAULT: Action = Action::Shutdown;
+#[cfg(panic = "reboot")] const DEFAULT: Action = Action::Reboot;
+#[cfg(panic = "exit")] const DEFAULT: Action = Action::ExitQemu(1);
+#[cfg(not(any(panic = "shutdown", panic = "reboot", panic = "exit")))]
+const DEFAULT: Action = Action::Halt;
+
+static mut action: Action = DEFAULT;
+static mut panicking: bool = false;
+
+pub fn set_action(a: Action) {
+    unsafe {
+        action = a;
+    }
+}
+
+#[lang = "panic_fmt"]
+pub extern fn rust_begin_unwind(msg: &fmt::Arguments,
+                                file: &'static str, line: usize) -> ! {
+    unsafe {
+        if panicking {
+            // the report itself panicked
+            cpu::halt();
+        }
+        panicking = true;
+    }
+
+    println!("\nKernel panic at {}:{}: {}", file, line, msg);
+    cpu::dump_registers();
+    finish()
+}
+
+/// Takes the action chosen for panics, once a fatal error is reported.
+pub fn finish() -> ! {
+    match unsafe { action } {
+        Action::Halt => cpu::halt(),
+        Action::Shutdown => power::shutdown(),
+        Action::Reboot => power::reboot(),
+        Action::ExitQemu(code) => cpu::exit_qemu(code)
+    }
+}
+
+// TODO respect destructors
+#[lang = "begin_unwind"]
+unsafe extern "C" fn begin_unwind(msg: &fmt::Arguments,
+                                  file: &'static str, line: usize) -> ! {
+    rust_begin_unwind(msg, file, line)
+}
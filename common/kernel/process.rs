@@ -1,38 +1,86 @@
 use core::clone::Clone;
+use core::prelude::*;
+use core::result::Result;
 
-use kernel::mm::{Flags, PageDirectory};
+use kernel::collections::Vec;
+use kernel::mm::{Flags, PageDirectory, OutOfMemory};
 use kernel::mm::physical;
 
 use platform::cpu::mmu;
+use platform::cpu::fpu;
+use platform::cpu::fpu::FpuState;
+
+/// The terminal of the process running.
+static mut running_terminal: usize = 0;
+
+/// The terminal of the process making a system call.
+pub fn current_terminal() -> usize {
+    unsafe { running_terminal }
+}
 
 pub struct Process {
     pub eip: u32,
     pub esp: u32,
-    pub paging: physical::Phys<PageDirectory>
+    /// Lowest address of the stack.
+    pub stack_end: u32,
+    pub paging: physical::Phys<PageDirectory>,
+    pub fpu: FpuState,
+    /// The terminal its `READ` and `WRITE` system calls use.
+    pub terminal: usize,
+    /// Memory mapped with `mmap`, from start to end address.
+    regions: Vec<(usize, usize)>
 }
 
 impl Process {
-    pub fn new() -> Process {
-        Process {
+    /// Fails when there is no frame left for the page directory.
+    pub fn new() -> Result<Process, OutOfMemory> {
+        Ok(Process {
             eip: 0,
             esp: 0,
+            stack_end: 0,
             // paging: unsafe { physical::zero_alloc_frames(1) as *mut PageDirectory }
-            paging: unsafe { mmu::clone_directory() }
+            paging: try!(mmu::clone_directory()),
+            fpu: FpuState::new(),
+            terminal: 0,
+            regions: Vec::new()
+        })
+    }
+
+    /// Fails when memory runs out, leaving the rest of the kernel running.
+    pub fn mmap(&mut self, page_ptr: *mut u8, size: usize, flags: Flags) -> Result<(), OutOfMemory> {
+        // so that the push below can't fail
+        try!(self.regions.try_reserve(1));
+        unsafe {
+            try!((*self.paging.as_ptr()).map(page_ptr, size, flags));
         }
+        self.regions.push((page_ptr as usize, page_ptr as usize + size));
+        Ok(())
     }
 
-    pub fn mmap(&self, page_ptr: *mut u8, size: usize, flags: Flags) {
+    /// Releases the memory of a process that will never run: the frames it
+    /// mapped, the page tables made for them and the page directory.
+    pub fn kill(self) {
         unsafe {
-            (*self.paging.as_ptr()).map(page_ptr, size, flags);
+            let dir = &mut *self.paging.as_ptr();
+            for &(start, end) in self.regions.iter() {
+                dir.unmap(start as *mut u8, end as *mut u8);
+            }
+            mmu::free_directory(self.paging);
         }
+        self.fpu.free();
     }
 
     #[cfg(target_arch = "x86")]
     pub fn enter(&self) {
+        use platform::cpu;
+
         unsafe {
             //breakpoint();
             // TODO need to store physical address
+            running_terminal = self.terminal;
             mmu::switch_directory(self.paging);
+            fpu::switch_to(&self.fpu);
+            cpu::set_stack(self.stack_end as usize, self.esp as usize);
             asm!("xor %eax, %eax
                   xor %edx, %edx
                   jmp *$0" :: "m"(self.eip), "{esp}"(self.esp) :: "volatile")
@@ -0,0 +1,68 @@
+//! Per-CPU run queues of kernel tasks. Each processor runs the tasks queued
+//! for it, and idles until the next interrupt when there are none.
+
+use core::option::Option;
+use core::option::Option::{Some, None};
+use core::ptr;
+
+use kernel::collections::LinkedList;
+use kernel::heap;
+use kernel::sync::Spinlock;
+use platform::cpu;
+
+pub type Task = fn();
+
+type RunQueue = Spinlock<LinkedList<Task>>;
+
+static mut queues: *mut RunQueue = 0 as *mut RunQueue;
+static mut count: usize = 0;
+
+/// Creates a run queue for each of `cpus` processors.
+pub fn init(cpus: usize) {
+    unsafe {
+        queues = heap::alloc::<RunQueue>(cpus);
+        for i in 0..cpus {
+            ptr::write(queues.offset(i as isize), Spinlock::new(LinkedList::new()));
+        }
+        count = cpus;
+    }
+}
+
+fn queue<'a>(cpu: usize) -> Option<&'a RunQueue> {
+    unsafe {
+        if cpu < count {
+            Some(&*queues.offset(cpu as isize))
+        } else {
+            None
+        }
+    }
+}
+
+/// Queues a task on a processor. Returns `false` for an unknown processor.
+pub fn spawn(cpu: usize, task: Task) -> bool {
+    match queue(cpu) {
+        Some(q) => {
+            q.lock().push_back(task);
+            true
+        }
+        None => false
+    }
+}
+
+/// Takes the next task queued on the current processor.
+pub fn next() -> Option<Task> {
+    match queue(cpu::current()) {
+        Some(q) => q.lock().pop_front(),
+        None => None
+    }
+}
+
+/// Runs queued tasks on the current processor forever.
+pub fn run() -> ! {
+    loop {
+        match next() {
+            Some(task) => task(),
+            None => cpu::idle()
+        }
+    }
+}
@@ -0,0 +1,74 @@
+//! Locks shared between processors.
+
+use core::atomic::{AtomicBool, Ordering};
+use core::cell::UnsafeCell;
+use core::ops::{Deref, DerefMut, Drop};
+use core::option::Option;
+use core::option::Option::{Some, None};
+
+use platform::cpu;
+
+/// A lock that busy-waits. Holders must not be interrupted by code taking
+/// the same lock.
+pub struct Spinlock<T> {
+    locked: AtomicBool,
+    data: UnsafeCell<T>
+}
+
+unsafe impl<T: Send> Sync for Spinlock<T> {}
+
+/// Releases the lock when dropped.
+pub struct Guard<'a, T: 'a> {
+    lock: &'a Spinlock<T>
+}
+
+impl<T> Spinlock<T> {
+    pub fn new(data: T) -> Spinlock<T> {
+        Spinlock {
+            locked: AtomicBool::new(false),
+            data: UnsafeCell::new(data)
+        }
+    }
+
+    pub fn lock(&self) -> Guard<T> {
+        loop {
+            match self.try_lock() {
+                Some(guard) => return guard,
+                None => while self.locked.load(Ordering::Relaxed) {
+                    cpu::relax();
+                }
+            }
+        }
+    }
+
+    pub fn try_lock(&self) -> Option<Guard<T>> {
+        if self.locked.swap(true, Ordering::Acquire) {
+            None
+        } else {
+            Some(Guard { lock: self })
+        }
+    }
+}
+
+impl<'a, T> Deref for Guard<'a, T> {
+    type Target = T;
+
+    #[inline]
+    fn deref(&self) -> &T {
+        unsafe { &*self.lock.data.get() }
+    }
+}
+
+impl<'a, T> DerefMut for Guard<'a, T> {
+    #[inline]
+    fn deref_mut(&mut self) -> &mut T {
+        unsafe { &mut *self.lock.data.get() }
+    }
+}
+
+#[unsafe_destructor]
+impl<'a, T> Drop for Guard<'a, T> {
+    fn drop(&mut self) {
+        self.lock.locked.store(false, Ordering::Release);
+    }
+}
@@ -0,0 +1,118 @@
+//! System calls. The number goes in the first register and up to three
+//! arguments in the next ones; the result comes back in the first register.
+
+use core::mem::transmute;
+use core::option::Option::{Some, None};
+use core::raw;
+
+use kernel::input;
+use kernel::input::MouseEvent;
+use kernel::keymap;
+use kernel::process;
+use kernel::time;
+use kernel::time::UserTimespec;
+use platform::{io, power};
+
+pub const SHUTDOWN: u32 = 1;
+pub const REBOOT: u32 = 2;
+/// Fills the `UserTimespec` at the second argument with the clock named by
+/// the first, one of `time::CLOCK_*`.
+pub const CLOCK_GETTIME: u32 = 3;
+/// Switches to the keyboard layout named by the string at the first
+/// argument, of the length in the second, such as "de".
+pub const SET_KEYMAP: u32 = 4;
+/// Takes the oldest mouse event into the `MouseEvent` at the first argument.
+/// Returns `EAGAIN` when there is none.
+pub const READ_MOUSE: u32 = 5;
+/// Takes up to the second argument's count of keys typed on the caller's
+/// terminal into the buffer at the first. Returns how many were taken, or
+/// `EAGAIN` when there is none.
+pub const READ: u32 = 6;
+/// Writes the buffer at the first argument, of the length in the second,
+/// to the caller's terminal. Returns the length.
+pub const WRITE: u32 = 7;
+
+// Errors come back negated, with their numbers on Linux.
+
+/// Returned for unknown system calls.
+pub const ENOSYS: u32 = -38i32 as u32;
+/// Returned for invalid arguments.
+pub const EINVAL: u32 = -22i32 as u32;
+/// Returned when there is nothing to read yet.
+pub const EAGAIN: u32 = -11i32 as u32;
+
+pub fn dispatch(num: u32, args: [u32; ..3]) -> u32 {
+    match num {
+        SHUTDOWN => power::shutdown(),
+        REBOOT => power::reboot(),
+        CLOCK_GETTIME => clock_gettime(args[0], args[1] as *mut UserTimespec),
+        SET_KEYMAP => set_keymap(args[0] as *const u8, args[1] as usize),
+        READ_MOUSE => read_mouse(args[0] as *mut MouseEvent),
+        READ => read(args[0] as *mut u8, args[1] as usize),
+        WRITE => write(args[0] as *const u8, args[1] as usize),
+        _ => ENOSYS
+    }
+}
+
+// TODO check that `ts` belongs to the caller once processes leave ring 0
+fn clock_gettime(clock: u32, ts: *mut UserTimespec) -> u32 {
+    match (time::clock_gettime(clock), unsafe { ts.as_mut() }) {
+        (Some(now), Some(ts)) => {
+            *ts = now.to_user();
+            0
+        }
+        _ => EINVAL
+    }
+}
+
+// TODO check that `name` belongs to the caller once processes leave ring 0
+fn set_keymap(name: *const u8, len: usize) -> u32 {
+    if name.is_null() {
+        return EINVAL;
+    }
+    let name: &[u8] = unsafe { transmute(raw::Slice { data: name, len: len }) };
+    if keymap::select(name) { 0 } else { EINVAL }
+}
+
+// TODO check that `event` belongs to the caller once processes leave ring 0
+fn read_mouse(event: *mut MouseEvent) -> u32 {
+    match unsafe { event.as_mut() } {
+        Some(event) => match input::read_mouse() {
+            Some(e) => {
+                *event = e;
+                0
+            }
+            None => EAGAIN
+        },
+        None => EINVAL
+    }
+}
+
+// TODO check that `buf` belongs to the caller once processes leave ring 0
+fn read(buf: *mut u8, len: usize) -> u32 {
+    if buf.is_null() {
+        return EINVAL;
+    }
+    let terminal = process::current_terminal();
+    let mut count = 0;
+    while count < len {
+        match io::read_terminal(terminal) {
+            Some(c) => unsafe { *buf.offset(count as isize) = c },
+            None => break
+        }
+        count += 1;
+    }
+    if count == 0 && len > 0 { EAGAIN } else { count as u32 }
+}
+
+// TODO check that `buf` belongs to the caller once processes leave ring 0
+fn write(buf: *const u8, len: usize) -> u32 {
+    if buf.is_null() {
+        return EINVAL;
+    }
+    let terminal = process::current_terminal();
+    for i in 0..len {
+        io::write_terminal(terminal, unsafe { *buf.offset(i as isize) });
+    }
+    len as u32
+}
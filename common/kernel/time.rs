@@ -0,0 +1,159 @@
+//! Wall-clock and monotonic time. The wall clock is read once from the
+//! real-time clock at boot and then follows the monotonic clock.
+
+use core::fmt;
+use core::option::Option;
+use core::option::Option::{Some, None};
+
+use platform::cpu;
+use platform::drivers::{clock, rtc};
+
+pub const CLOCK_REALTIME: u32 = 0;
+pub const CLOCK_MONOTONIC: u32 = 1;
+
+const NANOS_PER_SEC: u64 = 1000000000;
+
+#[derive(Copy)]
+pub struct Timespec {
+    pub sec: u64,
+    pub nsec: u32
+}
+
+/// `struct timespec` as programs for Linux on i386 lay it out, for system
+/// calls.
+#[repr(C)]
+#[derive(Copy)]
+pub struct UserTimespec {
+    pub tv_sec: i32,
+    pub tv_nsec: i32
+}
+
+impl Timespec {
+    fn from_nanos(ns: u64) -> Timespec {
+        Timespec { sec: ns / NANOS_PER_SEC, nsec: (ns % NANOS_PER_SEC) as u32 }
+    }
+
+    /// Seconds wrap in 2038, as they do for such programs.
+    pub fn to_user(&self) -> UserTimespec {
+        UserTimespec { tv_sec: self.sec as i32, tv_nsec: self.nsec as i32 }
+    }
+}
+
+/// A date and time in UTC.
+#[derive(Copy)]
+pub struct DateTime {
+    pub year: u16,
+    pub month: u8,
+    pub day: u8,
+    pub hour: u8,
+    pub minute: u8,
+    pub second: u8
+}
+
+impl DateTime {
+    /// Seconds since 1970-01-01. Uses the days-from-civil algorithm[[1]].
+    ///
+    /// [1]: http://howardhinnant.github.io/date_algorithms.html
+    pub fn to_unix(&self) -> u64 {
+        let (m, d) = (self.month as i64, self.day as i64);
+        let y = self.year as i64 - if m <= 2 { 1 } else { 0 };
+        let era = y / 400;
+        let yoe = y - era * 400;
+        let doy = (153 * (m + if m > 2 { -3 } else { 9 }) + 2) / 5 + d - 1;
+        let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+        let days = era * 146097 + doe - 719468;
+        (days * 86400 + self.hour as i64 * 3600 + self.minute as i64 * 60
+         + self.second as i64) as u64
+    }
+
+    pub fn from_unix(secs: u64) -> DateTime {
+        let days = (secs / 86400) as i64 + 719468;
+        let rem = secs % 86400;
+        let era = days / 146097;
+        let doe = days - era * 146097;
+        let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+        let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+        let mp = (5 * doy + 2) / 153;
+        let day = doy - (153 * mp + 2) / 5 + 1;
+        let month = if mp < 10 { mp + 3 } else { mp - 9 };
+        let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+        DateTime {
+            year: year as u16,
+            month: month as u8,
+            day: day as u8,
+            hour: (rem / 3600) as u8,
+            minute: (rem / 60 % 60) as u8,
+            second: (rem % 60) as u8
+        }
+    }
+}
+
+impl fmt::Show for DateTime {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{:04}-{:02}-{:02} {:02}:{:02}:{:02} UTC",
+               self.year, self.month, self.day, self.hour, self.minute, self.second)
+    }
+}
+
+/// Seconds since the epoch when the monotonic clock started.
+static mut boot_time: u64 = 0;
+static mut running: bool = false;
+
+/// Starts the monotonic clock and reads the real-time clock. The platform's
+/// `drivers::init` calls it once interrupts are routed, before the drivers
+/// that wait with timeouts.
+pub fn init() {
+    clock::init();
+    unsafe {
+        running = true;
+        boot_time = rtc::now();
+        println!("time: {}", DateTime::from_unix(boot_time));
+    }
+}
+
+/// Time since boot, which never goes backwards.
+pub fn monotonic() -> Timespec {
+    Timespec::from_nanos(clock::nanos())
+}
+
+pub fn realtime() -> Timespec {
+    let mono = monotonic();
+    Timespec { sec: unsafe { boot_time } + mono.sec, nsec: mono.nsec }
+}
+
+/// Reads a clock by its `CLOCK_*` id.
+pub fn clock_gettime(clock: u32) -> Option<Timespec> {
+    match clock {
+        CLOCK_REALTIME => Some(realtime()),
+        CLOCK_MONOTONIC => Some(monotonic()),
+        _ => None
+    }
+}
+
+/// A deadline on the monotonic clock.
+#[derive(Copy)]
+pub struct Timeout {
+    deadline: u64
+}
+
+impl Timeout {
+    /// Panics before `init`, since the deadline would never come.
+    pub fn ms(ms: u32) -> Timeout {
+        if unsafe { !running } {
+            panic!("time: timeout before the clock started");
+        }
+        Timeout { deadline: clock::nanos() + ms as u64 * 1000000 }
+    }
+
+    pub fn expired(&self) -> bool {
+        clock::nanos() >= self.deadline
+    }
+}
+
+/// Busy-waits for `ms` milliseconds.
+pub fn sleep(ms: u32) {
+    let timeout = Timeout::ms(ms);
+    while !timeout.expired() {
+        cpu::relax();
+    }
+}
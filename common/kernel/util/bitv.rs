@@ -1,39 +1,168 @@
-use core::intrinsics::write_bytes;
+//! Bitmaps and vectors of packed values.
+//!
+//! Both borrow their storage and check bounds.
 
-// TODO: make an actual bitmap.
+use core::intrinsics::cttz32;
+use core::mem::transmute;
+use core::option::Option;
+use core::option::Option::{Some, None};
+use core::prelude::*;
+use core::raw;
 
-/// A vector of 2-bit values.
-pub struct Bitv {
-    pub storage: *mut u32
+#[inline]
+fn mask(bits: usize) -> u32 {
+    !0u32 >> (32 - bits)
 }
 
-impl Bitv {
+#[inline]
+unsafe fn get_bits(storage: *const u32, i: usize, bits: usize) -> u32 {
+    let per_word = 32 / bits;
+    let w = (i / per_word) as isize;
+    let b = (i % per_word) * bits;
+    (*storage.offset(w) >> b) & mask(bits)
+}
+
+#[inline]
+unsafe fn set_bits(storage: *mut u32, i: usize, bits: usize, x: u32) {
+    let per_word = 32 / bits;
+    let w = (i / per_word) as isize;
+    let b = (i % per_word) * bits;
+    *storage.offset(w) = *storage.offset(w) & !(mask(bits) << b) | ((x & mask(bits)) << b)
+}
+
+/// A vector of 1-bit values.
+pub struct Bitmap<'a> {
+    storage: &'a mut [u32],
+    len: usize
+}
+
+impl<'a> Bitmap<'a> {
+    pub fn new(storage: &'a mut [u32], len: usize) -> Bitmap<'a> {
+        assert!(len <= storage.len() * 32);
+        Bitmap { storage: storage, len: len }
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
     #[inline]
-    pub fn get(&self, i: usize) -> u8 {
-        let w = (i / 16) as isize;
-        let b = (i % 16) * 2;
-        unsafe {
-            (*self.storage.offset(w) as usize >> b) as u8 & 3
+    pub fn get(&self, i: usize) -> bool {
+        assert!(i < self.len);
+        self.storage[i / 32] & (1 << (i % 32)) != 0
+    }
+
+    #[inline]
+    pub fn set(&mut self, i: usize, x: bool) {
+        assert!(i < self.len);
+        if x {
+            self.storage[i / 32] |= 1 << (i % 32);
+        }
+        else {
+            self.storage[i / 32] &= !(1 << (i % 32));
+        }
+    }
+
+    pub fn set_range(&mut self, start: usize, count: usize, x: bool) {
+        for i in start..start + count {
+            self.set(i, x);
+        }
+    }
+
+    pub fn clear(&mut self) {
+        for w in self.storage.iter_mut() {
+            *w = 0;
+        }
+    }
+
+    /// Finds the index of the first bit equal to `x`, starting from `start`.
+    pub fn find(&self, start: usize, x: bool) -> Option<usize> {
+        if start >= self.len {
+            return None;
+        }
+        let flip = if x { 0 } else { !0u32 };
+        let mut w = start / 32;
+        // ignore bits below `start`
+        let mut word = (self.storage[w] ^ flip) & (!0u32 << (start % 32));
+
+        loop {
+            if word != 0 {
+                let i = w * 32 + unsafe { cttz32(word) } as usize;
+                return if i < self.len { Some(i) } else { None };
+            }
+            w += 1;
+            if w * 32 >= self.len {
+                return None;
+            }
+            word = self.storage[w] ^ flip;
         }
     }
 
     #[inline]
-    pub fn set(&self, i: usize, x: u8) {
-        let w = (i / 16) as isize;
-        let b = (i % 16) * 2;
-        unsafe {
-            *self.storage.offset(w) = *self.storage.offset(w) & !(3 << b) | ((x as u32) << b)
+    pub fn first_zero(&self) -> Option<usize> {
+        self.find(0, false)
+    }
+
+    /// Finds the first run of `count` consecutive zero bits.
+    pub fn find_run(&self, count: usize) -> Option<usize> {
+        let mut start = 0;
+        loop {
+            start = match self.find(start, false) {
+                Some(i) => i,
+                None => return None
+            };
+            match self.find(start, true) {
+                Some(end) if end - start < count => start = end,
+                Some(_) => return Some(start),
+                None if self.len - start >= count => return Some(start),
+                None => return None
+            }
         }
     }
+}
+
+/// A vector of `bits`-wide values, where `bits` divides 32.
+pub struct PackedArray<'a> {
+    storage: &'a mut [u32],
+    bits: usize,
+    len: usize
+}
+
+impl<'a> PackedArray<'a> {
+    pub fn new(storage: &'a mut [u32], bits: usize, len: usize) -> PackedArray<'a> {
+        assert!(bits > 0 && bits <= 32 && 32 % bits == 0);
+        assert!(len <= storage.len() * (32 / bits));
+        PackedArray { storage: storage, bits: bits, len: len }
+    }
+
+    /// An array over `len` values at `storage`, for memory placed outside of
+    /// any Rust object, such as the allocator trees.
+    pub unsafe fn from_raw(storage: *mut u32, bits: usize, len: usize) -> PackedArray<'static> {
+        let words = (len * bits + 31) / 32;
+        PackedArray::new(transmute(raw::Slice { data: storage as *const u32, len: words }), bits, len)
+    }
+
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.len
+    }
+
+    #[inline]
+    pub fn get(&self, i: usize) -> u32 {
+        assert!(i < self.len);
+        unsafe { get_bits(self.storage.as_ptr(), i, self.bits) }
+    }
 
     #[inline]
-    fn as_mut_ptr(&self) -> *mut u8 {
-        self.storage as *mut u8
+    pub fn set(&mut self, i: usize, x: u32) {
+        assert!(i < self.len && x & !mask(self.bits) == 0);
+        unsafe { set_bits(self.storage.as_mut_ptr(), i, self.bits, x) }
     }
 
-    pub fn clear(&self, capacity: usize) {
-        unsafe {
-            write_bytes(self.as_mut_ptr(), 0, capacity / 4);
+    pub fn clear(&mut self) {
+        for w in self.storage.iter_mut() {
+            *w = 0;
         }
     }
 }
@@ -1,12 +1,12 @@
 #![crate_name = "main"]
 #![crate_type = "staticlib"]
 #![no_std]
-#![feature(plugin, no_std, asm, macro_rules, default_type_params, phase, globs, lang_items, intrinsics, link_llvm_intrinsics)]
+#![feature(plugin, no_std, asm, macro_rules, default_type_params, phase, globs, lang_items, intrinsics, link_llvm_intrinsics, unsafe_destructor)]
 
 // The plugin phase imports compiler plugins, including regular macros.
 
 #[plugin]
-#[macro_use(write, writeln, assert, panic)]
+#[macro_use(write, writeln, assert, panic, try)]
 extern crate core;
 
 #[cfg(target_arch = "x86")]
@@ -31,6 +31,7 @@ mod platform {
     pub mod io;
     pub mod drivers;
     pub mod runtime;
+    pub mod power;
 }
 
 #[allow(dead_code)]
@@ -40,6 +41,7 @@ mod platform {
     pub mod cpu;
     pub mod io;
     pub mod drivers;
+    pub mod power;
 }
 
 mod std {
@@ -75,9 +75,9 @@ macro_rules! impl_ops (
 );
 
 macro_rules! print(
-    ($($arg:tt)*) => (format_args!($($arg)*))
+    ($($arg:tt)*) => (::platform::io::print_args(&format_args!($($arg)*)))
 );
 
 macro_rules! println(
-    ($($arg:tt)*) => (format_args!($($arg)*))
+    ($($arg:tt)*) => (::platform::io::println_args(&format_args!($($arg)*)))
 );